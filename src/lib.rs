@@ -6,6 +6,17 @@ use mongodb::Database;
 // import and merge all route here
 mod routes;
 
+pub use routes::addresses::ensure_address_indexes;
+pub use routes::geo::ensure_geo_indexes;
+pub use routes::inventory::{reset_daily_stock, restore_order_stock};
+pub use routes::locations::migrate_delivery_locations;
+pub use routes::moderation::ensure_moderation_indexes;
+pub use routes::onboarding::ensure_onboarding_indexes;
+pub use routes::pricing::apply_scheduled_prices;
+pub use routes::promos::restore_order_promotion;
+pub use routes::ratings::rebuild_rating_summaries;
+pub use routes::retaurants::ensure_popularity_index;
+
 pub fn app(db: Database) -> Router{
     Router::new()
         .merge(routes::api_router(db))
@@ -8,10 +8,12 @@ use axum::Router;
 use dotenv::dotenv;
 use chrono::Utc;
 use reqwest::Client as HttpClient;
+use futures::stream::TryStreamExt;
 
 // import the app constructor from lib,
 // don't use "mod lib", the compiler will find through src/lib/routes
 use Expressing_server::app as lib_app;
+use Expressing_server::{apply_scheduled_prices, ensure_address_indexes, ensure_geo_indexes, ensure_moderation_indexes, ensure_onboarding_indexes, ensure_popularity_index, migrate_delivery_locations, rebuild_rating_summaries, reset_daily_stock, restore_order_promotion, restore_order_stock};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>>{
@@ -28,34 +30,125 @@ async fn main() -> Result<(), Box<dyn std::error::Error>>{
 
     let app: Router = lib_app(db.clone());
 
-    // background task: auto cancel orders older than 1 hour if not delivered/cancelled
+    // fold NTOU_location into delivery_locations, then GeoJSON points and 2dsphere indexes
+    // for nearby queries; the API still works without them
+    let db_for_geo = db.clone();
+    tokio::spawn(async move {
+        match migrate_delivery_locations(&db_for_geo).await {
+            Ok((migrated, skipped)) if migrated + skipped > 0 => println!("Delivery locations migrated: {}, skipped: {}", migrated, skipped),
+            Ok(_) => {}
+            Err(e) => eprintln!("Delivery location migration error: {}", e),
+        }
+        if let Err(e) = ensure_geo_indexes(&db_for_geo).await {
+            eprintln!("Geo index setup error: {}", e);
+        }
+        if let Err(e) = ensure_onboarding_indexes(&db_for_geo).await {
+            eprintln!("Onboarding index setup error: {}", e);
+        }
+        if let Err(e) = ensure_address_indexes(&db_for_geo).await {
+            eprintln!("Address index setup error: {}", e);
+        }
+        if let Err(e) = ensure_popularity_index(&db_for_geo).await {
+            eprintln!("Popularity index setup error: {}", e);
+        }
+        if let Err(e) = ensure_moderation_indexes(&db_for_geo).await {
+            eprintln!("Moderation index setup error: {}", e);
+        }
+    });
+
+    // background task: auto cancel orders more than 1 hour past when they were due if not delivered/cancelled
     let db_for_task = db.clone();
     tokio::spawn(async move {
         let orders = db_for_task.collection::<mongodb::bson::Document>("orders");
         loop {
             let cutoff = Utc::now().timestamp_millis() - 60 * 60 * 1000;
+            // scheduled orders are due at their requested time; older orders without `dueAt` at placement
             let filter = doc! {
                 "status": { "$nin": ["delivered", "cancelled"] },
-                "placedAt": { "$lt": DateTime::from_millis(cutoff) }
+                "$or": [
+                    { "dueAt": { "$lt": DateTime::from_millis(cutoff) } },
+                    { "dueAt": { "$exists": false }, "placedAt": { "$lt": DateTime::from_millis(cutoff) } }
+                ]
             };
             let update = doc! {
                 "$set": { "status": "cancelled" },
                 "$push": { "statusHistory": { "status": "cancelled", "timestamp": DateTime::from_millis(Utc::now().timestamp_millis()) } }
             };
-            match orders.update_many(filter, update).await {
-                Ok(res) => {
-                    if res.modified_count > 0 {
-                        println!("Auto-cancelled {} stale orders (>1h).", res.modified_count);
-                    }
-                }
+            // cancel one by one so the stock and promotion budget each order held can be given back
+            let stale_ids: Vec<String> = match orders.find(filter.clone()).await {
+                Ok(cursor) => cursor.try_collect::<Vec<_>>().await
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|o| o.get_str("id").ok().map(|s| s.to_string()))
+                    .collect(),
                 Err(e) => {
                     eprintln!("Auto-cancel task error: {}", e);
+                    Vec::new()
+                }
+            };
+            let mut cancelled = 0;
+            for id in stale_ids {
+                let mut one = filter.clone();
+                one.insert("id", &id);
+                match orders.update_one(one, update.clone()).await {
+                    Ok(res) if res.modified_count > 0 => {
+                        cancelled += 1;
+                        if let Err(e) = restore_order_stock(&db_for_task, &id).await {
+                            eprintln!("Auto-cancel stock restore error: {}", e);
+                        }
+                        if let Err(e) = restore_order_promotion(&db_for_task, &id).await {
+                            eprintln!("Auto-cancel promotion restore error: {}", e);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Auto-cancel task error: {}", e),
                 }
             }
+            if cancelled > 0 {
+                println!("Auto-cancelled {} stale orders (>1h).", cancelled);
+            }
             sleep(Duration::from_secs(60)).await;
         }
     });
 
+    // background task: refill daily stock counts once per day (Asia/Taipei)
+    let db_for_stock = db.clone();
+    tokio::spawn(async move {
+        loop {
+            match reset_daily_stock(&db_for_stock).await {
+                Ok(count) if count > 0 => println!("Reset daily stock for {} menu items.", count),
+                Ok(_) => {}
+                Err(e) => eprintln!("Daily stock reset error: {}", e),
+            }
+            sleep(Duration::from_secs(60)).await;
+        }
+    });
+
+    // background task: apply scheduled menu price changes once they come due
+    let db_for_prices = db.clone();
+    tokio::spawn(async move {
+        loop {
+            match apply_scheduled_prices(&db_for_prices).await {
+                Ok(count) if count > 0 => println!("Applied {} scheduled price changes.", count),
+                Ok(_) => {}
+                Err(e) => eprintln!("Scheduled price task error: {}", e),
+            }
+            sleep(Duration::from_secs(60)).await;
+        }
+    });
+
+    // background task: rebuild restaurant rating summaries at startup and hourly, correcting any drift
+    let db_for_ratings = db.clone();
+    tokio::spawn(async move {
+        loop {
+            match rebuild_rating_summaries(&db_for_ratings).await {
+                Ok(count) => println!("Rebuilt rating summaries for {} shops.", count),
+                Err(e) => eprintln!("Rating summary rebuild error: {}", e),
+            }
+            sleep(Duration::from_secs(60 * 60)).await;
+        }
+    });
+
     // self-ping to keep Render awake (optional: set SELF_PING_URL)
     if let Ok(self_url) = env::var("SELF_PING_URL") {
         tokio::spawn(async move {
@@ -0,0 +1,306 @@
+#![allow(non_snake_case)]
+
+use axum::{Router, routing::{get, put, patch}, extract::{State, Path}, Json, http::HeaderMap};
+use mongodb::{bson::{doc, Bson, Document}, options::IndexOptions, Database, IndexModel};
+use futures::stream::TryStreamExt;
+use serde::Deserialize;
+use axum::http::StatusCode;
+use crate::routes::common::{Claims, ApiResult, data_response, data_response_with_status, error_response, get_string, get_f64, get_bool, require_role, now_datetime, iso_from_bson, is_duplicate_key, invalid, checked_text};
+use crate::routes::locations::find_location;
+use crate::routes::orders::DeliveryLocation;
+use crate::routes::profile::{MAX_NAME_CHARS, validate_latlng};
+
+// `saved_addresses`: a customer's named drop-off spots. Each one either points at a campus
+// location (`locationId`) or is a custom pin with its own coordinates. At most one is the default,
+// which a partial unique index holds to when two requests change it at once.
+
+const MAX_ADDRESSES: u64 = 20;
+const MAX_LABEL_CHARS: usize = 50;
+const MAX_NOTE_CHARS: usize = 200;
+
+#[derive(Deserialize)]
+struct AddressRequest {
+    label: String,
+    locationId: Option<String>,
+    name: Option<String>,
+    lat: Option<f64>,
+    lng: Option<f64>,
+    note: Option<String>,
+    isDefault: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct AddressPatch {
+    label: Option<String>,
+    // an empty string turns a linked address into a custom pin
+    locationId: Option<String>,
+    name: Option<String>,
+    lat: Option<f64>,
+    lng: Option<f64>,
+    note: Option<String>,
+    isDefault: Option<bool>,
+}
+
+fn address_view(address: &Document) -> Document{
+    doc! {
+        "id": get_string(address, "id"),
+        "label": get_string(address, "label"),
+        "locationId": get_string(address, "locationId"),
+        "name": get_string(address, "name"),
+        "lat": get_f64(address, "lat"),
+        "lng": get_f64(address, "lng"),
+        "note": get_string(address, "note"),
+        "isDefault": get_bool(address, "isDefault").unwrap_or(false),
+        "createdAt": address.get("createdAt").and_then(iso_from_bson),
+        "updatedAt": address.get("updatedAt").and_then(iso_from_bson)
+    }
+}
+
+// Where an address points: the campus location's name and coordinates, or the pin's own.
+async fn place_fields(db: &Database, location_id: Option<&str>, name: Option<&str>, lat: Option<f64>, lng: Option<f64>, label: &str) -> Result<Document, (StatusCode, Json<Document>)>{
+    if let Some(location_id) = location_id {
+        let location = find_location(db, location_id)
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+            .ok_or_else(|| error_response(StatusCode::BAD_REQUEST, "location.not_found", "delivery location not found"))?;
+        return Ok(doc! {
+            "locationId": get_string(&location, "id"),
+            "name": get_string(&location, "name"),
+            "lat": get_f64(&location, "lat"),
+            "lng": get_f64(&location, "lng")
+        });
+    }
+    let (Some(lat), Some(lng)) = (lat, lng) else {
+        return Err(invalid("a custom pin needs both lat and lng"));
+    };
+    validate_latlng(lat, lng)?;
+    let name = match name.map(str::trim).filter(|n| !n.is_empty()) {
+        Some(name) => checked_text("name", name, MAX_NAME_CHARS)?,
+        None => label.to_string(),
+    };
+    Ok(doc! { "locationId": Bson::Null, "name": name, "lat": lat, "lng": lng })
+}
+
+pub async fn ensure_address_indexes(db: &Database) -> mongodb::error::Result<()>{
+    let one_default = IndexModel::builder()
+        .keys(doc! { "userId": 1 })
+        .options(IndexOptions::builder().unique(true).partial_filter_expression(doc! { "isDefault": true }).name("one_default_per_user".to_string()).build())
+        .build();
+    db.collection::<Document>("saved_addresses").create_index(one_default).await?;
+    Ok(())
+}
+
+// A write that lost a race for the default slot to another request.
+fn write_error(e: mongodb::error::Error) -> (StatusCode, Json<Document>){
+    if is_duplicate_key(&e) {
+        return error_response(StatusCode::CONFLICT, "address.conflict", "the default address was changed at the same time; try again");
+    }
+    error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string())
+}
+
+async fn clear_default(db: &Database, claims: &Claims) -> Result<(), (StatusCode, Json<Document>)>{
+    db.collection::<Document>("saved_addresses")
+        .update_many(doc! { "userId": &claims.sub, "isDefault": true }, doc! { "$set": { "isDefault": false } })
+        .await
+        .map(|_| ())
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))
+}
+
+async fn load_address(db: &Database, claims: &Claims, id: &str) -> Result<Document, (StatusCode, Json<Document>)>{
+    db.collection::<Document>("saved_addresses").find_one(doc! { "id": id, "userId": &claims.sub })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "address.not_found", "Address not found"))
+}
+
+// The drop-off for an order: the saved address `address_id`, or the customer's default when it is `None`.
+// Linked addresses follow later edits to the campus location; a removed one falls back to what was saved.
+pub async fn saved_delivery_location(db: &Database, user_id: &str, address_id: Option<&str>) -> Result<Option<DeliveryLocation>, (StatusCode, Json<Document>)>{
+    let filter = match address_id {
+        Some(id) => doc! { "id": id, "userId": user_id },
+        None => doc! { "userId": user_id, "isDefault": true },
+    };
+    let address = db.collection::<Document>("saved_addresses").find_one(filter)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let Some(mut address) = address else {
+        return match address_id {
+            Some(_) => Err(error_response(StatusCode::BAD_REQUEST, "address.not_found", "saved address not found")),
+            None => Ok(None),
+        };
+    };
+    if let Some(location_id) = get_string(&address, "locationId")
+        && let Ok(Some(location)) = find_location(db, &location_id).await {
+        for key in ["name", "lat", "lng"] {
+            if let Some(value) = location.get(key).filter(|v| !matches!(v, Bson::Null)) {
+                address.insert(key, value.clone());
+            }
+        }
+        // the spot's own drop-off instructions, unless the customer wrote their own
+        if get_string(&address, "note").is_none() {
+            address.insert("note", get_string(&location, "instructions"));
+        }
+    }
+    Ok(Some(DeliveryLocation {
+        name: get_string(&address, "name").unwrap_or_default(),
+        lat: get_f64(&address, "lat"),
+        lng: get_f64(&address, "lng"),
+        note: get_string(&address, "note"),
+    }))
+}
+
+// GET /me/addresses
+async fn list_addresses(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let addresses: Vec<Document> = db.collection::<Document>("saved_addresses").find(doc! { "userId": &claims.sub })
+        .sort(doc! { "isDefault": -1, "createdAt": 1 })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .try_collect()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let items: Vec<Bson> = addresses.iter().map(|a| Bson::Document(address_view(a))).collect();
+    Ok(data_response(Bson::Array(items)))
+}
+
+// POST /me/addresses
+async fn create_address(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<AddressRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let collection = db.collection::<Document>("saved_addresses");
+    let existing = collection.count_documents(doc! { "userId": &claims.sub })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    if existing >= MAX_ADDRESSES {
+        return Err(invalid(&format!("at most {} saved addresses", MAX_ADDRESSES)));
+    }
+    let label = checked_text("label", &payload.label, MAX_LABEL_CHARS)?;
+    let note = match payload.note.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+        Some(note) => Some(checked_text("note", note, MAX_NOTE_CHARS)?),
+        None => None,
+    };
+    let location_id = payload.locationId.as_deref().map(str::trim).filter(|l| !l.is_empty());
+    if location_id.is_some() && payload.name.as_deref().is_some_and(|n| !n.trim().is_empty()) {
+        return Err(invalid("name comes from the linked delivery location; leave out locationId to name a custom pin"));
+    }
+    let place = place_fields(&db, location_id, payload.name.as_deref(), payload.lat, payload.lng, &label).await?;
+
+    // the first address is the default until another is picked
+    let is_default = payload.isDefault.unwrap_or(false) || existing == 0;
+    if is_default {
+        clear_default(&db, &claims).await?;
+    }
+    let now = now_datetime();
+    let mut address = doc! {
+        "id": mongodb::bson::oid::ObjectId::new().to_hex(),
+        "userId": &claims.sub,
+        "label": label,
+        "note": note,
+        "isDefault": is_default,
+        "createdAt": now,
+        "updatedAt": now
+    };
+    address.extend(place);
+    collection.insert_one(&address).await.map_err(write_error)?;
+    Ok(data_response_with_status(StatusCode::CREATED, Bson::Document(address_view(&address))))
+}
+
+// PATCH /me/addresses/{id}
+async fn update_address(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<AddressPatch>) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let current = load_address(&db, &claims, &id).await?;
+    let mut set = Document::new();
+    let label = match payload.label.as_deref() {
+        Some(label) => {
+            let label = checked_text("label", label, MAX_LABEL_CHARS)?;
+            set.insert("label", &label);
+            label
+        }
+        None => get_string(&current, "label").unwrap_or_default(),
+    };
+    if let Some(note) = payload.note.as_deref().map(str::trim) {
+        if note.is_empty() {
+            set.insert("note", Bson::Null);
+        } else {
+            set.insert("note", checked_text("note", note, MAX_NOTE_CHARS)?);
+        }
+    }
+    let moves = payload.locationId.is_some() || payload.lat.is_some() || payload.lng.is_some() || payload.name.is_some();
+    if moves {
+        let location_id = match payload.locationId.as_deref().map(str::trim) {
+            Some("") => None,
+            Some(location_id) => Some(location_id.to_string()),
+            // coordinates alone make it a custom pin
+            None if payload.lat.is_some() || payload.lng.is_some() => None,
+            None => get_string(&current, "locationId"),
+        };
+        if location_id.is_some() && payload.name.is_some() {
+            return Err(invalid("name comes from the linked delivery location; send an empty locationId to make it a custom pin"));
+        }
+        let lat = payload.lat.or_else(|| get_f64(&current, "lat"));
+        let lng = payload.lng.or_else(|| get_f64(&current, "lng"));
+        let name = payload.name.clone().or_else(|| get_string(&current, "name"));
+        set.extend(place_fields(&db, location_id.as_deref(), name.as_deref(), lat, lng, &label).await?);
+    }
+    if payload.isDefault == Some(true) {
+        clear_default(&db, &claims).await?;
+        set.insert("isDefault", true);
+    } else if payload.isDefault == Some(false) {
+        set.insert("isDefault", false);
+    }
+    if set.is_empty() {
+        return Err(invalid("No fields to update"));
+    }
+    set.insert("updatedAt", now_datetime());
+    let updated = db.collection::<Document>("saved_addresses")
+        .find_one_and_update(doc! { "id": &id, "userId": &claims.sub }, doc! { "$set": set })
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await
+        .map_err(write_error)?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "address.not_found", "Address not found"))?;
+    Ok(data_response(Bson::Document(address_view(&updated))))
+}
+
+// PUT /me/addresses/{id}/default
+async fn set_default(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    load_address(&db, &claims, &id).await?;
+    clear_default(&db, &claims).await?;
+    let updated = db.collection::<Document>("saved_addresses")
+        .find_one_and_update(doc! { "id": &id, "userId": &claims.sub }, doc! { "$set": { "isDefault": true, "updatedAt": now_datetime() } })
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await
+        .map_err(write_error)?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "address.not_found", "Address not found"))?;
+    Ok(data_response(Bson::Document(address_view(&updated))))
+}
+
+// DELETE /me/addresses/{id}
+async fn delete_address(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let collection = db.collection::<Document>("saved_addresses");
+    let removed = collection.find_one_and_delete(doc! { "id": &id, "userId": &claims.sub })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "address.not_found", "Address not found"))?;
+    // hand the default on to the oldest remaining address
+    if get_bool(&removed, "isDefault").unwrap_or(false) {
+        let oldest = collection.find_one(doc! { "userId": &claims.sub })
+            .sort(doc! { "createdAt": 1 })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        if let Some(next_id) = oldest.and_then(|a| get_string(&a, "id")) {
+            collection.update_one(doc! { "id": next_id }, doc! { "$set": { "isDefault": true } })
+                .await
+                .map_err(write_error)?;
+        }
+    }
+    Ok(data_response(Bson::Document(doc! { "ok": true })))
+}
+
+pub fn addresses_router(db: Database) -> Router{
+    Router::new()
+        .route("/addresses", get(list_addresses).post(create_address))
+        .route("/addresses/{id}", patch(update_address).delete(delete_address))
+        .route("/addresses/{id}/default", put(set_default))
+        .with_state(db)
+}
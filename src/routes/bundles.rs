@@ -0,0 +1,140 @@
+#![allow(non_snake_case)]
+
+use axum::Json;
+use axum::http::StatusCode;
+use chrono::{DateTime, FixedOffset};
+use mongodb::{bson::{doc, Bson, Document}, Database};
+use serde::Deserialize;
+use std::collections::HashMap;
+use crate::routes::common::{error_response, get_string, get_i64, get_array, document_id, menu_item_filter, menu_restaurant_id};
+use crate::routes::i18n::localized;
+use crate::routes::inventory::tracks_stock;
+use crate::routes::pricing::base_price;
+use crate::routes::schedule::{item_in_window, load_category};
+
+// One slot of a bundle: a menu item of the same shop with its own option choices.
+#[derive(Deserialize)]
+pub struct BundleComponent {
+    pub menuItemId: String,
+    pub quantity: Option<i64>,
+    pub size: Option<String>,
+    pub spiciness: Option<String>,
+}
+
+pub fn is_bundle(menu_doc: &Document) -> bool{
+    get_string(menu_doc, "type").as_deref() == Some("bundle")
+}
+
+pub fn offers(menu_doc: &Document, keys: &[&str], choice: &str) -> bool{
+    keys.iter()
+        .filter_map(|k| get_array(menu_doc, k))
+        .next()
+        .is_some_and(|options| options.iter().any(|o| o.as_str() == Some(choice)))
+}
+
+// Check every component against the shop's menu and return the array stored on the bundle.
+pub async fn validate_components(db: &Database, restaurant_id: &str, components: &[BundleComponent]) -> Result<Bson, (StatusCode, Json<Document>)>{
+    if components.is_empty() {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "a bundle needs at least one component"));
+    }
+    let collection = db.collection::<Document>("menu");
+    let mut stored: Vec<Bson> = Vec::new();
+    for component in components {
+        let quantity = component.quantity.unwrap_or(1);
+        if quantity < 1 {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "component quantity must be at least 1"));
+        }
+        let found = collection.find_one(menu_item_filter(&component.menuItemId))
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        let Some(item) = found else {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("component {} not found", component.menuItemId)));
+        };
+        if menu_restaurant_id(&item).as_deref() != Some(restaurant_id) {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "components must come from the same restaurant"));
+        }
+        if is_bundle(&item) {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "a bundle cannot contain another bundle"));
+        }
+        if let Some(size) = component.size.as_deref()
+            && !offers(&item, &["sizes", "size"], size) {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("size {} not offered for {}", size, get_string(&item, "name").unwrap_or_default())));
+        }
+        if let Some(spiciness) = component.spiciness.as_deref()
+            && !offers(&item, &["spicinessOptions"], spiciness) {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("spiciness {} not offered for {}", spiciness, get_string(&item, "name").unwrap_or_default())));
+        }
+        stored.push(Bson::Document(doc! {
+            "menuItemId": document_id(&item).unwrap_or_default(),
+            "quantity": quantity,
+            "size": component.size.clone(),
+            "spiciness": component.spiciness.clone()
+        }));
+    }
+    Ok(Bson::Array(stored))
+}
+
+fn component_entries(bundle: &Document) -> Vec<&Document>{
+    bundle.get_array("components")
+        .map(|list| list.iter().filter_map(Bson::as_document).collect())
+        .unwrap_or_default()
+}
+
+fn orderable(item: &Document) -> bool{
+    item.get_bool("isAvailable").unwrap_or(true) && !item.get_bool("archived").unwrap_or(false)
+}
+
+// Customer menu view: a bundle is only available while every component is.
+pub fn bundle_available(bundle: &Document, menu_by_id: &HashMap<String, Document>, categories: &HashMap<String, Document>, at: &DateTime<FixedOffset>) -> bool{
+    component_entries(bundle).iter().all(|component| {
+        let id = get_string(component, "menuItemId").unwrap_or_default();
+        menu_by_id.get(&id).is_some_and(|item| {
+            let category = get_string(item, "category").and_then(|c| categories.get(&c));
+            orderable(item) && item_in_window(item, category, at)
+        })
+    })
+}
+
+pub fn describe_components(bundle: &Document, menu_by_id: &HashMap<String, Document>, languages: &[&str]) -> Vec<Bson>{
+    component_entries(bundle).iter().map(|component| {
+        let id = get_string(component, "menuItemId").unwrap_or_default();
+        let name = menu_by_id.get(&id).and_then(|item| localized(item, "name", languages));
+        Bson::Document(doc! {
+            "menuItemId": &id,
+            "name": name,
+            "quantity": get_i64(component, "quantity").unwrap_or(1),
+            "size": get_string(component, "size"),
+            "spiciness": get_string(component, "spiciness")
+        })
+    }).collect()
+}
+
+// Order time: resolve each component for `line_quantity` bundles and check it can be served.
+pub async fn expand_bundle(db: &Database, bundle: &Document, line_quantity: i64, at: &DateTime<FixedOffset>) -> Result<Vec<Bson>, (StatusCode, Json<Document>)>{
+    let collection = db.collection::<Document>("menu");
+    let mut expanded: Vec<Bson> = Vec::new();
+    for component in component_entries(bundle) {
+        let id = get_string(component, "menuItemId").unwrap_or_default();
+        let found = collection.find_one(menu_item_filter(&id))
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        let Some(item) = found.filter(orderable) else {
+            return Err(error_response(StatusCode::BAD_REQUEST, "menu.unavailable", "bundle component unavailable"));
+        };
+        let category = load_category(db, &item).await;
+        if !item_in_window(&item, category.as_ref(), at) {
+            return Err(error_response(StatusCode::BAD_REQUEST, "menu.unavailable", "bundle component not served at this time"));
+        }
+        expanded.push(Bson::Document(doc! {
+            "menuItemId": &id,
+            "name": get_string(&item, "name").unwrap_or_default(),
+            "quantity": get_i64(component, "quantity").unwrap_or(1) * line_quantity,
+            "size": get_string(component, "size").unwrap_or_default(),
+            "spiciness": get_string(component, "spiciness").unwrap_or_default(),
+            // list price, used to split the bundle's revenue across components in reports
+            "unitPrice": base_price(&item),
+            "stockTracked": tracks_stock(&item)
+        }));
+    }
+    Ok(expanded)
+}
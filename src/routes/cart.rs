@@ -0,0 +1,437 @@
+#![allow(non_snake_case)]
+
+use axum::{Router, routing::{get, post, patch}, extract::{State, Path, Query}, Json, http::HeaderMap};
+use mongodb::{bson::{doc, Bson, Document}, Database};
+use serde::Deserialize;
+use axum::http::StatusCode;
+use crate::routes::common::{Claims, ApiResult, data_response, data_response_with_status, error_response, document_id, get_string, get_i64, get_bool, require_role, now_datetime, iso_from_bson, menu_item_filter, menu_restaurant_id};
+use crate::routes::bundles::offers;
+use crate::routes::hours::{next_open, shop_open_at};
+use crate::routes::inventory::stock_left;
+use crate::routes::onboarding::shop_is_listed;
+use crate::routes::orders::{CreateOrderRequest, DeliveryLocation, OrderItemRequest, place_order, price_order};
+use crate::routes::pricing::resolve_price;
+use crate::routes::schedule::{item_in_window, load_category, order_time};
+
+// One cart per customer in `carts`: `{ userId, restaurantId, lines: [{ id, menuItemId, quantity, size, spiciness, addDrink }] }`.
+// Lines only hold what the customer picked; names, prices and problems are worked out on every read.
+
+const MAX_QUANTITY: i64 = 99;
+const MAX_LINES: usize = 50;
+// a checkout that hasn't finished after this long is taken to have died, and the cart is free again
+const CHECKOUT_CLAIM_MILLIS: i64 = 2 * 60 * 1000;
+
+#[derive(Deserialize)]
+struct AddLineRequest {
+    menuItemId: String,
+    quantity: Option<i64>,
+    size: Option<String>,
+    spiciness: Option<String>,
+    addDrink: Option<bool>,
+    // empty a cart from another restaurant instead of refusing
+    replace: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct UpdateLineRequest {
+    // 0 removes the line
+    quantity: Option<i64>,
+    size: Option<String>,
+    spiciness: Option<String>,
+    addDrink: Option<bool>,
+}
+
+// GET /cart prices the preview for these; checkout takes the same plus a deliveryLocation
+#[derive(Deserialize, Default)]
+struct CartQuery {
+    requestedTime: Option<String>,
+    addressId: Option<String>,
+    promoCode: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct CheckoutRequest {
+    // as for POST /orders: a location, a saved address, or neither for the default address
+    deliveryLocation: Option<DeliveryLocation>,
+    addressId: Option<String>,
+    notes: Option<String>,
+    requestedTime: Option<String>,
+    promoCode: Option<String>,
+}
+
+pub fn check_quantity(quantity: i64) -> Result<(), (StatusCode, Json<Document>)>{
+    if !(1..=MAX_QUANTITY).contains(&quantity) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("quantity must be 1-{}", MAX_QUANTITY)));
+    }
+    Ok(())
+}
+
+async fn load_cart(db: &Database, claims: &Claims) -> Result<Document, (StatusCode, Json<Document>)>{
+    let cart = db.collection::<Document>("carts").find_one(doc! { "userId": &claims.sub })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(cart.unwrap_or_else(|| doc! { "userId": &claims.sub, "lines": [] }))
+}
+
+async fn save_lines(db: &Database, claims: &Claims, restaurant_id: Option<&str>, lines: Vec<Bson>) -> Result<(), (StatusCode, Json<Document>)>{
+    let carts = db.collection::<Document>("carts");
+    let result = if lines.is_empty() {
+        carts.delete_one(doc! { "userId": &claims.sub }).await.map(|_| ())
+    } else {
+        carts.update_one(
+            doc! { "userId": &claims.sub },
+            doc! { "$set": { "restaurantId": restaurant_id, "lines": lines, "updatedAt": now_datetime() } },
+        )
+            .upsert(true)
+            .await
+            .map(|_| ())
+    };
+    result.map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))
+}
+
+fn cart_lines(cart: &Document) -> Vec<Document>{
+    cart.get_array("lines")
+        .map(|lines| lines.iter().filter_map(Bson::as_document).cloned().collect())
+        .unwrap_or_default()
+}
+
+fn problem(line_id: Option<&str>, code: &str, message: &str) -> Bson{
+    Bson::Document(doc! { "lineId": line_id, "code": code, "message": message })
+}
+
+// Options a line asks for must be on the item's lists, where it has them.
+pub fn option_problem(menu_doc: &Document, size: Option<&str>, spiciness: Option<&str>) -> Option<String>{
+    let offered = |keys: &[&str], choice: &str| keys.iter().all(|k| menu_doc.get_array(k).is_err()) || offers(menu_doc, keys, choice);
+    if let Some(size) = size.filter(|s| !s.is_empty())
+        && !offered(&["sizes", "size"], size) {
+        return Some(format!("size {} is not offered", size));
+    }
+    if let Some(spiciness) = spiciness.filter(|s| !s.is_empty())
+        && !offered(&["spicinessOptions"], spiciness) {
+        return Some(format!("spiciness {} is not offered", spiciness));
+    }
+    None
+}
+
+// The order checkout would place for this cart.
+fn order_request(cart: &Document, options: &CheckoutRequest) -> CreateOrderRequest{
+    let items: Vec<OrderItemRequest> = cart_lines(cart).iter().map(|line| OrderItemRequest {
+        menu_item_id: get_string(line, "menuItemId").unwrap_or_default(),
+        quantity: get_i64(line, "quantity"),
+        size: get_string(line, "size"),
+        spiciness: get_string(line, "spiciness"),
+        add_drink: get_bool(line, "addDrink"),
+    }).collect();
+    CreateOrderRequest {
+        restaurant_id: get_string(cart, "restaurantId"),
+        delivery_location: options.deliveryLocation.clone(),
+        address_id: options.addressId.clone(),
+        items,
+        notes: options.notes.clone(),
+        requested_time: options.requestedTime.clone(),
+        promo_code: options.promoCode.clone(),
+    }
+}
+
+// Cart with current names and prices, every problem that would stop checkout, and the totals.
+// Lines are judged at the time the order would be for, and a valid cart is priced like POST /orders/quote.
+async fn cart_view(db: &Database, claims: &Claims, cart: &Document, options: &CheckoutRequest) -> Result<Document, (StatusCode, Json<Document>)>{
+    let at = order_time(options.requestedTime.as_deref())?;
+    let priced_at = mongodb::bson::DateTime::from_millis(at.timestamp_millis());
+    let mut lines: Vec<Bson> = Vec::new();
+    let mut problems: Vec<Bson> = Vec::new();
+    let mut subtotal = 0i64;
+    let mut item_count = 0i64;
+    for line in cart_lines(cart) {
+        let line_id = get_string(&line, "id");
+        let quantity = get_i64(&line, "quantity").unwrap_or(1);
+        let size = get_string(&line, "size");
+        let spiciness = get_string(&line, "spiciness");
+        let menu_doc = db.collection::<Document>("menu")
+            .find_one(menu_item_filter(&get_string(&line, "menuItemId").unwrap_or_default()))
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+            .filter(|m| !get_bool(m, "archived").unwrap_or(false));
+        let mut view = doc! {
+            "id": &line_id,
+            "menuItemId": get_string(&line, "menuItemId"),
+            "quantity": quantity,
+            "size": &size,
+            "spiciness": &spiciness,
+            "addDrink": get_bool(&line, "addDrink").unwrap_or(false)
+        };
+        let Some(menu_doc) = menu_doc else {
+            problems.push(problem(line_id.as_deref(), "menu.unavailable", "menu item no longer exists"));
+            view.insert("name", Bson::Null);
+            lines.push(Bson::Document(view));
+            continue;
+        };
+        let category = load_category(db, &menu_doc).await;
+        if !get_bool(&menu_doc, "isAvailable").unwrap_or(true) {
+            problems.push(problem(line_id.as_deref(), "menu.unavailable", "menu item unavailable"));
+        } else if !item_in_window(&menu_doc, category.as_ref(), &at) {
+            problems.push(problem(line_id.as_deref(), "menu.unavailable", "menu item not served at this time"));
+        } else if stock_left(&menu_doc).is_some_and(|left| left < quantity) {
+            problems.push(problem(line_id.as_deref(), "menu.sold_out", "not enough left for this quantity"));
+        }
+        if let Some(message) = option_problem(&menu_doc, size.as_deref(), spiciness.as_deref()) {
+            problems.push(problem(line_id.as_deref(), "validation.failed", &message));
+        }
+        let (unit_price, _) = resolve_price(&menu_doc, priced_at);
+        subtotal += unit_price * quantity;
+        item_count += quantity;
+        view.insert("name", get_string(&menu_doc, "name"));
+        view.insert("imageUrl", get_string(&menu_doc, "imageUrl"));
+        view.insert("unitPrice", unit_price);
+        view.insert("lineTotal", unit_price * quantity);
+        lines.push(Bson::Document(view));
+    }
+
+    let restaurant_id = get_string(cart, "restaurantId");
+    let mut restaurant = Bson::Null;
+    if let Some(rest_id) = restaurant_id.as_deref() {
+        let shop = db.collection::<Document>("shops").find_one(doc! { "id": rest_id })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        match shop {
+            Some(shop) if !shop_is_listed(&shop) => problems.push(problem(None, "restaurant.unavailable", "restaurant is not taking orders")),
+            Some(shop) => {
+                if !shop_open_at(&shop, &at) {
+                    let message = match next_open(&shop, &at) {
+                        Some(next) => format!("restaurant is closed; next open at {}", next.to_rfc3339()),
+                        None => "restaurant is closed".to_string(),
+                    };
+                    problems.push(problem(None, "restaurant.closed", &message));
+                }
+                restaurant = Bson::Document(doc! { "id": rest_id, "name": get_string(&shop, "name") });
+            }
+            None => {}
+        }
+    }
+
+    // fee, discount and total come from the same pricing as the order itself
+    let mut preview = doc! { "itemCount": item_count, "subtotal": subtotal, "currency": "TWD" };
+    if problems.is_empty() && item_count > 0 {
+        match price_order(db, claims, &order_request(cart, options)).await {
+            Ok(priced) => preview.extend(priced.summary()),
+            Err(e) if e.0.is_server_error() => return Err(e),
+            Err((_, Json(body))) => problems.push(problem(
+                None,
+                &get_string(&body, "code").unwrap_or_else(|| "validation.failed".to_string()),
+                &get_string(&body, "message").unwrap_or_default(),
+            )),
+        }
+    }
+
+    Ok(doc! {
+        "restaurantId": restaurant_id,
+        "restaurant": restaurant,
+        "lines": lines,
+        "valid": problems.is_empty() && item_count > 0,
+        "errors": problems,
+        "preview": preview,
+        "updatedAt": cart.get("updatedAt").and_then(iso_from_bson)
+    })
+}
+
+async fn respond(db: &Database, claims: &Claims) -> ApiResult{
+    let cart = load_cart(db, claims).await?;
+    Ok(data_response(Bson::Document(cart_view(db, claims, &cart, &CheckoutRequest::default()).await?)))
+}
+
+// GET /cart?requestedTime=&addressId=&promoCode=
+async fn get_cart(State(db): State<Database>, headers: HeaderMap, Query(query): Query<CartQuery>) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let cart = load_cart(&db, &claims).await?;
+    let options = CheckoutRequest {
+        addressId: query.addressId,
+        requestedTime: query.requestedTime,
+        promoCode: query.promoCode,
+        ..CheckoutRequest::default()
+    };
+    Ok(data_response(Bson::Document(cart_view(&db, &claims, &cart, &options).await?)))
+}
+
+// DELETE /cart
+async fn clear_cart(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    save_lines(&db, &claims, None, Vec::new()).await?;
+    respond(&db, &claims).await
+}
+
+// POST /cart/items
+// Adding the same item with the same options again raises that line's quantity.
+async fn add_line(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<AddLineRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let quantity = payload.quantity.unwrap_or(1);
+    check_quantity(quantity)?;
+    let menu_doc = db.collection::<Document>("menu").find_one(menu_item_filter(&payload.menuItemId))
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .filter(|m| !get_bool(m, "archived").unwrap_or(false))
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "menu.unavailable", "Menu item not found"))?;
+    if let Some(message) = option_problem(&menu_doc, payload.size.as_deref(), payload.spiciness.as_deref()) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &message));
+    }
+    let restaurant_id = menu_restaurant_id(&menu_doc);
+    let menu_item_id = document_id(&menu_doc).unwrap_or(payload.menuItemId.clone());
+
+    let cart = load_cart(&db, &claims).await?;
+    let mut lines = cart_lines(&cart);
+    if !lines.is_empty() && get_string(&cart, "restaurantId") != restaurant_id {
+        if payload.replace != Some(true) {
+            return Err(error_response(StatusCode::CONFLICT, "cart.restaurant_conflict", "cart holds items from another restaurant; send replace to start over"));
+        }
+        lines.clear();
+    }
+    let add_drink = payload.addDrink.unwrap_or(false);
+    let same = lines.iter_mut().find(|l| {
+        get_string(l, "menuItemId").as_deref() == Some(menu_item_id.as_str())
+            && get_string(l, "size") == payload.size
+            && get_string(l, "spiciness") == payload.spiciness
+            && get_bool(l, "addDrink").unwrap_or(false) == add_drink
+    });
+    match same {
+        Some(line) => {
+            let merged = get_i64(line, "quantity").unwrap_or(1) + quantity;
+            check_quantity(merged)?;
+            line.insert("quantity", merged);
+        }
+        None => {
+            if lines.len() >= MAX_LINES {
+                return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("a cart holds at most {} lines", MAX_LINES)));
+            }
+            lines.push(doc! {
+                "id": mongodb::bson::oid::ObjectId::new().to_hex(),
+                "menuItemId": &menu_item_id,
+                "quantity": quantity,
+                "size": &payload.size,
+                "spiciness": &payload.spiciness,
+                "addDrink": add_drink
+            });
+        }
+    }
+    save_lines(&db, &claims, restaurant_id.as_deref(), lines.into_iter().map(Bson::Document).collect()).await?;
+    let cart = load_cart(&db, &claims).await?;
+    Ok(data_response_with_status(StatusCode::CREATED, Bson::Document(cart_view(&db, &claims, &cart, &CheckoutRequest::default()).await?)))
+}
+
+// PATCH /cart/items/{id}
+async fn update_line(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<UpdateLineRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let cart = load_cart(&db, &claims).await?;
+    let mut lines = cart_lines(&cart);
+    let Some(index) = lines.iter().position(|l| get_string(l, "id").as_deref() == Some(id.as_str())) else {
+        return Err(error_response(StatusCode::NOT_FOUND, "cart.line_not_found", "Cart line not found"));
+    };
+    if payload.quantity == Some(0) {
+        lines.remove(index);
+    } else {
+        let line = &mut lines[index];
+        if let Some(quantity) = payload.quantity {
+            check_quantity(quantity)?;
+            line.insert("quantity", quantity);
+        }
+        if let Some(size) = payload.size {
+            line.insert("size", Some(size).filter(|s| !s.is_empty()));
+        }
+        if let Some(spiciness) = payload.spiciness {
+            line.insert("spiciness", Some(spiciness).filter(|s| !s.is_empty()));
+        }
+        if let Some(add_drink) = payload.addDrink {
+            line.insert("addDrink", add_drink);
+        }
+    }
+    let restaurant_id = get_string(&cart, "restaurantId");
+    save_lines(&db, &claims, restaurant_id.as_deref(), lines.into_iter().map(Bson::Document).collect()).await?;
+    respond(&db, &claims).await
+}
+
+// DELETE /cart/items/{id}
+async fn remove_line(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let cart = load_cart(&db, &claims).await?;
+    let mut lines = cart_lines(&cart);
+    let before = lines.len();
+    lines.retain(|l| get_string(l, "id").as_deref() != Some(id.as_str()));
+    if lines.len() == before {
+        return Err(error_response(StatusCode::NOT_FOUND, "cart.line_not_found", "Cart line not found"));
+    }
+    let restaurant_id = get_string(&cart, "restaurantId");
+    save_lines(&db, &claims, restaurant_id.as_deref(), lines.into_iter().map(Bson::Document).collect()).await?;
+    respond(&db, &claims).await
+}
+
+// Give a cart back after a checkout that didn't go through.
+async fn release_checkout(db: &Database, claims: &Claims, token: &str){
+    let released = db.collection::<Document>("carts")
+        .update_one(doc! { "userId": &claims.sub, "checkoutToken": token }, doc! { "$unset": { "checkoutToken": "", "checkoutAt": "" } })
+        .await;
+    if let Err(e) = released {
+        eprintln!("cart.release_checkout error: {}", e);
+    }
+}
+
+// POST /cart/checkout
+// Places the cart through the same path as POST /orders and empties it once the order exists.
+// The cart is claimed first, so a second checkout running at the same time can't place it again.
+async fn checkout(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<CheckoutRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let carts = db.collection::<Document>("carts");
+    let token = mongodb::bson::oid::ObjectId::new().to_hex();
+    let now = now_datetime();
+    let stale = mongodb::bson::DateTime::from_millis(now.timestamp_millis() - CHECKOUT_CLAIM_MILLIS);
+    let claim = doc! {
+        "userId": &claims.sub,
+        "$or": [{ "checkoutToken": { "$exists": false } }, { "checkoutAt": { "$lt": stale } }]
+    };
+    let cart = carts.find_one_and_update(claim, doc! { "$set": { "checkoutToken": &token, "checkoutAt": now } })
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let Some(cart) = cart else {
+        let existing = load_cart(&db, &claims).await?;
+        if cart_lines(&existing).is_empty() {
+            return Err(error_response(StatusCode::BAD_REQUEST, "cart.empty", "cart is empty"));
+        }
+        return Err(error_response(StatusCode::CONFLICT, "cart.checkout_in_progress", "this cart is already being checked out"));
+    };
+    if cart_lines(&cart).is_empty() {
+        release_checkout(&db, &claims, &token).await;
+        return Err(error_response(StatusCode::BAD_REQUEST, "cart.empty", "cart is empty"));
+    }
+
+    let view = match cart_view(&db, &claims, &cart, &payload).await {
+        Ok(view) => view,
+        Err(e) => {
+            release_checkout(&db, &claims, &token).await;
+            return Err(e);
+        }
+    };
+    if let Some(first) = view.get_array("errors").ok().and_then(|e| e.first()).and_then(Bson::as_document) {
+        release_checkout(&db, &claims, &token).await;
+        let code = get_string(first, "code").unwrap_or_else(|| "validation.failed".to_string());
+        let message = get_string(first, "message").unwrap_or_default();
+        return Err(error_response(StatusCode::BAD_REQUEST, &code, &message));
+    }
+    let created = match place_order(&db, &claims, order_request(&cart, &payload)).await {
+        Ok(created) => created,
+        Err(e) => {
+            release_checkout(&db, &claims, &token).await;
+            return Err(e);
+        }
+    };
+    carts.delete_one(doc! { "userId": &claims.sub, "checkoutToken": &token })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(data_response_with_status(StatusCode::CREATED, Bson::Document(created)))
+}
+
+pub fn cart_router(db: Database) -> Router{
+    Router::new()
+        .route("/", get(get_cart).delete(clear_cart))
+        .route("/items", post(add_line))
+        .route("/items/{id}", patch(update_line).delete(remove_line))
+        .route("/checkout", post(checkout))
+        .with_state(db)
+}
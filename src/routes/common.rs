@@ -19,6 +19,19 @@ pub fn error_response(status: StatusCode, code: &str, message: &str) -> (StatusC
     (status, Json(doc! { "message": message, "code": code }))
 }
 
+pub fn invalid(message: &str) -> (StatusCode, Json<Document>){
+    error_response(StatusCode::BAD_REQUEST, "validation.failed", message)
+}
+
+// Trimmed text of 1-`max_chars` characters; `field` names it in the error.
+pub fn checked_text(field: &str, value: &str, max_chars: usize) -> Result<String, (StatusCode, Json<Document>)>{
+    let value = value.trim();
+    if value.is_empty() || value.chars().count() > max_chars {
+        return Err(invalid(&format!("{} must be 1-{} characters", field, max_chars)));
+    }
+    Ok(value.to_string())
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub sub: String,
@@ -52,7 +65,7 @@ pub fn get_i64(doc: &Document, key: &str) -> Option<i64>{
 }
 
 pub fn get_array(doc: &Document, key: &str) -> Option<Vec<Bson>>{
-    doc.get_array(key).ok().map(|arr| arr.clone())
+    doc.get_array(key).ok().cloned()
 }
 
 pub fn document_id(doc: &Document) -> Option<String>{
@@ -148,3 +161,63 @@ pub fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64{
     let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
     r * c
 }
+
+// Escape user text for use inside a MongoDB `$regex`.
+pub fn escape_regex(text: &str) -> String{
+    text.chars().fold(String::new(), |mut out, c| {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+        out
+    })
+}
+
+// Matches a document by its string id or, for older rows, its ObjectId
+pub fn id_filter(id: &str) -> Document{
+    doc! {
+        "$or": [
+            { "id": id },
+            { "_id": mongodb::bson::oid::ObjectId::parse_str(id).ok() }
+        ]
+    }
+}
+
+pub fn menu_item_filter(menu_item_id: &str) -> Document{
+    id_filter(menu_item_id)
+}
+
+// The shop a menu item belongs to; older rows use shop_id or restaurant_id.
+pub fn menu_restaurant_id(menu_doc: &Document) -> Option<String>{
+    get_string(menu_doc, "restaurantId")
+        .or_else(|| get_string(menu_doc, "shop_id"))
+        .or_else(|| get_string(menu_doc, "restaurant_id"))
+}
+
+// Campus runs on Taiwan time (UTC+8, no DST)
+pub fn taipei_now() -> chrono::DateTime<chrono::FixedOffset>{
+    let offset = chrono::FixedOffset::east_opt(8 * 60 * 60).expect("valid offset");
+    chrono::Utc::now().with_timezone(&offset)
+}
+
+// The shop a restaurant token acts for: its linked restaurantId, or the account itself.
+pub fn claims_restaurant_id(claims: &Claims) -> String{
+    claims.restaurant_id.clone().filter(|r| !r.is_empty()).unwrap_or_else(|| claims.sub.clone())
+}
+
+// A restaurant may only name its own shop; anything else is refused rather than acted on.
+pub fn requested_restaurant_id(claims: &Claims, requested: Option<&str>) -> Result<String, (StatusCode, Json<Document>)>{
+    let own = claims_restaurant_id(claims);
+    match requested.filter(|r| !r.is_empty()) {
+        Some(requested) if requested != own => Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden")),
+        _ => Ok(own),
+    }
+}
+
+// A write refused by a unique index.
+pub fn is_duplicate_key(error: &mongodb::error::Error) -> bool{
+    matches!(
+        error.kind.as_ref(),
+        mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteError(e)) if e.code == 11000
+    )
+}
@@ -4,6 +4,9 @@ use serde::Deserialize;
 use futures::stream::TryStreamExt;
 use axum::http::StatusCode;
 use crate::routes::common::{ApiResult, data_response, error_response, document_id, get_i64, now_datetime, get_string, get_f64, date_range_to_bson, iso_from_bson, require_role};
+use crate::routes::inventory::restore_order_stock;
+use crate::routes::promos::restore_order_promotion;
+use crate::routes::locations::load_locations;
 
 #[derive(Deserialize)]
 struct AcceptRequest {
@@ -83,19 +86,18 @@ async fn map_delivery(db: &Database, order: &Document) -> Result<Document, (Stat
     }
     let has_lat = merchant_doc.get("lat").and_then(Bson::as_f64).is_some();
     let has_lng = merchant_doc.get("lng").and_then(Bson::as_f64).is_some();
-    if !(has_lat && has_lng) {
-        if let Some(rest_id) = get_string(order, "restaurantId") {
-            let shops = db.collection::<Document>("shops");
-            if let Ok(Some(shop)) = shops.find_one(doc! { "id": rest_id }).await {
-                if let Some(lat) = get_f64(&shop, "lat") {
-                    merchant_doc.insert("lat", Bson::Double(lat));
-                }
-                if let Some(lng) = get_f64(&shop, "lng") {
-                    merchant_doc.insert("lng", Bson::Double(lng));
-                }
-                if let Some(name) = get_string(&shop, "name") {
-                    merchant_doc.entry("name".to_string()).or_insert(Bson::String(name));
-                }
+    if !(has_lat && has_lng)
+        && let Some(rest_id) = get_string(order, "restaurantId") {
+        let shops = db.collection::<Document>("shops");
+        if let Ok(Some(shop)) = shops.find_one(doc! { "id": rest_id }).await {
+            if let Some(lat) = get_f64(&shop, "lat") {
+                merchant_doc.insert("lat", Bson::Double(lat));
+            }
+            if let Some(lng) = get_f64(&shop, "lng") {
+                merchant_doc.insert("lng", Bson::Double(lng));
+            }
+            if let Some(name) = get_string(&shop, "name") {
+                merchant_doc.entry("name".to_string()).or_insert(Bson::String(name));
             }
         }
     }
@@ -140,10 +142,9 @@ async fn get_delivery(Path(id): Path<String>, State(db): State<Database>, header
     if get_string(&order_doc, "userId").as_deref() == Some(&claims.sub) {
         return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
     }
-    if get_string(&order_doc, "status").as_deref() != Some("available") {
-        if get_string(&order_doc, "delivererId").as_deref() != Some(&claims.sub) {
-            return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
-        }
+    if get_string(&order_doc, "status").as_deref() != Some("available")
+        && get_string(&order_doc, "delivererId").as_deref() != Some(&claims.sub) {
+        return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
     }
 
     Ok(data_response(Bson::Document(map_delivery(&db, &order_doc).await?)))
@@ -273,6 +274,14 @@ async fn update_status(Path(id): Path<String>, State(db): State<Database>, heade
     if result.matched_count == 0 {
         return Err(error_response(StatusCode::NOT_FOUND, "order.not_found", "Order not found"));
     }
+    if payload.status == "cancelled" {
+        restore_order_stock(&db, &id)
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        restore_order_promotion(&db, &id)
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    }
     let updated = collection.find_one(doc! { "id": &id })
         .await
         .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
@@ -296,42 +305,22 @@ async fn report_incident(Path(id): Path<String>, State(db): State<Database>, hea
     Ok(data_response(Bson::Document(doc! { "status": "reported" })))
 }
 
+// GET /delivery/locations: enabled campus spots grouped by category. Public.
 async fn list_locations(State(db): State<Database>) -> ApiResult{
-    // Public list; keep open. Merge delivery_locations and NTOU_location.
+    let locations = load_locations(&db, false)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
     let mut grouped: std::collections::BTreeMap<String, Vec<Bson>> = std::collections::BTreeMap::new();
-
-    // delivery_locations
-    let collection = db.collection::<Document>("delivery_locations");
-    if let Ok(mut cursor) = collection.find(doc! {}).await {
-        while let Some(doc) = cursor.try_next()
-            .await
-            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))? {
-            let category = get_string(&doc, "category").unwrap_or_else(|| "default".to_string());
-            let item = doc! {
-                "name": get_string(&doc, "name").unwrap_or_default(),
-                "lat": doc.get_f64("lat").ok(),
-                "lng": doc.get_f64("lng").ok()
-            };
-            grouped.entry(category).or_default().push(Bson::Document(item));
-        }
-    }
-
-    // NTOU_location
-    let ntou = db.collection::<Document>("NTOU_location");
-    if let Ok(mut cursor) = ntou.find(doc! {}).await {
-        while let Some(doc) = cursor.try_next()
-            .await
-            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))? {
-            let location = doc.get_document("location").ok();
-            let lat = location.and_then(|d| d.get_f64("lat").ok());
-            let lng = location.and_then(|d| d.get_f64("lng").ok());
-            let item = doc! {
-                "name": get_string(&doc, "name").unwrap_or_default(),
-                "lat": lat,
-                "lng": lng
-            };
-            grouped.entry("default".to_string()).or_default().push(Bson::Document(item));
-        }
+    for location in locations {
+        let category = get_string(&location, "category").unwrap_or_default();
+        let item = doc! {
+            "id": get_string(&location, "id"),
+            "name": get_string(&location, "name").unwrap_or_default(),
+            "lat": get_f64(&location, "lat"),
+            "lng": get_f64(&location, "lng"),
+            "instructions": get_string(&location, "instructions")
+        };
+        grouped.entry(category).or_default().push(Bson::Document(item));
     }
 
     let locations: Vec<Bson> = grouped.into_iter().map(|(category, items)| {
@@ -349,10 +338,9 @@ async fn update_location(Path(_id): Path<String>, State(db): State<Database>, he
     let Some(order_doc) = order else {
         return Err(error_response(StatusCode::NOT_FOUND, "order.not_found", "Order not found"));
     };
-    if let Some(deliverer) = get_string(&order_doc, "delivererId") {
-        if !deliverer.is_empty() && deliverer != claims.sub {
-            return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
-        }
+    if let Some(deliverer) = get_string(&order_doc, "delivererId")
+        && !deliverer.is_empty() && deliverer != claims.sub {
+        return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
     }
     let lat = payload.lat.ok_or_else(|| error_response(StatusCode::BAD_REQUEST, "validation.failed", "lat required"))?;
     let lng = payload.lng.ok_or_else(|| error_response(StatusCode::BAD_REQUEST, "validation.failed", "lng required"))?;
@@ -439,12 +427,58 @@ async fn list_notifications(State(db): State<Database>, headers: HeaderMap, Quer
     Ok(data_response(Bson::Array(notifications)))
 }
 
+// Rider rating average, histogram and tag counts over every order this rider delivered.
+async fn delivery_stats(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["deliverer"])?;
+    let collection = db.collection::<Document>("orders");
+    let mut cursor = collection.find(doc! { "delivererId": &claims.sub, "status": "delivered" })
+        .projection(doc! { "rating": 1 })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let mut delivered = 0i64;
+    let mut rated = 0i64;
+    let mut sum = 0i64;
+    let mut histogram = [0i64; 5];
+    let mut tags: std::collections::BTreeMap<String, i64> = std::collections::BTreeMap::new();
+    while let Some(doc) = cursor.try_next()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))? {
+        delivered += 1;
+        // moderated ratings don't count against the rider
+        let Some(rating) = doc.get_document("rating").ok().filter(|r| !r.get_bool("hidden").unwrap_or(false)) else {
+            continue;
+        };
+        if let Some(score) = get_f64(rating, "riderScore").map(|s| s.round() as i64).filter(|s| (1..=5).contains(s)) {
+            rated += 1;
+            sum += score;
+            histogram[(score - 1) as usize] += 1;
+        }
+        for tag in rating.get_array("tags").map(|t| t.as_slice()).unwrap_or_default().iter().filter_map(Bson::as_str) {
+            *tags.entry(tag.to_string()).or_insert(0) += 1;
+        }
+    }
+    let mut histogram_doc = Document::new();
+    for (i, count) in histogram.iter().enumerate() {
+        histogram_doc.insert((i + 1).to_string(), *count);
+    }
+    let average = (rated > 0).then(|| ((sum as f64 / rated as f64) * 10.0).round() / 10.0);
+    let tag_counts: Vec<Bson> = tags.into_iter().map(|(tag, count)| Bson::Document(doc! { "tag": tag, "count": count })).collect();
+    Ok(data_response(Bson::Document(doc! {
+        "deliveredCount": delivered,
+        "rating": average,
+        "ratingCount": rated,
+        "ratingHistogram": histogram_doc,
+        "tags": tag_counts
+    })))
+}
+
 pub fn delivery_router(db: Database) -> Router{
     Router::new()
         .route("/available", get(list_available))
         .route("/active", get(list_active))
         .route("/history", get(list_history))
         .route("/earnings", get(list_earnings))
+        .route("/stats", get(delivery_stats))
         .route("/notifications", get(list_notifications))
         .route("/locations", get(list_locations))
         .route("/{id}", get(get_delivery).post(accept_delivery))
@@ -0,0 +1,177 @@
+use axum::{Router, routing::{get, put}, extract::{State, Path}, Json, http::HeaderMap};
+use mongodb::{bson::{doc, Bson, Document}, Database};
+use futures::stream::TryStreamExt;
+use axum::http::StatusCode;
+use std::collections::{HashMap, HashSet};
+use crate::routes::common::{ApiResult, data_response, error_response, document_id, get_string, get_bool, require_role, auth_claims, now_datetime, menu_item_filter, menu_restaurant_id};
+use crate::routes::hours::open_status;
+use crate::routes::i18n::{localized, preferred_languages};
+use crate::routes::onboarding::shop_is_listed;
+use crate::routes::pricing::resolve_price;
+use crate::routes::ratings::rating_view;
+
+// `favorites` holds one `{ userId, kind: "shop" | "item", targetId, createdAt }` per saved shop or dish.
+
+fn kind_of(segment: &str) -> Result<&'static str, (StatusCode, Json<Document>)>{
+    match segment {
+        "shops" => Ok("shop"),
+        "items" => Ok("item"),
+        _ => Err(error_response(StatusCode::NOT_FOUND, "favorite.not_found", "favorites are shops or items")),
+    }
+}
+
+// Ids the caller has saved, or `None` for anonymous callers so the flag can be left out.
+pub async fn favorite_ids(db: &Database, headers: &HeaderMap, kind: &str) -> Result<Option<HashSet<String>>, (StatusCode, Json<Document>)>{
+    let Ok(claims) = auth_claims(headers) else {
+        return Ok(None);
+    };
+    let ids = db.collection::<Document>("favorites")
+        .distinct("targetId", doc! { "userId": &claims.sub, "kind": kind })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(Some(ids.into_iter().filter_map(|id| id.as_str().map(|s| s.to_string())).collect()))
+}
+
+// Drop saved dishes that no longer exist; called when menu items are deleted.
+pub async fn remove_item_favorites(db: &Database, item_ids: &[String]){
+    if item_ids.is_empty() {
+        return;
+    }
+    if let Err(e) = db.collection::<Document>("favorites").delete_many(doc! { "kind": "item", "targetId": { "$in": item_ids } }).await {
+        eprintln!("favorites.remove_item_favorites error: {}", e);
+    }
+}
+
+// GET /me/favorites
+async fn list_favorites(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let languages = preferred_languages(&headers);
+    let saved: Vec<Document> = db.collection::<Document>("favorites").find(doc! { "userId": &claims.sub })
+        .sort(doc! { "createdAt": -1 })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .try_collect()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let ids_of = |kind: &str| -> Vec<String> {
+        saved.iter().filter(|f| get_string(f, "kind").as_deref() == Some(kind)).filter_map(|f| get_string(f, "targetId")).collect()
+    };
+    let shop_ids = ids_of("shop");
+    let item_ids = ids_of("item");
+
+    let mut items: HashMap<String, Document> = HashMap::new();
+    if !item_ids.is_empty() {
+        let menu_filters: Vec<Bson> = item_ids.iter().map(|id| Bson::Document(menu_item_filter(id))).collect();
+        items = db.collection::<Document>("menu").find(doc! { "$or": menu_filters })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+            .try_collect::<Vec<Document>>()
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+            .into_iter()
+            .filter(|item| !get_bool(item, "archived").unwrap_or(false))
+            .filter_map(|item| document_id(&item).map(|id| (id, item)))
+            .collect();
+    }
+    let mut restaurant_ids: Vec<String> = shop_ids.clone();
+    restaurant_ids.extend(items.values().filter_map(menu_restaurant_id));
+    let mut shops: HashMap<String, Document> = HashMap::new();
+    if !restaurant_ids.is_empty() {
+        shops = db.collection::<Document>("shops").find(doc! { "id": { "$in": &restaurant_ids } })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+            .try_collect::<Vec<Document>>()
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+            .into_iter()
+            .filter(shop_is_listed)
+            .filter_map(|shop| document_id(&shop).map(|id| (id, shop)))
+            .collect();
+    }
+
+    // saved order, skipping shops and dishes that have since gone away
+    let shop_views: Vec<Bson> = shop_ids.iter().filter_map(|id| shops.get(id)).map(|shop| {
+        let mut view = doc! {
+            "id": document_id(shop),
+            "name": localized(shop, "name", &languages),
+            "imageUrl": get_string(shop, "imageUrl"),
+            "thumbnailUrl": get_string(shop, "thumbnailUrl")
+        };
+        view.extend(rating_view(shop));
+        view.extend(open_status(shop));
+        Bson::Document(view)
+    }).collect();
+    let now = now_datetime();
+    let item_views: Vec<Bson> = item_ids.iter().filter_map(|id| items.get(id)).filter_map(|item| {
+        let rest_id = menu_restaurant_id(item);
+        // a dish from a hidden shop can't be ordered either
+        let shop = match rest_id.as_deref() {
+            Some(rest_id) => Some(shops.get(rest_id)?),
+            None => None,
+        };
+        let (price, _) = resolve_price(item, now);
+        Some(Bson::Document(doc! {
+            "id": document_id(item),
+            "name": localized(item, "name", &languages),
+            "price": price,
+            "imageUrl": get_string(item, "imageUrl"),
+            "thumbnailUrl": get_string(item, "thumbnailUrl"),
+            "isAvailable": get_bool(item, "isAvailable").unwrap_or(true),
+            "restaurantId": rest_id,
+            "restaurantName": shop.and_then(|s| localized(s, "name", &languages))
+        }))
+    }).collect();
+
+    Ok(data_response(Bson::Document(doc! { "shops": shop_views, "items": item_views })))
+}
+
+// PUT /me/favorites/{shops|items}/{id}
+async fn add_favorite(Path((segment, id)): Path<(String, String)>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let kind = kind_of(&segment)?;
+    let target_id = if kind == "shop" {
+        let shop = db.collection::<Document>("shops").find_one(doc! { "id": &id })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        match shop.filter(shop_is_listed) {
+            Some(shop) => document_id(&shop).unwrap_or(id),
+            None => return Err(error_response(StatusCode::NOT_FOUND, "restaurant.not_found", "Restaurant not found")),
+        }
+    } else {
+        let item = db.collection::<Document>("menu").find_one(menu_item_filter(&id))
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        match item.filter(|item| !get_bool(item, "archived").unwrap_or(false)) {
+            Some(item) => document_id(&item).unwrap_or(id),
+            None => return Err(error_response(StatusCode::NOT_FOUND, "menu.unavailable", "Menu item not found")),
+        }
+    };
+    // saving twice is harmless
+    db.collection::<Document>("favorites")
+        .update_one(
+            doc! { "userId": &claims.sub, "kind": kind, "targetId": &target_id },
+            doc! { "$setOnInsert": { "id": mongodb::bson::oid::ObjectId::new().to_hex(), "createdAt": now_datetime() } },
+        )
+        .upsert(true)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(data_response(Bson::Document(doc! { "kind": kind, "id": target_id, "isFavorite": true })))
+}
+
+// DELETE /me/favorites/{shops|items}/{id}
+async fn remove_favorite(Path((segment, id)): Path<(String, String)>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let kind = kind_of(&segment)?;
+    db.collection::<Document>("favorites")
+        .delete_one(doc! { "userId": &claims.sub, "kind": kind, "targetId": &id })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(data_response(Bson::Document(doc! { "kind": kind, "id": id, "isFavorite": false })))
+}
+
+pub fn favorites_router(db: Database) -> Router{
+    Router::new()
+        .route("/favorites", get(list_favorites))
+        .route("/favorites/{kind}/{id}", put(add_favorite).delete(remove_favorite))
+        .with_state(db)
+}
@@ -0,0 +1,347 @@
+#![allow(non_snake_case)]
+
+use axum::{Router, routing::{get, put}, extract::{State, Path}, Json, http::HeaderMap};
+use mongodb::{bson::{doc, Bson, Document}, Database};
+use futures::stream::TryStreamExt;
+use serde::Deserialize;
+use axum::http::StatusCode;
+use chrono::{DateTime, FixedOffset};
+use crate::routes::common::{ApiResult, data_response, error_response, document_id, get_string, get_i64, get_f64, get_array, require_role, now_datetime};
+use crate::routes::schedule::{TimeWindow, validate_windows, windows_allow, windows_to_bson};
+
+// Delivery fees are priced here, never taken from the client. The global rules are kept in
+// `fee_settings` (id "delivery"); a shop's `deliveryFeeRules` overrides any of them for that shop.
+// All amounts are whole TWD.
+
+const MAX_PEAK_MULTIPLIER: f64 = 5.0;
+
+#[derive(Clone)]
+pub struct FeeRules {
+    pub base_fee: i64,
+    // the first `included_km` are covered by the base fee
+    pub included_km: f64,
+    pub per_km: f64,
+    pub small_order_threshold: i64,
+    pub small_order_surcharge: i64,
+    pub peak_multiplier: f64,
+    pub peak_hours: Vec<Bson>,
+    pub max_fee: Option<i64>,
+}
+
+impl Default for FeeRules {
+    fn default() -> Self{
+        FeeRules {
+            base_fee: 20,
+            included_km: 1.0,
+            per_km: 10.0,
+            small_order_threshold: 100,
+            small_order_surcharge: 10,
+            peak_multiplier: 1.0,
+            peak_hours: Vec::new(),
+            max_fee: None,
+        }
+    }
+}
+
+impl FeeRules {
+    // Fields missing from `rules` keep their current value.
+    fn overlay(&mut self, rules: &Document){
+        if let Some(v) = get_i64(rules, "baseFee") {
+            self.base_fee = v;
+        }
+        if let Some(v) = get_f64(rules, "includedKm") {
+            self.included_km = v;
+        }
+        if let Some(v) = get_f64(rules, "perKm") {
+            self.per_km = v;
+        }
+        if let Some(v) = get_i64(rules, "smallOrderThreshold") {
+            self.small_order_threshold = v;
+        }
+        if let Some(v) = get_i64(rules, "smallOrderSurcharge") {
+            self.small_order_surcharge = v;
+        }
+        if let Some(v) = get_f64(rules, "peakMultiplier") {
+            self.peak_multiplier = v;
+        }
+        if let Some(v) = get_array(rules, "peakHours") {
+            self.peak_hours = v;
+        }
+        if let Some(v) = get_i64(rules, "maxFee") {
+            self.max_fee = Some(v);
+        }
+    }
+
+    fn to_document(&self) -> Document{
+        doc! {
+            "baseFee": self.base_fee,
+            "includedKm": self.included_km,
+            "perKm": self.per_km,
+            "smallOrderThreshold": self.small_order_threshold,
+            "smallOrderSurcharge": self.small_order_surcharge,
+            "peakMultiplier": self.peak_multiplier,
+            "peakHours": self.peak_hours.clone(),
+            "maxFee": self.max_fee
+        }
+    }
+}
+
+pub struct FeeQuote {
+    pub fee: i64,
+    pub breakdown: Document,
+}
+
+#[derive(Deserialize)]
+struct FeeRulesRequest {
+    baseFee: Option<i64>,
+    includedKm: Option<f64>,
+    perKm: Option<f64>,
+    smallOrderThreshold: Option<i64>,
+    smallOrderSurcharge: Option<i64>,
+    peakMultiplier: Option<f64>,
+    peakHours: Option<Vec<TimeWindow>>,
+    maxFee: Option<i64>,
+}
+
+// Only the fields that were sent, so a shop override leaves the rest to the global rules.
+fn rules_document(payload: &FeeRulesRequest) -> Result<Document, (StatusCode, Json<Document>)>{
+    let amounts = [payload.baseFee, payload.smallOrderThreshold, payload.smallOrderSurcharge, payload.maxFee];
+    if amounts.iter().flatten().any(|v| *v < 0) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "fees must not be negative"));
+    }
+    if [payload.includedKm, payload.perKm].iter().flatten().any(|v| !v.is_finite() || *v < 0.0) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "includedKm/perKm must not be negative"));
+    }
+    if payload.peakMultiplier.is_some_and(|m| !(1.0..=MAX_PEAK_MULTIPLIER).contains(&m)) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("peakMultiplier must be 1 - {}", MAX_PEAK_MULTIPLIER)));
+    }
+    let mut rules = Document::new();
+    if let Some(v) = payload.baseFee {
+        rules.insert("baseFee", v);
+    }
+    if let Some(v) = payload.includedKm {
+        rules.insert("includedKm", v);
+    }
+    if let Some(v) = payload.perKm {
+        rules.insert("perKm", v);
+    }
+    if let Some(v) = payload.smallOrderThreshold {
+        rules.insert("smallOrderThreshold", v);
+    }
+    if let Some(v) = payload.smallOrderSurcharge {
+        rules.insert("smallOrderSurcharge", v);
+    }
+    if let Some(v) = payload.peakMultiplier {
+        rules.insert("peakMultiplier", v);
+    }
+    if let Some(windows) = payload.peakHours.as_deref() {
+        validate_windows(windows)?;
+        rules.insert("peakHours", windows_to_bson(windows));
+    }
+    if let Some(v) = payload.maxFee {
+        rules.insert("maxFee", v);
+    }
+    Ok(rules)
+}
+
+async fn global_rules(db: &Database) -> Result<Document, (StatusCode, Json<Document>)>{
+    let stored = db.collection::<Document>("fee_settings")
+        .find_one(doc! { "id": "delivery" })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(stored.and_then(|d| d.get_document("rules").ok().cloned()).unwrap_or_default())
+}
+
+// The rules that apply to an order from `shop` (or the global ones when there is no shop).
+pub async fn load_fee_rules(db: &Database, shop: Option<&Document>) -> Result<FeeRules, (StatusCode, Json<Document>)>{
+    let mut rules = FeeRules::default();
+    rules.overlay(&global_rules(db).await?);
+    if let Some(overrides) = shop.and_then(|s| s.get_document("deliveryFeeRules").ok()) {
+        rules.overlay(overrides);
+    }
+    Ok(rules)
+}
+
+// base + distance past the included km, raised by the peak multiplier, plus the small-order surcharge.
+pub fn quote_fee(rules: &FeeRules, distance_km: f64, subtotal: i64, at: &DateTime<FixedOffset>) -> FeeQuote{
+    let distance_fee = ((distance_km - rules.included_km).max(0.0) * rules.per_km).round() as i64;
+    let is_peak = !rules.peak_hours.is_empty() && windows_allow(Some(&rules.peak_hours), at);
+    let peak_surcharge = if is_peak {
+        ((rules.base_fee + distance_fee) as f64 * (rules.peak_multiplier - 1.0)).round() as i64
+    } else {
+        0
+    };
+    let small_order_surcharge = if subtotal < rules.small_order_threshold { rules.small_order_surcharge } else { 0 };
+    let mut fee = rules.base_fee + distance_fee + peak_surcharge + small_order_surcharge;
+    if let Some(max_fee) = rules.max_fee {
+        fee = fee.min(max_fee);
+    }
+    FeeQuote {
+        fee,
+        breakdown: doc! {
+            "baseFee": rules.base_fee,
+            "distanceKm": (distance_km * 10.0).round() / 10.0,
+            "distanceFee": distance_fee,
+            "peak": is_peak,
+            "peakMultiplier": if is_peak { rules.peak_multiplier } else { 1.0 },
+            "peakSurcharge": peak_surcharge,
+            "smallOrderSurcharge": small_order_surcharge,
+            "total": fee
+        },
+    }
+}
+
+// GET /admin/delivery-fees
+async fn get_fee_rules(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    let rules = load_fee_rules(&db, None).await?;
+    let shops: Vec<Document> = db.collection::<Document>("shops")
+        .find(doc! { "deliveryFeeRules": { "$exists": true } })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .try_collect()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let overrides: Vec<Bson> = shops.iter().map(|shop| Bson::Document(doc! {
+        "restaurantId": document_id(shop),
+        "name": get_string(shop, "name"),
+        "rules": shop.get_document("deliveryFeeRules").cloned().unwrap_or_default()
+    })).collect();
+    Ok(data_response(Bson::Document(doc! { "rules": rules.to_document(), "overrides": overrides })))
+}
+
+// PUT /admin/delivery-fees
+// Replaces the global rules; anything left out goes back to the built-in default.
+async fn set_fee_rules(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<FeeRulesRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["admin"])?;
+    let rules = rules_document(&payload)?;
+    db.collection::<Document>("fee_settings")
+        .update_one(
+            doc! { "id": "delivery" },
+            doc! { "$set": { "rules": rules, "updatedAt": now_datetime(), "updatedBy": &claims.sub } },
+        )
+        .upsert(true)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let rules = load_fee_rules(&db, None).await?;
+    Ok(data_response(Bson::Document(rules.to_document())))
+}
+
+// PUT /admin/delivery-fees/restaurants/{id}
+async fn set_shop_fee_rules(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<FeeRulesRequest>) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    let rules = rules_document(&payload)?;
+    let shop = db.collection::<Document>("shops")
+        .find_one_and_update(doc! { "id": &id }, doc! { "$set": { "deliveryFeeRules": rules } })
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "restaurant.not_found", "Restaurant not found"))?;
+    let effective = load_fee_rules(&db, Some(&shop)).await?;
+    Ok(data_response(Bson::Document(doc! {
+        "restaurantId": &id,
+        "overrides": shop.get_document("deliveryFeeRules").cloned().unwrap_or_default(),
+        "rules": effective.to_document()
+    })))
+}
+
+// DELETE /admin/delivery-fees/restaurants/{id}
+async fn clear_shop_fee_rules(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    let result = db.collection::<Document>("shops")
+        .update_one(doc! { "id": &id }, doc! { "$unset": { "deliveryFeeRules": "" } })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    if result.matched_count == 0 {
+        return Err(error_response(StatusCode::NOT_FOUND, "restaurant.not_found", "Restaurant not found"));
+    }
+    let rules = load_fee_rules(&db, None).await?;
+    Ok(data_response(Bson::Document(doc! { "restaurantId": &id, "rules": rules.to_document() })))
+}
+
+pub fn fees_admin_router(db: Database) -> Router{
+    Router::new()
+        .route("/delivery-fees", get(get_fee_rules).put(set_fee_rules))
+        .route("/delivery-fees/restaurants/{id}", put(set_shop_fee_rules).delete(clear_shop_fee_rules))
+        .with_state(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // Monday 2026-10-19 in Taipei
+    fn at(hour: u32, minute: u32) -> DateTime<FixedOffset>{
+        FixedOffset::east_opt(8 * 60 * 60).unwrap().with_ymd_and_hms(2026, 10, 19, hour, minute, 0).unwrap()
+    }
+
+    fn lunch_peak(multiplier: f64) -> FeeRules{
+        FeeRules {
+            peak_multiplier: multiplier,
+            peak_hours: vec![Bson::Document(doc! { "days": [1_i64, 2, 3, 4, 5], "start": "11:00", "end": "13:00" })],
+            ..FeeRules::default()
+        }
+    }
+
+    #[test]
+    fn base_fee_covers_the_included_distance() {
+        let quote = quote_fee(&FeeRules::default(), 0.8, 200, &at(15, 0));
+        assert_eq!(quote.fee, 20);
+        assert_eq!(get_i64(&quote.breakdown, "distanceFee"), Some(0));
+    }
+
+    #[test]
+    fn distance_past_the_included_km_is_charged_per_km() {
+        let quote = quote_fee(&FeeRules::default(), 3.0, 200, &at(15, 0));
+        assert_eq!(get_i64(&quote.breakdown, "distanceFee"), Some(20));
+        assert_eq!(quote.fee, 40);
+    }
+
+    #[test]
+    fn distance_fee_is_rounded_to_whole_dollars() {
+        // 1.26 km past the included km at 10/km
+        let quote = quote_fee(&FeeRules::default(), 2.26, 200, &at(15, 0));
+        assert_eq!(get_i64(&quote.breakdown, "distanceFee"), Some(13));
+    }
+
+    #[test]
+    fn small_orders_pay_the_surcharge_below_the_threshold_only() {
+        let rules = FeeRules::default();
+        assert_eq!(quote_fee(&rules, 0.0, 99, &at(15, 0)).fee, 30);
+        assert_eq!(quote_fee(&rules, 0.0, 100, &at(15, 0)).fee, 20);
+    }
+
+    #[test]
+    fn peak_multiplier_raises_base_and_distance_but_not_the_surcharge() {
+        let rules = lunch_peak(1.5);
+        let quote = quote_fee(&rules, 3.0, 50, &at(12, 0));
+        assert_eq!(get_i64(&quote.breakdown, "peakSurcharge"), Some(20));
+        assert_eq!(quote.fee, 20 + 20 + 20 + 10);
+        assert_eq!(quote.breakdown.get_bool("peak").ok(), Some(true));
+    }
+
+    #[test]
+    fn no_peak_surcharge_outside_peak_hours() {
+        let quote = quote_fee(&lunch_peak(2.0), 3.0, 200, &at(13, 0));
+        assert_eq!(get_i64(&quote.breakdown, "peakSurcharge"), Some(0));
+        assert_eq!(quote.fee, 40);
+    }
+
+    #[test]
+    fn max_fee_caps_the_total() {
+        let rules = FeeRules { max_fee: Some(50), ..FeeRules::default() };
+        let quote = quote_fee(&rules, 10.0, 50, &at(15, 0));
+        assert_eq!(quote.fee, 50);
+        assert_eq!(get_i64(&quote.breakdown, "total"), Some(50));
+    }
+
+    #[test]
+    fn overlay_keeps_fields_that_are_not_overridden() {
+        let mut rules = FeeRules::default();
+        rules.overlay(&doc! { "baseFee": 35_i64, "maxFee": 80_i64 });
+        assert_eq!(rules.base_fee, 35);
+        assert_eq!(rules.max_fee, Some(80));
+        assert_eq!(rules.per_km, 10.0);
+    }
+}
@@ -0,0 +1,51 @@
+use mongodb::{bson::{doc, Bson, Document}, Database, IndexModel};
+use crate::routes::common::get_f64;
+
+pub const PREP_MINUTES: i64 = 10;
+pub const WALK_SPEED_KMH: f64 = 5.0;
+// used when either end of the trip has no coordinates
+const DEFAULT_TRAVEL_MINUTES: i64 = 15;
+
+// Collections that carry a GeoJSON point, the field it lives in, and where the plain lat/lng come from.
+const GEO_COLLECTIONS: [(&str, &str, &str); 2] = [
+    ("shops", "location", ""),
+    ("delivery_locations", "location", ""),
+];
+
+// Coordinates of a shop or location: the GeoJSON point if present, else plain lat/lng.
+pub fn doc_latlng(doc: &Document) -> Option<(f64, f64)>{
+    let from_point = doc.get_document("location").ok()
+        .and_then(|p| p.get_array("coordinates").ok())
+        .and_then(|c| Some((c.get(1)?.as_f64()?, c.first()?.as_f64()?)));
+    from_point.or_else(|| Some((get_f64(doc, "lat")?, get_f64(doc, "lng")?)))
+}
+
+// GeoJSON point for a stored `location` field; longitude comes first.
+pub fn geo_point(lat: f64, lng: f64) -> Bson{
+    Bson::Document(doc! { "type": "Point", "coordinates": [lng, lat] })
+}
+
+pub fn eta_minutes(distance_km: Option<f64>) -> i64{
+    let travel_minutes = match distance_km {
+        Some(km) if km > 0.0 => ((km / WALK_SPEED_KMH) * 60.0).ceil() as i64,
+        _ => DEFAULT_TRAVEL_MINUTES,
+    };
+    PREP_MINUTES + travel_minutes
+}
+
+// Derive GeoJSON points (longitude first) from lat/lng and make sure every geo collection has its 2dsphere index.
+pub async fn ensure_geo_indexes(db: &Database) -> mongodb::error::Result<()>{
+    for (name, field, source) in GEO_COLLECTIONS {
+        let collection = db.collection::<Document>(name);
+        let lat = format!("{}lat", source);
+        let lng = format!("{}lng", source);
+        // out-of-range values would make the index build fail
+        let filter = doc! { &lat: { "$gte": -90, "$lte": 90 }, &lng: { "$gte": -180, "$lte": 180 } };
+        let point = doc! { "type": "Point", "coordinates": [format!("${}", lng), format!("${}", lat)] };
+        let pipeline = vec![doc! { "$set": { field: Bson::Document(point) } }];
+        collection.update_many(filter, pipeline).await?;
+        let index = IndexModel::builder().keys(doc! { field: "2dsphere" }).build();
+        collection.create_index(index).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,223 @@
+#![allow(non_snake_case)]
+
+use axum::{Router, routing::{get, post, delete}, extract::{State, Path}, Json, http::HeaderMap};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate};
+use mongodb::{bson::{doc, Bson, Document}, Database};
+use serde::Deserialize;
+use axum::http::StatusCode;
+use crate::routes::common::{ApiResult, data_response, data_response_with_status, error_response, get_string, get_array, get_bool, require_role, claims_restaurant_id, taipei_now, now_datetime};
+use crate::routes::schedule::{TimeWindow, parse_hhmm, validate_windows, windows_allow, windows_to_bson};
+
+// how far ahead `next_open` looks before giving up
+const LOOKAHEAD_DAYS: i64 = 14;
+
+#[derive(Deserialize)]
+struct HoursRequest {
+    openingHours: Vec<TimeWindow>,
+}
+
+#[derive(Deserialize)]
+struct ClosureRequest {
+    // Asia/Taipei dates, both inclusive
+    from: String,
+    to: String,
+    reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PauseRequest {
+    // pause for this many minutes; omit to pause until resumed
+    minutes: Option<i64>,
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate>{
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+}
+
+fn paused_until(shop: &Document, offset: &FixedOffset) -> Option<DateTime<FixedOffset>>{
+    let until = shop.get_datetime("pausedUntil").ok()?;
+    DateTime::from_timestamp_millis(until.timestamp_millis()).map(|t| t.with_timezone(offset))
+}
+
+fn is_paused(shop: &Document, at: &DateTime<FixedOffset>) -> bool{
+    if !get_bool(shop, "paused").unwrap_or(false) {
+        return false;
+    }
+    paused_until(shop, at.offset()).is_none_or(|until| *at < until)
+}
+
+fn closures(shop: &Document) -> Vec<(NaiveDate, NaiveDate)>{
+    get_array(shop, "closures").unwrap_or_default()
+        .iter()
+        .filter_map(Bson::as_document)
+        .filter_map(|c| Some((parse_date(&get_string(c, "from")?)?, parse_date(&get_string(c, "to")?)?)))
+        .collect()
+}
+
+// Open at `at`: not paused, not inside a closure, and within the weekly hours.
+// Shops without any opening hours keep the old behaviour and are always open.
+pub fn shop_open_at(shop: &Document, at: &DateTime<FixedOffset>) -> bool{
+    if is_paused(shop, at) {
+        return false;
+    }
+    let day = at.date_naive();
+    if closures(shop).iter().any(|(from, to)| *from <= day && day <= *to) {
+        return false;
+    }
+    windows_allow(shop.get_array("openingHours").ok(), at)
+}
+
+// The next moment from `from` the shop is open. Opening can only begin when a window starts,
+// a closure ends or a pause runs out, so only those instants are checked.
+pub fn next_open(shop: &Document, from: &DateTime<FixedOffset>) -> Option<DateTime<FixedOffset>>{
+    if shop_open_at(shop, from) {
+        return Some(*from);
+    }
+    let offset = *from.offset();
+    let at_minute = |date: NaiveDate, minute: u32| {
+        (date.and_hms_opt(0, 0, 0)? + Duration::minutes(minute as i64)).and_local_timezone(offset).single()
+    };
+    let mut candidates: Vec<DateTime<FixedOffset>> = Vec::new();
+    if let Some(until) = paused_until(shop, &offset) {
+        candidates.push(until);
+    }
+    for (_, to) in closures(shop) {
+        if let Some(next_day) = to.succ_opt().and_then(|d| at_minute(d, 0)) {
+            candidates.push(next_day);
+        }
+    }
+    let windows = get_array(shop, "openingHours").unwrap_or_default();
+    for offset_days in 0..=LOOKAHEAD_DAYS {
+        let date = from.date_naive() + Duration::days(offset_days);
+        let weekday = date.weekday().number_from_monday();
+        for window in windows.iter().filter_map(Bson::as_document) {
+            let runs_today = window.get_array("days").is_ok_and(|days| days.iter().any(|d| match d {
+                Bson::Int32(v) => *v as u32 == weekday,
+                Bson::Int64(v) => *v as u32 == weekday,
+                _ => false,
+            }));
+            if let (true, Some(start)) = (runs_today, get_string(window, "start").as_deref().and_then(parse_hhmm)) {
+                candidates.extend(at_minute(date, start));
+            }
+        }
+    }
+    let horizon = *from + Duration::days(LOOKAHEAD_DAYS);
+    candidates.sort();
+    candidates.into_iter()
+        .filter(|t| t > from && *t <= horizon)
+        .find(|t| shop_open_at(shop, t))
+}
+
+// `isOpen` and `nextOpenAt` as the customer endpoints report them.
+pub fn open_status(shop: &Document) -> Document{
+    let now = taipei_now();
+    let is_open = shop_open_at(shop, &now);
+    let next = if is_open { None } else { next_open(shop, &now) };
+    doc! {
+        "isOpen": is_open,
+        "nextOpenAt": next.map(|t| t.to_rfc3339()),
+        "paused": is_paused(shop, &now)
+    }
+}
+
+fn hours_view(shop: &Document) -> Document{
+    let mut body = doc! {
+        "openingHours": Bson::Array(get_array(shop, "openingHours").unwrap_or_default()),
+        "closures": Bson::Array(get_array(shop, "closures").unwrap_or_default()),
+        "pausedUntil": shop.get_datetime("pausedUntil").ok().and_then(|t| t.try_to_rfc3339_string().ok())
+    };
+    body.extend(open_status(shop));
+    body
+}
+
+pub async fn load_shop(db: &Database, restaurant_id: &str) -> Result<Document, (StatusCode, Json<Document>)>{
+    db.collection::<Document>("shops").find_one(doc! { "id": restaurant_id })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "restaurant.not_found", "Restaurant not found"))
+}
+
+pub async fn update_shop(db: &Database, restaurant_id: &str, update: Document) -> Result<Document, (StatusCode, Json<Document>)>{
+    db.collection::<Document>("shops").find_one_and_update(doc! { "id": restaurant_id }, update)
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "restaurant.not_found", "Restaurant not found"))
+}
+
+// GET /restaurant/hours
+async fn get_hours(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let shop = load_shop(&db, &claims_restaurant_id(&claims)).await?;
+    Ok(data_response(Bson::Document(hours_view(&shop))))
+}
+
+// PUT /restaurant/hours
+async fn set_hours(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<HoursRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    validate_windows(&payload.openingHours)?;
+    let update = doc! { "$set": { "openingHours": windows_to_bson(&payload.openingHours) } };
+    let shop = update_shop(&db, &claims_restaurant_id(&claims), update).await?;
+    Ok(data_response(Bson::Document(hours_view(&shop))))
+}
+
+// POST /restaurant/closures
+async fn add_closure(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<ClosureRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let (Some(from), Some(to)) = (parse_date(&payload.from), parse_date(&payload.to)) else {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "from/to must be YYYY-MM-DD"));
+    };
+    if to < from {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "to must not be before from"));
+    }
+    let closure = doc! {
+        "id": mongodb::bson::oid::ObjectId::new().to_hex(),
+        "from": from.to_string(),
+        "to": to.to_string(),
+        "reason": payload.reason,
+        "createdAt": now_datetime()
+    };
+    let shop = update_shop(&db, &claims_restaurant_id(&claims), doc! { "$push": { "closures": closure } }).await?;
+    Ok(data_response_with_status(StatusCode::CREATED, Bson::Document(hours_view(&shop))))
+}
+
+// DELETE /restaurant/closures/{id}
+async fn remove_closure(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let shop = update_shop(&db, &claims_restaurant_id(&claims), doc! { "$pull": { "closures": { "id": &id } } }).await?;
+    Ok(data_response(Bson::Document(hours_view(&shop))))
+}
+
+// POST /restaurant/pause
+async fn pause(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<PauseRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let update = match payload.minutes {
+        Some(minutes) if minutes <= 0 => {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "minutes must be positive"));
+        }
+        Some(minutes) => {
+            let until = mongodb::bson::DateTime::from_millis(now_datetime().timestamp_millis() + minutes * 60 * 1000);
+            doc! { "$set": { "paused": true, "pausedUntil": until } }
+        }
+        None => doc! { "$set": { "paused": true }, "$unset": { "pausedUntil": "" } },
+    };
+    let shop = update_shop(&db, &claims_restaurant_id(&claims), update).await?;
+    Ok(data_response(Bson::Document(hours_view(&shop))))
+}
+
+// DELETE /restaurant/pause
+async fn resume(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let update = doc! { "$set": { "paused": false }, "$unset": { "pausedUntil": "" } };
+    let shop = update_shop(&db, &claims_restaurant_id(&claims), update).await?;
+    Ok(data_response(Bson::Document(hours_view(&shop))))
+}
+
+pub fn hours_router(db: Database) -> Router{
+    Router::new()
+        .route("/hours", get(get_hours).put(set_hours))
+        .route("/closures", post(add_closure))
+        .route("/closures/{id}", delete(remove_closure))
+        .route("/pause", post(pause).delete(resume))
+        .with_state(db)
+}
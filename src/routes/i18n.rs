@@ -0,0 +1,124 @@
+use axum::Json;
+use axum::http::{HeaderMap, StatusCode, header};
+use mongodb::bson::{Bson, Document};
+use serde::Deserialize;
+use std::collections::HashMap;
+use crate::routes::common::{error_response, get_string};
+
+// Languages content can be translated into; the first is what untagged `name`/`description` are written in.
+pub const SUPPORTED_LANGUAGES: [&str; 2] = ["zh-TW", "en"];
+pub const DEFAULT_LANGUAGE: &str = "zh-TW";
+
+// Translated fields for one language, keyed by language under `translations`.
+#[derive(Deserialize)]
+pub struct Translation {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+// Map a language tag onto a supported language: zh, zh-Hant, zh-TW -> zh-TW; en-US -> en.
+pub fn normalize_language(tag: &str) -> Option<&'static str>{
+    let tag = tag.trim().to_ascii_lowercase();
+    let primary = tag.split(['-', '_']).next().unwrap_or_default();
+    match primary {
+        "zh" if !tag.contains("hans") && !tag.contains("cn") => Some("zh-TW"),
+        "en" => Some("en"),
+        _ => None,
+    }
+}
+
+// Supported languages from Accept-Language, best first, ending with the default.
+pub fn preferred_languages(headers: &HeaderMap) -> Vec<&'static str>{
+    let header = headers.get(header::ACCEPT_LANGUAGE).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    let mut ranked: Vec<(f32, &'static str)> = header.split(',')
+        .filter_map(|part| {
+            let mut pieces = part.split(';');
+            let lang = normalize_language(pieces.next()?)?;
+            let quality = pieces
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (quality > 0.0).then_some((quality, lang))
+        })
+        .collect();
+    // stable, so equal weights keep header order
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut languages: Vec<&'static str> = Vec::new();
+    for (_, lang) in ranked {
+        if !languages.contains(&lang) {
+            languages.push(lang);
+        }
+    }
+    if !languages.contains(&DEFAULT_LANGUAGE) {
+        languages.push(DEFAULT_LANGUAGE);
+    }
+    languages
+}
+
+fn translated(doc: &Document, lang: &str, field: &str) -> Option<String>{
+    doc.get_document("translations").ok()
+        .and_then(|t| t.get_document(lang).ok())
+        .and_then(|t| get_string(t, field))
+        .filter(|s| !s.trim().is_empty())
+}
+
+// Fallback chain: requested languages in order, then the untagged field, then any translation at all.
+// The untagged field is written in the default language, so it stands in for a missing default translation.
+pub fn localized(doc: &Document, field: &str, languages: &[&str]) -> Option<String>{
+    languages.iter()
+        .find_map(|lang| {
+            let text = translated(doc, lang, field);
+            if *lang == DEFAULT_LANGUAGE {
+                text.or_else(|| get_string(doc, field).filter(|s| !s.trim().is_empty()))
+            } else {
+                text
+            }
+        })
+        .or_else(|| get_string(doc, field))
+        .or_else(|| SUPPORTED_LANGUAGES.iter().find_map(|lang| translated(doc, lang, field)))
+}
+
+type ByLanguage<'a> = Vec<(&'static str, &'a Translation)>;
+
+// Check request translations and key them by the canonical language code.
+fn validate_translations(translations: &HashMap<String, Translation>) -> Result<ByLanguage<'_>, (StatusCode, Json<Document>)>{
+    let mut out = Vec::new();
+    for (tag, translation) in translations {
+        let Some(lang) = SUPPORTED_LANGUAGES.iter().copied().find(|l| l.eq_ignore_ascii_case(tag)) else {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("unsupported language {}, expected one of {}", tag, SUPPORTED_LANGUAGES.join(", "))));
+        };
+        out.push((lang, translation));
+    }
+    Ok(out)
+}
+
+// `$set`/`$unset` entries for a translations patch; an empty string removes that translation.
+pub fn translation_updates(translations: &HashMap<String, Translation>, set: &mut Document, unset: &mut Document) -> Result<(), (StatusCode, Json<Document>)>{
+    for (lang, translation) in validate_translations(translations)? {
+        for (field, value) in [("name", &translation.name), ("description", &translation.description)] {
+            let Some(value) = value else { continue };
+            let key = format!("translations.{}.{}", lang, field);
+            if value.trim().is_empty() {
+                unset.insert(key, "");
+            } else {
+                set.insert(key, value.trim());
+            }
+        }
+    }
+    Ok(())
+}
+
+// Full `translations` document for a newly created record.
+pub fn translations_to_bson(translations: &HashMap<String, Translation>) -> Result<Bson, (StatusCode, Json<Document>)>{
+    let mut out = Document::new();
+    for (lang, translation) in validate_translations(translations)? {
+        let mut entry = Document::new();
+        for (field, value) in [("name", &translation.name), ("description", &translation.description)] {
+            if let Some(value) = value.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+                entry.insert(field, value);
+            }
+        }
+        out.insert(lang, entry);
+    }
+    Ok(Bson::Document(out))
+}
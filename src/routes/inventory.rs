@@ -0,0 +1,138 @@
+use axum::Json;
+use axum::http::StatusCode;
+use chrono::Timelike;
+use mongodb::{bson::{doc, Bson, Document}, options::ReturnDocument, Database};
+use crate::routes::common::{error_response, get_string, get_i64, menu_item_filter, now_datetime, taipei_now, menu_restaurant_id};
+
+// Items without a numeric `stock` are untracked and never sell out automatically.
+pub fn tracks_stock(menu_doc: &Document) -> bool{
+    int_field(menu_doc, "stock").is_some()
+}
+
+// Units left today, for items that track stock.
+pub fn stock_left(menu_doc: &Document) -> Option<i64>{
+    int_field(menu_doc, "stock")
+}
+
+fn int_field(doc: &Document, key: &str) -> Option<i64>{
+    match doc.get(key) {
+        Some(Bson::Int32(v)) => Some(*v as i64),
+        Some(Bson::Int64(v)) => Some(*v),
+        _ => None,
+    }
+}
+
+async fn notify_low_stock(db: &Database, menu_doc: &Document, remaining: i64){
+    let restaurant_id = menu_restaurant_id(menu_doc).unwrap_or_default();
+    let kind = if remaining <= 0 { "stock.sold_out" } else { "stock.low" };
+    let notification = doc! {
+        "id": mongodb::bson::oid::ObjectId::new().to_hex(),
+        "restaurantId": restaurant_id,
+        "type": kind,
+        "menuItemId": get_string(menu_doc, "id").or_else(|| menu_doc.get_object_id("_id").ok().map(|o| o.to_hex())),
+        "name": get_string(menu_doc, "name").unwrap_or_default(),
+        "stock": remaining,
+        "createdAt": now_datetime()
+    };
+    if let Err(e) = db.collection::<Document>("restaurant_notifications").insert_one(notification).await {
+        eprintln!("inventory.notify_low_stock error: {}", e);
+    }
+}
+
+// Atomically take `quantity` units; returns false when not enough stock is left.
+pub async fn reserve_stock(db: &Database, menu_item_id: &str, quantity: i64) -> Result<bool, (StatusCode, Json<Document>)>{
+    let collection = db.collection::<Document>("menu");
+    let filter = doc! { "$and": [ menu_item_filter(menu_item_id), { "stock": { "$gte": quantity } } ] };
+    let updated = collection.find_one_and_update(filter, doc! { "$inc": { "stock": -quantity } })
+        .return_document(ReturnDocument::After)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let Some(menu_doc) = updated else {
+        return Ok(false);
+    };
+
+    let remaining = int_field(&menu_doc, "stock").unwrap_or(0);
+    if remaining <= 0 {
+        let sold_out = doc! { "$and": [ menu_item_filter(menu_item_id), { "stock": { "$lte": 0 } } ] };
+        collection.update_one(sold_out, doc! { "$set": { "isAvailable": false, "soldOut": true } })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    }
+    let threshold = int_field(&menu_doc, "lowStockThreshold").unwrap_or(0);
+    // only alert when this order crossed the threshold, not on every later order
+    if remaining <= threshold && remaining + quantity > threshold {
+        notify_low_stock(db, &menu_doc, remaining).await;
+    }
+    Ok(true)
+}
+
+pub async fn release_stock(db: &Database, menu_item_id: &str, quantity: i64) -> mongodb::error::Result<()>{
+    let collection = db.collection::<Document>("menu");
+    // an item whose tracking was switched off since the order was placed stays untracked
+    let tracked = doc! { "$and": [ menu_item_filter(menu_item_id), { "stock": { "$type": "number" } } ] };
+    collection.update_one(tracked, doc! { "$inc": { "stock": quantity } }).await?;
+    // only re-enable items that were switched off by a sell-out, not by hand
+    let restocked = doc! { "$and": [ menu_item_filter(menu_item_id), { "soldOut": true, "stock": { "$gt": 0 } } ] };
+    collection.update_one(restocked, doc! { "$set": { "isAvailable": true }, "$unset": { "soldOut": "" } }).await?;
+    Ok(())
+}
+
+// Stock an order line holds: its own item, plus each tracked component when it is a bundle.
+pub fn line_stock_claims(line: &Document) -> Vec<(String, i64)>{
+    let mut claims = Vec::new();
+    if line.get_bool("stockTracked").unwrap_or(false) {
+        claims.push((get_string(line, "menuItemId").unwrap_or_default(), get_i64(line, "quantity").unwrap_or(1)));
+    }
+    if let Ok(components) = line.get_array("components") {
+        for component in components.iter().filter_map(Bson::as_document) {
+            if component.get_bool("stockTracked").unwrap_or(false) {
+                claims.push((get_string(component, "menuItemId").unwrap_or_default(), get_i64(component, "quantity").unwrap_or(1)));
+            }
+        }
+    }
+    claims
+}
+
+// Give back the stock held by a cancelled order. Safe to call more than once per order.
+pub async fn restore_order_stock(db: &Database, order_id: &str) -> mongodb::error::Result<()>{
+    let orders = db.collection::<Document>("orders");
+    let claimed = orders.find_one_and_update(
+        doc! { "id": order_id, "stockRestored": { "$ne": true } },
+        doc! { "$set": { "stockRestored": true } },
+    ).await?;
+    let Some(order_doc) = claimed else {
+        return Ok(());
+    };
+    if let Ok(items) = order_doc.get_array("items") {
+        for line in items.iter().filter_map(Bson::as_document) {
+            for (menu_item_id, quantity) in line_stock_claims(line) {
+                release_stock(db, &menu_item_id, quantity).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Refill every item with a `dailyStock` once per Taipei day, at its `stockResetHour` (default midnight).
+pub async fn reset_daily_stock(db: &Database) -> mongodb::error::Result<u64>{
+    let now = taipei_now();
+    let today = now.format("%Y-%m-%d").to_string();
+    let filter = doc! {
+        "dailyStock": { "$type": "number" },
+        "stockResetOn": { "$ne": &today },
+        "$or": [
+            { "stockResetHour": { "$exists": false } },
+            { "stockResetHour": { "$lte": now.hour() as i64 } }
+        ]
+    };
+    let pipeline = vec![
+        doc! { "$set": {
+            "stock": "$dailyStock",
+            "stockResetOn": &today,
+            "isAvailable": { "$cond": [ { "$eq": ["$soldOut", true] }, true, "$isAvailable" ] }
+        } },
+        doc! { "$unset": "soldOut" },
+    ];
+    let result = db.collection::<Document>("menu").update_many(filter, pipeline).await?;
+    Ok(result.modified_count)
+}
@@ -0,0 +1,284 @@
+#![allow(non_snake_case)]
+
+use axum::{Router, routing::{get, post, patch, put}, extract::{State, Path}, Json, http::HeaderMap};
+use mongodb::{bson::{doc, Bson, Document}, Database};
+use futures::stream::TryStreamExt;
+use serde::Deserialize;
+use axum::http::StatusCode;
+use crate::routes::common::{ApiResult, data_response, data_response_with_status, error_response, document_id, get_string, get_f64, get_i64, get_bool, require_role, now_datetime, iso_from_bson, id_filter, invalid, checked_text};
+use crate::routes::geo::{doc_latlng, geo_point};
+use crate::routes::profile::validate_latlng;
+
+// Campus drop-off spots live in `delivery_locations` as
+// `{ id, name, category, lat, lng, location (GeoJSON), instructions, enabled, sortOrder }`.
+// The older `NTOU_location` collection is folded in by `migrate_delivery_locations`.
+
+const DEFAULT_CATEGORY: &str = "default";
+const MAX_NAME_CHARS: usize = 100;
+const MAX_CATEGORY_CHARS: usize = 50;
+const MAX_INSTRUCTIONS_CHARS: usize = 300;
+
+#[derive(Deserialize)]
+struct LocationRequest {
+    name: String,
+    category: Option<String>,
+    lat: f64,
+    lng: f64,
+    instructions: Option<String>,
+    enabled: Option<bool>,
+    sortOrder: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct LocationPatch {
+    name: Option<String>,
+    category: Option<String>,
+    lat: Option<f64>,
+    lng: Option<f64>,
+    // empty string clears the instructions
+    instructions: Option<String>,
+    enabled: Option<bool>,
+    sortOrder: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct ReorderRequest {
+    // location ids in the order they should be listed; ids left out keep their current sortOrder
+    ids: Vec<String>,
+}
+
+fn location_view(location: &Document) -> Document{
+    let latlng = doc_latlng(location);
+    doc! {
+        "id": document_id(location),
+        "name": get_string(location, "name").unwrap_or_default(),
+        "category": get_string(location, "category").unwrap_or_else(|| DEFAULT_CATEGORY.to_string()),
+        "lat": latlng.map(|(lat, _)| lat),
+        "lng": latlng.map(|(_, lng)| lng),
+        "instructions": get_string(location, "instructions"),
+        "enabled": get_bool(location, "enabled").unwrap_or(true),
+        "sortOrder": get_i64(location, "sortOrder").unwrap_or(0),
+        "updatedAt": location.get("updatedAt").and_then(iso_from_bson)
+    }
+}
+
+// Locations in display order; disabled ones only when `include_disabled`.
+pub async fn load_locations(db: &Database, include_disabled: bool) -> mongodb::error::Result<Vec<Document>>{
+    let filter = if include_disabled { doc! {} } else { doc! { "enabled": { "$ne": false } } };
+    let locations: Vec<Document> = db.collection::<Document>("delivery_locations").find(filter)
+        .sort(doc! { "category": 1, "sortOrder": 1, "name": 1 })
+        .await?
+        .try_collect()
+        .await?;
+    Ok(locations.iter().map(location_view).collect())
+}
+
+// One enabled campus spot, as `{ id, name, lat, lng, instructions }`.
+pub async fn find_location(db: &Database, id: &str) -> mongodb::error::Result<Option<Document>>{
+    let location = db.collection::<Document>("delivery_locations").find_one(id_filter(id)).await?;
+    Ok(location.filter(|l| get_bool(l, "enabled").unwrap_or(true)).map(|l| location_view(&l)))
+}
+
+// The enabled campus spot called exactly `name`, in the same shape as `find_location`.
+pub async fn find_location_by_name(db: &Database, name: &str) -> mongodb::error::Result<Option<Document>>{
+    let location = db.collection::<Document>("delivery_locations")
+        .find_one(doc! { "name": name, "enabled": { "$ne": false } })
+        .await?;
+    Ok(location.map(|l| location_view(&l)))
+}
+
+// Fold `NTOU_location` into `delivery_locations` and fill in fields older entries lack.
+// Migrated entries keep their original id, so saved addresses that point at them still resolve.
+// Safe to run repeatedly; returns (migrated, skipped for bad coordinates).
+pub async fn migrate_delivery_locations(db: &Database) -> mongodb::error::Result<(u64, u64)>{
+    let locations = db.collection::<Document>("delivery_locations");
+    let now = now_datetime();
+    let mut migrated = 0;
+    let mut skipped = 0;
+
+    let mut legacy = db.collection::<Document>("NTOU_location").find(doc! {}).await?;
+    while let Some(old) = legacy.try_next().await? {
+        let Some(id) = document_id(&old) else { continue };
+        let point = old.get_document("location").ok();
+        let coords = point.and_then(|p| Some((get_f64(p, "lat")?, get_f64(p, "lng")?)));
+        let Some((lat, lng)) = coords.filter(|(lat, lng)| validate_latlng(*lat, *lng).is_ok()) else {
+            eprintln!("delivery location {} skipped: missing or invalid coordinates", id);
+            skipped += 1;
+            continue;
+        };
+        // insert-only: once migrated, the spot is edited through the admin API and restarts leave it alone
+        let result = locations.update_one(
+            doc! { "id": &id },
+            doc! {
+                "$setOnInsert": {
+                    "name": get_string(&old, "name").unwrap_or_default(),
+                    "lat": lat,
+                    "lng": lng,
+                    "location": geo_point(lat, lng),
+                    "category": DEFAULT_CATEGORY,
+                    "enabled": true,
+                    "sortOrder": 0i64,
+                    "source": "NTOU_location",
+                    "createdAt": now
+                }
+            },
+        )
+            .upsert(true)
+            .await?;
+        if result.upserted_id.is_some() {
+            migrated += 1;
+        }
+    }
+
+    // entries added straight into MongoDB before this collection had a fixed shape
+    let mut existing = locations.find(doc! { "$or": [
+        { "id": { "$exists": false } },
+        { "enabled": { "$exists": false } },
+        { "category": { "$exists": false } }
+    ] }).await?;
+    while let Some(location) = existing.try_next().await? {
+        let Some(oid) = location.get("_id").cloned() else { continue };
+        let mut set = Document::new();
+        if get_string(&location, "id").is_none() {
+            set.insert("id", document_id(&location));
+        }
+        if location.get("enabled").is_none() {
+            set.insert("enabled", true);
+        }
+        if location.get("category").is_none() {
+            set.insert("category", DEFAULT_CATEGORY);
+        }
+        locations.update_one(doc! { "_id": oid }, doc! { "$set": set }).await?;
+    }
+    Ok((migrated, skipped))
+}
+
+// GET /admin/delivery-locations
+async fn admin_list(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    let locations = load_locations(&db, true)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(data_response(Bson::Array(locations.into_iter().map(Bson::Document).collect())))
+}
+
+// POST /admin/delivery-locations
+async fn create_location(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<LocationRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["admin"])?;
+    let name = checked_text("name", &payload.name, MAX_NAME_CHARS)?;
+    let category = match payload.category.as_deref() {
+        Some(category) => checked_text("category", category, MAX_CATEGORY_CHARS)?,
+        None => DEFAULT_CATEGORY.to_string(),
+    };
+    validate_latlng(payload.lat, payload.lng)?;
+    let instructions = match payload.instructions.as_deref().map(str::trim).filter(|i| !i.is_empty()) {
+        Some(instructions) => Some(checked_text("instructions", instructions, MAX_INSTRUCTIONS_CHARS)?),
+        None => None,
+    };
+    let now = now_datetime();
+    let location = doc! {
+        "id": mongodb::bson::oid::ObjectId::new().to_hex(),
+        "name": name,
+        "category": category,
+        "lat": payload.lat,
+        "lng": payload.lng,
+        "location": geo_point(payload.lat, payload.lng),
+        "instructions": instructions,
+        "enabled": payload.enabled.unwrap_or(true),
+        "sortOrder": payload.sortOrder.unwrap_or(0),
+        "createdAt": now,
+        "updatedAt": now,
+        "updatedBy": &claims.sub
+    };
+    db.collection::<Document>("delivery_locations").insert_one(&location)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(data_response_with_status(StatusCode::CREATED, Bson::Document(location_view(&location))))
+}
+
+// PATCH /admin/delivery-locations/{id}
+// Disabling hides a spot from customers; saved addresses that point at it keep their stored copy.
+async fn update_location(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<LocationPatch>) -> ApiResult{
+    let claims = require_role(&headers, &["admin"])?;
+    let mut set = Document::new();
+    if let Some(name) = payload.name.as_deref() {
+        set.insert("name", checked_text("name", name, MAX_NAME_CHARS)?);
+    }
+    if let Some(category) = payload.category.as_deref() {
+        set.insert("category", checked_text("category", category, MAX_CATEGORY_CHARS)?);
+    }
+    match (payload.lat, payload.lng) {
+        (None, None) => {}
+        (Some(lat), Some(lng)) => {
+            validate_latlng(lat, lng)?;
+            set.insert("lat", lat);
+            set.insert("lng", lng);
+            set.insert("location", geo_point(lat, lng));
+        }
+        _ => return Err(invalid("lat and lng must be given together")),
+    }
+    if let Some(instructions) = payload.instructions.as_deref().map(str::trim) {
+        if instructions.is_empty() {
+            set.insert("instructions", Bson::Null);
+        } else {
+            set.insert("instructions", checked_text("instructions", instructions, MAX_INSTRUCTIONS_CHARS)?);
+        }
+    }
+    if let Some(enabled) = payload.enabled {
+        set.insert("enabled", enabled);
+    }
+    if let Some(sort_order) = payload.sortOrder {
+        set.insert("sortOrder", sort_order);
+    }
+    if set.is_empty() {
+        return Err(invalid("No fields to update"));
+    }
+    set.insert("updatedAt", now_datetime());
+    set.insert("updatedBy", &claims.sub);
+    let updated = db.collection::<Document>("delivery_locations")
+        .find_one_and_update(id_filter(&id), doc! { "$set": set })
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "location.not_found", "Delivery location not found"))?;
+    Ok(data_response(Bson::Document(location_view(&updated))))
+}
+
+// PUT /admin/delivery-locations/order
+async fn reorder_locations(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<ReorderRequest>) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    if payload.ids.is_empty() {
+        return Err(invalid("ids must list at least one location"));
+    }
+    let collection = db.collection::<Document>("delivery_locations");
+    for (position, id) in payload.ids.iter().enumerate() {
+        let result = collection.update_one(id_filter(id), doc! { "$set": { "sortOrder": position as i64, "updatedAt": now_datetime() } })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        if result.matched_count == 0 {
+            return Err(error_response(StatusCode::NOT_FOUND, "location.not_found", &format!("Delivery location {} not found", id)));
+        }
+    }
+    let locations = load_locations(&db, true)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(data_response(Bson::Array(locations.into_iter().map(Bson::Document).collect())))
+}
+
+// POST /admin/delivery-locations/migrate
+async fn run_migration(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    let (migrated, skipped) = migrate_delivery_locations(&db)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(data_response(Bson::Document(doc! { "migrated": migrated as i64, "skipped": skipped as i64 })))
+}
+
+pub fn locations_admin_router(db: Database) -> Router{
+    Router::new()
+        .route("/delivery-locations", get(admin_list).post(create_location))
+        .route("/delivery-locations/order", put(reorder_locations))
+        .route("/delivery-locations/migrate", post(run_migration))
+        .route("/delivery-locations/{id}", patch(update_location))
+        .with_state(db)
+}
@@ -1,78 +1,311 @@
-use axum::{Router, extract::{State, Path}, routing::get, response::IntoResponse, Json};
+#![allow(non_snake_case)]
+
+use axum::{Router, extract::{State, Path, Query}, routing::get, response::IntoResponse, Json, http::HeaderMap};
 use mongodb::{bson::{doc, Bson, Document}, Database};
 use futures::stream::TryStreamExt;
 use axum::http::StatusCode;
-use crate::routes::common::{ApiResult, error_response, get_string, get_bool, get_i64, get_array, document_id};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use crate::routes::common::{escape_regex, ApiResult, data_response, error_response, get_string, get_bool, get_i64, get_array, document_id, taipei_now, now_datetime, iso_from_bson, menu_item_filter, menu_restaurant_id};
+use crate::routes::bundles::{is_bundle, bundle_available, describe_components};
+use crate::routes::i18n::{SUPPORTED_LANGUAGES, localized, preferred_languages};
+use crate::routes::favorites::favorite_ids;
+use crate::routes::onboarding::shop_is_listed;
+use crate::routes::pricing::{base_price, resolve_price};
+use crate::routes::schedule::{item_in_window, load_categories};
 
-async fn get_menu(Path(shop_id): Path<String>, State(db): State<Database>) -> ApiResult{
-    // Query the `menu` collection
-    let collection = db.collection::<Document>("menu");
+// cap on raw matches pulled from the menu collection per search
+const MAX_SEARCH_MATCHES: i64 = 1000;
 
-    // filter: match the shop_id as a string
-    let filter = doc! {
-        "$or": [
-            { "shop_id": shop_id.clone() },
-            { "restaurantId": shop_id.clone() },
-            { "restaurant_id": shop_id.clone() }
-        ]
-    };
+#[derive(Deserialize)]
+struct MenuFilterQuery {
+    // comma separated, e.g. peanut,shellfish
+    excludeAllergens: Option<String>,
+    // comma separated; an item has to carry every one
+    tags: Option<String>,
+    minPrice: Option<i64>,
+    maxPrice: Option<i64>,
+    availableOnly: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct DishSearchQuery {
+    q: Option<String>,
+    limit: Option<i64>,
+    #[serde(flatten)]
+    filter: MenuFilterQuery,
+}
 
-    println!("menu.get_menu - using filter: {:?}", filter);
+fn split_list(value: Option<&str>) -> Vec<String>{
+    value.unwrap_or_default()
+        .split(',')
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn lowercase_strings(item: &Document, key: &str) -> Vec<String>{
+    get_array(item, key).unwrap_or_default()
+        .iter()
+        .filter_map(|v| v.as_str().map(|s| s.trim().to_lowercase()))
+        .collect()
+}
+
+impl MenuFilterQuery {
+    fn validate(&self) -> Result<(), (StatusCode, Json<Document>)>{
+        if let (Some(min), Some(max)) = (self.minPrice, self.maxPrice)
+            && min > max {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "minPrice must not exceed maxPrice"));
+        }
+        Ok(())
+    }
 
-    let mut cursor = collection.find(filter)
+    // Applied to the customer view, so price is the current price and availability includes serving windows.
+    fn matches(&self, item: &Document) -> bool{
+        let excluded = split_list(self.excludeAllergens.as_deref());
+        let allergens = lowercase_strings(item, "allergens");
+        if allergens.iter().any(|a| excluded.contains(a)) {
+            return false;
+        }
+        let tags = lowercase_strings(item, "tags");
+        if !split_list(self.tags.as_deref()).iter().all(|t| tags.contains(t)) {
+            return false;
+        }
+        let price = get_i64(item, "price").unwrap_or(0);
+        if self.minPrice.is_some_and(|min| price < min) || self.maxPrice.is_some_and(|max| price > max) {
+            return false;
+        }
+        !self.availableOnly.unwrap_or(false) || get_bool(item, "isAvailable").unwrap_or(false)
+    }
+}
+
+fn restaurant_filter(shop_id: &str) -> Document{
+    doc! {
+        "$or": [
+            { "shop_id": shop_id },
+            { "restaurantId": shop_id },
+            { "restaurant_id": shop_id }
+        ],
+        "archived": { "$ne": true }
+    }
+}
+
+async fn load_shop_menu(db: &Database, shop_id: &str) -> Result<Vec<Document>, (StatusCode, Json<Document>)>{
+    db.collection::<Document>("menu").find(restaurant_filter(shop_id))
         .await
-        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Find error: {}", e)))?;
-    let mut results: Vec<Bson> = Vec::new();
-    while let Some(doc) = cursor.try_next()
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Find error: {}", e)))?
+        .try_collect()
         .await
-        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Cursor error: {}", e)))? {
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Cursor error: {}", e)))
+}
+
+// Customer view of one restaurant's menu, in the shape `get_menu` returns.
+async fn menu_views(db: &Database, shop_id: &str, docs: &[Document], languages: &[&str]) -> Vec<Document>{
+    let categories = load_categories(db, shop_id).await;
+    let now = taipei_now();
+    // bundles look their components up among the shop's own items
+    let menu_by_id: HashMap<String, Document> = docs.iter()
+        .filter_map(|d| document_id(d).map(|id| (id, d.clone())))
+        .collect();
+    let mut results: Vec<Document> = Vec::new();
+    for doc in docs.iter() {
         let mut item = Document::new();
-        let id = document_id(&doc);
-        let name = get_string(&doc, "name");
-        let description = get_string(&doc, "description");
-        let price = match doc.get("price") {
-            Some(Bson::Int32(v)) => *v as i64,
-            Some(Bson::Int64(v)) => *v,
-            Some(Bson::Double(v)) => v.round() as i64,
-            Some(Bson::String(s)) => s.parse::<i64>().unwrap_or(0),
-            _ => 0,
-        };
-        let sizes = get_array(&doc, "sizes").or_else(|| get_array(&doc, "size"));
-        let spiciness = get_array(&doc, "spicinessOptions");
-        let image = get_string(&doc, "imageUrl");
-        let is_available = get_bool(&doc, "isAvailable").unwrap_or(true);
-        let sort_order = get_i64(&doc, "sortOrder").unwrap_or(0);
-        let allergens = get_array(&doc, "allergens").unwrap_or_default();
-        let tags = get_array(&doc, "tags").unwrap_or_default();
+        let id = document_id(doc);
+        let name = localized(doc, "name", languages);
+        let description = localized(doc, "description", languages);
+        // scheduled changes and promotions apply from the moment they start
+        let (price, promo) = resolve_price(doc, now_datetime());
+        let sizes = get_array(doc, "sizes").or_else(|| get_array(doc, "size"));
+        let spiciness = get_array(doc, "spicinessOptions");
+        let image = get_string(doc, "imageUrl");
+        let thumbnail = get_string(doc, "thumbnailUrl");
+        let category = get_string(doc, "category");
+        let category_doc = category.as_ref().and_then(|c| categories.get(c));
+        // outside its serving window an item shows as unavailable, whatever the manual toggle says
+        let is_available = get_bool(doc, "isAvailable").unwrap_or(true) && item_in_window(doc, category_doc, &now);
+        let bundle = is_bundle(doc);
+        let is_available = is_available && (!bundle || bundle_available(doc, &menu_by_id, &categories, &now));
+        let availability = get_array(doc, "availability")
+            .filter(|w| !w.is_empty())
+            .or_else(|| category_doc.and_then(|c| get_array(c, "availability")))
+            .unwrap_or_default();
+        let sort_order = get_i64(doc, "sortOrder").unwrap_or(0);
+        let allergens = get_array(doc, "allergens").unwrap_or_default();
+        let tags = get_array(doc, "tags").unwrap_or_default();
+        let stock = get_i64(doc, "stock");
 
         item.insert("id", match id { Some(v) => Bson::String(v), None => Bson::Null });
         item.insert("name", match name { Some(v) => Bson::String(v), None => Bson::Null });
         item.insert("description", match description { Some(v) => Bson::String(v), None => Bson::Null });
         item.insert("price", Bson::Int64(price));
+        item.insert("basePrice", Bson::Int64(base_price(doc)));
+        item.insert("promoEndsAt", match promo.and_then(|p| p.get("endsAt").and_then(iso_from_bson)) { Some(v) => Bson::String(v), None => Bson::Null });
         item.insert("sizes", match sizes { Some(v) => Bson::Array(v), None => Bson::Null });
         item.insert("spicinessOptions", match spiciness { Some(v) => Bson::Array(v), None => Bson::Null });
         item.insert("imageUrl", match image { Some(v) => Bson::String(v), None => Bson::Null });
+        item.insert("thumbnailUrl", match thumbnail { Some(v) => Bson::String(v), None => Bson::Null });
         item.insert("isAvailable", Bson::Boolean(is_available));
         item.insert("sortOrder", Bson::Int64(sort_order));
         item.insert("allergens", Bson::Array(allergens));
         item.insert("tags", Bson::Array(tags));
-        results.push(Bson::Document(item));
+        item.insert("stock", match stock { Some(v) => Bson::Int64(v), None => Bson::Null });
+        item.insert("category", match category { Some(v) => Bson::String(v), None => Bson::Null });
+        item.insert("availability", Bson::Array(availability));
+        item.insert("type", if bundle { "bundle" } else { "item" });
+        if bundle {
+            item.insert("components", Bson::Array(describe_components(doc, &menu_by_id, languages)));
+        }
+        results.push(item);
     }
+    results
+}
+
+// GET /restaurants/{shop_id}/menu?excludeAllergens=&tags=&minPrice=&maxPrice=&availableOnly=
+async fn get_menu(Path(shop_id): Path<String>, State(db): State<Database>, headers: HeaderMap, Query(query): Query<MenuFilterQuery>) -> ApiResult{
+    query.validate()?;
+    let languages = preferred_languages(&headers);
+    let shop = db.collection::<Document>("shops").find_one(doc! { "id": &shop_id })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    if shop.is_some_and(|s| !shop_is_listed(&s)) {
+        return Err(error_response(StatusCode::NOT_FOUND, "restaurant.not_found", "Restaurant not found"));
+    }
+    let docs = load_shop_menu(&db, &shop_id).await?;
+    let favorites = favorite_ids(&db, &headers, "item").await?;
+    let results: Vec<Bson> = menu_views(&db, &shop_id, &docs, &languages).await
+        .into_iter()
+        .filter(|item| query.matches(item))
+        .map(|mut item| {
+            if let Some(favorites) = &favorites {
+                let saved = get_string(&item, "id").is_some_and(|id| favorites.contains(&id));
+                item.insert("isFavorite", saved);
+            }
+            Bson::Document(item)
+        })
+        .collect();
 
     println!("menu.get_menu - found {} documents", results.len());
 
     // Return both the primary shape (`data.items`) and a lenient `items` for clients that use the fallback.
     let items = Bson::Array(results);
     let body = doc! {
-        "data": { "items": items.clone() },
+        "data": { "items": items.clone(), "language": languages[0] },
         "items": items.clone(),
     };
 
     Ok(Json(body).into_response())
 }
 
+// GET /restaurants/dishes?q=&excludeAllergens=&tags=&minPrice=&maxPrice=&availableOnly=&limit=
+// Matching dishes across every restaurant, grouped by restaurant.
+async fn search_dishes(State(db): State<Database>, headers: HeaderMap, Query(query): Query<DishSearchQuery>) -> ApiResult{
+    query.filter.validate()?;
+    let languages = preferred_languages(&headers);
+    let limit = query.limit.unwrap_or(100).clamp(1, 500) as usize;
+    let Some(keyword) = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()).map(|q| q.to_lowercase()) else {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "q is required"));
+    };
+
+    let pattern = doc! { "$regex": escape_regex(&keyword), "$options": "i" };
+    let mut fields: Vec<Bson> = vec![
+        Bson::Document(doc! { "name": pattern.clone() }),
+        Bson::Document(doc! { "description": pattern.clone() }),
+        Bson::Document(doc! { "tags": pattern.clone() }),
+    ];
+    for lang in SUPPORTED_LANGUAGES {
+        fields.push(Bson::Document(doc! { format!("translations.{}.name", lang): pattern.clone() }));
+    }
+    let filter = doc! { "archived": { "$ne": true }, "$or": fields };
+    let matched: Vec<Document> = db.collection::<Document>("menu").find(filter)
+        .limit(MAX_SEARCH_MATCHES)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Find error: {}", e)))?
+        .try_collect()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Cursor error: {}", e)))?;
+    let matched_ids: HashSet<String> = matched.iter().filter_map(document_id).collect();
+
+    // bundles need their components to work out availability; fetch the ones the search didn't match in one go
+    let missing_components: Vec<Bson> = matched.iter()
+        .filter(|doc| is_bundle(doc))
+        .flat_map(|doc| get_array(doc, "components").unwrap_or_default())
+        .filter_map(|c| c.as_document().and_then(|c| get_string(c, "menuItemId")))
+        .filter(|id| !matched_ids.contains(id))
+        .collect::<HashSet<String>>()
+        .into_iter()
+        .map(|id| Bson::Document(menu_item_filter(&id)))
+        .collect();
+    let components: Vec<Document> = if missing_components.is_empty() {
+        Vec::new()
+    } else {
+        db.collection::<Document>("menu").find(doc! { "$or": missing_components })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Find error: {}", e)))?
+            .try_collect()
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Cursor error: {}", e)))?
+    };
+
+    // keep the restaurants in first-match order
+    let mut restaurant_ids: Vec<String> = Vec::new();
+    let mut docs_by_restaurant: HashMap<String, Vec<Document>> = HashMap::new();
+    for doc in matched.into_iter().chain(components) {
+        let Some(rest_id) = menu_restaurant_id(&doc) else { continue };
+        if !docs_by_restaurant.contains_key(&rest_id) {
+            restaurant_ids.push(rest_id.clone());
+        }
+        docs_by_restaurant.entry(rest_id).or_default().push(doc);
+    }
+
+    let shops: HashMap<String, Document> = db.collection::<Document>("shops").find(doc! { "id": { "$in": &restaurant_ids } })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Find error: {}", e)))?
+        .try_collect::<Vec<Document>>()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Cursor error: {}", e)))?
+        .into_iter()
+        .filter_map(|shop| document_id(&shop).map(|id| (id, shop)))
+        .collect();
+
+    let mut groups: Vec<Bson> = Vec::new();
+    let mut total = 0usize;
+    for rest_id in restaurant_ids {
+        if total >= limit {
+            break;
+        }
+        if shops.get(&rest_id).is_some_and(|s| !shop_is_listed(s)) {
+            continue;
+        }
+        let docs = docs_by_restaurant.remove(&rest_id).unwrap_or_default();
+        let items: Vec<Bson> = menu_views(&db, &rest_id, &docs, &languages).await
+            .into_iter()
+            .filter(|item| get_string(item, "id").is_some_and(|id| matched_ids.contains(&id)))
+            .filter(|item| query.filter.matches(item))
+            .take(limit - total)
+            .map(Bson::Document)
+            .collect();
+        if items.is_empty() {
+            continue;
+        }
+        total += items.len();
+        let shop = shops.get(&rest_id);
+        groups.push(Bson::Document(doc! {
+            "restaurant": {
+                "id": &rest_id,
+                "name": shop.and_then(|s| localized(s, "name", &languages)),
+                "imageUrl": shop.and_then(|s| get_string(s, "imageUrl")),
+                "thumbnailUrl": shop.and_then(|s| get_string(s, "thumbnailUrl"))
+            },
+            "items": items
+        }));
+    }
+
+    Ok(data_response(Bson::Array(groups)))
+}
+
 pub fn menu_router(db: Database) -> Router{
     Router::new()
+        .route("/dishes", get(search_dishes))
         .route("/{shop_id}/menu", get(get_menu))
         .with_state(db)
 }
@@ -0,0 +1,462 @@
+#![allow(non_snake_case)]
+
+use axum::{Router, routing::{get, post}, extract::{State, Query}, Json, http::{HeaderMap, header}, response::IntoResponse};
+use mongodb::{bson::{doc, Bson, Document}, Database};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use futures::stream::TryStreamExt;
+use axum::http::StatusCode;
+use std::collections::{HashMap, HashSet};
+use crate::routes::common::{ApiResult, data_response, error_response, document_id, get_string, now_datetime, require_role, requested_restaurant_id};
+use crate::routes::favorites::remove_item_favorites;
+use crate::routes::pricing::record_price_change;
+use crate::routes::restaurant::map_menu_item;
+
+// Columns shared by the CSV and JSON formats. List columns are "|"-separated in CSV.
+const COLUMNS: [&str; 15] = [
+    "id", "name", "description", "price", "sizes", "spicinessOptions", "imageUrl", "isAvailable",
+    "sortOrder", "allergens", "tags", "category", "stock", "dailyStock", "lowStockThreshold",
+];
+const LIST_COLUMNS: [&str; 4] = ["sizes", "spicinessOptions", "allergens", "tags"];
+const INT_COLUMNS: [&str; 5] = ["price", "sortOrder", "stock", "dailyStock", "lowStockThreshold"];
+
+type RawRows = Vec<Map<String, Value>>;
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    format: Option<String>,
+    restaurantId: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    format: Option<String>,
+    dryRun: Option<bool>,
+    // "merge" (default) keeps items missing from the file; "replace" archives them
+    mode: Option<String>,
+    restaurantId: Option<String>,
+}
+
+fn wants_csv(format: Option<&str>, headers: &HeaderMap) -> bool{
+    match format {
+        Some(f) => f.eq_ignore_ascii_case("csv"),
+        None => headers.get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("text/csv")),
+    }
+}
+
+async fn load_menu(db: &Database, restaurant_id: &str) -> Result<Vec<Document>, (StatusCode, Json<Document>)>{
+    let collection = db.collection::<Document>("menu");
+    let filter = doc! {
+        "$or": [ { "shop_id": restaurant_id }, { "restaurantId": restaurant_id }, { "restaurant_id": restaurant_id } ],
+        "archived": { "$ne": true }
+    };
+    let cursor = collection.find(filter)
+        .sort(doc! { "sortOrder": 1 })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    cursor.try_collect()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))
+}
+
+fn csv_cell(item: &Document, column: &str) -> String{
+    match item.get(column) {
+        Some(Bson::String(s)) => s.clone(),
+        Some(Bson::Int64(v)) => v.to_string(),
+        Some(Bson::Int32(v)) => v.to_string(),
+        Some(Bson::Boolean(b)) => b.to_string(),
+        Some(Bson::Array(list)) => list.iter().filter_map(Bson::as_str).collect::<Vec<_>>().join("|"),
+        _ => String::new(),
+    }
+}
+
+// GET /restaurant/menu/export?format=csv|json
+async fn export_menu(State(db): State<Database>, headers: HeaderMap, Query(query): Query<ExportQuery>) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let restaurant_id = requested_restaurant_id(&claims, query.restaurantId.as_deref())?;
+    // keep to the import columns so an export can be edited and imported back as is
+    let items: Vec<Document> = load_menu(&db, &restaurant_id).await?.iter()
+        .map(|d| map_menu_item(d).into_iter().filter(|(k, _)| COLUMNS.contains(&k.as_str())).collect())
+        .collect();
+
+    if !query.format.as_deref().is_some_and(|f| f.eq_ignore_ascii_case("csv")) {
+        return Ok(data_response(Bson::Array(items.into_iter().map(Bson::Document).collect())));
+    }
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let write_err = |e: csv::Error| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string());
+    writer.write_record(COLUMNS).map_err(write_err)?;
+    for item in &items {
+        writer.write_record(COLUMNS.iter().map(|c| csv_cell(item, c))).map_err(write_err)?;
+    }
+    let body = writer.into_inner()
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"menu.csv\""),
+        ],
+        body,
+    ).into_response())
+}
+
+fn parse_csv_rows(body: &str) -> Result<RawRows, (StatusCode, Json<Document>)>{
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(body.as_bytes());
+    let columns = reader.headers()
+        .map_err(|e| error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("invalid CSV header: {}", e)))?
+        .clone();
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("invalid CSV: {}", e)))?;
+        let mut row = Map::new();
+        for (column, cell) in columns.iter().zip(record.iter()) {
+            // empty cells mean "leave as is"
+            if cell.is_empty() {
+                continue;
+            }
+            let value = if LIST_COLUMNS.contains(&column) {
+                Value::Array(cell.split('|').map(str::trim).filter(|s| !s.is_empty()).map(|s| Value::String(s.to_string())).collect())
+            } else {
+                Value::String(cell.to_string())
+            };
+            row.insert(column.to_string(), value);
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+fn parse_json_rows(body: &str) -> Result<RawRows, (StatusCode, Json<Document>)>{
+    let value: Value = serde_json::from_str(body)
+        .map_err(|e| error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("invalid JSON: {}", e)))?;
+    // accept a bare array, `{ "items": [...] }`, or the export's `{ "data": [...] }`
+    let list = match value {
+        Value::Array(list) => list,
+        Value::Object(mut obj) => match obj.remove("items").or_else(|| obj.remove("data")) {
+            Some(Value::Array(list)) => list,
+            _ => return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "expected an items array")),
+        },
+        _ => return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "expected an items array")),
+    };
+    Ok(list.into_iter().map(|v| match v {
+        Value::Object(obj) => obj,
+        _ => Map::new(),
+    }).collect())
+}
+
+// Turn one raw row into menu fields, collecting every problem instead of stopping at the first.
+fn validate_row(row: &Map<String, Value>) -> (Document, Vec<String>){
+    let mut fields = Document::new();
+    let mut errors = Vec::new();
+    for (key, value) in row {
+        let key = key.as_str();
+        if key == "id" || value.is_null() {
+            continue;
+        }
+        if !COLUMNS.contains(&key) {
+            errors.push(format!("unknown column {}", key));
+        } else if INT_COLUMNS.contains(&key) {
+            let parsed = match value {
+                Value::Number(n) => n.as_i64(),
+                Value::String(s) => s.parse::<i64>().ok(),
+                _ => None,
+            };
+            match parsed {
+                Some(v) if v >= 0 => { fields.insert(key, v); }
+                _ => errors.push(format!("{} must be a non-negative integer", key)),
+            }
+        } else if LIST_COLUMNS.contains(&key) {
+            match value {
+                Value::Array(list) if list.iter().all(Value::is_string) => {
+                    fields.insert(key, list.iter().filter_map(Value::as_str).collect::<Vec<_>>());
+                }
+                _ => errors.push(format!("{} must be a list of strings", key)),
+            }
+        } else if key == "isAvailable" {
+            let parsed = match value {
+                Value::Bool(b) => Some(*b),
+                Value::String(s) => match s.to_ascii_lowercase().as_str() {
+                    "true" | "yes" | "1" => Some(true),
+                    "false" | "no" | "0" => Some(false),
+                    _ => None,
+                },
+                _ => None,
+            };
+            match parsed {
+                Some(b) => { fields.insert(key, b); }
+                None => errors.push("isAvailable must be true or false".to_string()),
+            }
+        } else {
+            match value {
+                Value::String(s) => { fields.insert(key, s.clone()); }
+                _ => errors.push(format!("{} must be a string", key)),
+            }
+        }
+    }
+    if get_string(&fields, "name").is_some_and(|n| n.trim().is_empty()) {
+        errors.push("name must not be empty".to_string());
+    }
+    (fields, errors)
+}
+
+fn row_id(row: &Map<String, Value>) -> Option<String>{
+    match row.get("id") {
+        Some(Value::String(s)) if !s.is_empty() => Some(s.clone()),
+        _ => None,
+    }
+}
+
+// The update for an imported row, with the same sell-out handling as PATCH /restaurant/menu/{id}:
+// a zero stock sells the item out, and restocking a sold-out item puts it back on sale,
+// unless the row sets isAvailable itself.
+fn update_for(current: &Document, mut changed: Document) -> Document{
+    let mut unset = Document::new();
+    if let (Ok(stock), false) = (changed.get_i64("stock"), changed.contains_key("isAvailable")) {
+        if stock > 0 {
+            unset.insert("soldOut", "");
+            if current.get_bool("soldOut").unwrap_or(false) {
+                changed.insert("isAvailable", true);
+            }
+        } else {
+            changed.insert("isAvailable", false);
+            changed.insert("soldOut", true);
+        }
+    }
+    let mut update = doc! { "$set": changed };
+    if !unset.is_empty() {
+        update.insert("$unset", unset);
+    }
+    update
+}
+
+// POST /restaurant/menu/import?format=csv|json&dryRun=true&mode=merge|replace
+async fn import_menu(State(db): State<Database>, headers: HeaderMap, Query(query): Query<ImportQuery>, body: String) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let restaurant_id = requested_restaurant_id(&claims, query.restaurantId.as_deref())?;
+    let replace = match query.mode.as_deref() {
+        None | Some("merge") => false,
+        Some("replace") => true,
+        Some(_) => return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "mode must be merge or replace")),
+    };
+    let dry_run = query.dryRun.unwrap_or(false);
+    let rows = if wants_csv(query.format.as_deref(), &headers) { parse_csv_rows(&body)? } else { parse_json_rows(&body)? };
+    if rows.is_empty() {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "import has no rows"));
+    }
+
+    let existing = load_menu(&db, &restaurant_id).await?;
+    let by_id: HashMap<String, &Document> = existing.iter().filter_map(|d| document_id(d).map(|id| (id, d))).collect();
+    let by_name: HashMap<String, &Document> = existing.iter().filter_map(|d| get_string(d, "name").map(|n| (n, d))).collect();
+
+    let mut creates: Vec<Document> = Vec::new();
+    let mut updates: Vec<(Bson, Document)> = Vec::new();
+    let mut price_changes: Vec<(Document, i64)> = Vec::new();
+    let mut preview_creates: Vec<Bson> = Vec::new();
+    let mut preview_updates: Vec<Bson> = Vec::new();
+    let mut row_errors: Vec<Bson> = Vec::new();
+    let mut unchanged = 0i64;
+    let mut touched: HashSet<String> = HashSet::new();
+    let mut new_names: HashSet<String> = HashSet::new();
+
+    for (index, row) in rows.iter().enumerate() {
+        let row_number = index as i64 + 1;
+        let (fields, mut errors) = validate_row(row);
+        let name = get_string(&fields, "name").unwrap_or_default();
+        // match on id first, then on name, so a file without ids still updates in place
+        let target = match row_id(row) {
+            Some(id) => {
+                let found = by_id.get(&id).copied();
+                if found.is_none() {
+                    errors.push(format!("unknown menu item id {}", id));
+                }
+                found
+            }
+            None => by_name.get(&name).copied(),
+        };
+        let target_id = target.and_then(document_id);
+        if target.is_none() && name.trim().is_empty() {
+            errors.push("name is required for new items".to_string());
+        }
+        if let Some(id) = target_id.as_ref() {
+            if !touched.insert(id.clone()) {
+                errors.push("menu item appears more than once".to_string());
+            }
+        } else if !name.is_empty() && !new_names.insert(name.clone()) {
+            errors.push("menu item appears more than once".to_string());
+        }
+
+        if !errors.is_empty() {
+            row_errors.push(Bson::Document(doc! { "row": row_number, "name": &name, "errors": errors }));
+            continue;
+        }
+
+        match (target, target_id) {
+            (Some(current), Some(id)) => {
+                let changed: Document = fields.iter()
+                    .filter(|(k, v)| current.get(k.as_str()) != Some(*v))
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                if changed.is_empty() {
+                    unchanged += 1;
+                    continue;
+                }
+                preview_updates.push(Bson::Document(doc! {
+                    "row": row_number,
+                    "id": &id,
+                    "name": get_string(&changed, "name").or_else(|| get_string(current, "name")).unwrap_or_default(),
+                    "fields": changed.keys().cloned().collect::<Vec<_>>()
+                }));
+                if let Ok(price) = changed.get_i64("price") {
+                    price_changes.push(((*current).clone(), price));
+                }
+                updates.push((current.get("_id").cloned().unwrap_or(Bson::Null), update_for(current, changed)));
+            }
+            _ => {
+                let id = mongodb::bson::oid::ObjectId::new().to_hex();
+                let mut menu_doc = doc! {
+                    "id": &id,
+                    "restaurantId": &restaurant_id,
+                    "isAvailable": true,
+                    "sortOrder": 0i64
+                };
+                menu_doc.extend(fields);
+                // a daily stock with no explicit starting count starts full
+                if !menu_doc.contains_key("stock")
+                    && let Some(daily) = menu_doc.get("dailyStock").cloned() {
+                    menu_doc.insert("stock", daily);
+                }
+                preview_creates.push(Bson::Document(doc! { "row": row_number, "id": &id, "name": &name }));
+                creates.push(menu_doc);
+            }
+        }
+    }
+
+    let deletes: Vec<&Document> = if replace {
+        existing.iter().filter(|d| document_id(d).is_none_or(|id| !touched.contains(&id))).collect()
+    } else {
+        Vec::new()
+    };
+    let preview_deletes: Vec<Bson> = deletes.iter().map(|d| Bson::Document(doc! {
+        "id": document_id(d).unwrap_or_default(),
+        "name": get_string(d, "name").unwrap_or_default()
+    })).collect();
+
+    let summary = doc! {
+        "dryRun": dry_run,
+        "valid": row_errors.is_empty(),
+        "creates": preview_creates,
+        "updates": preview_updates,
+        "deletes": preview_deletes,
+        "unchanged": unchanged,
+        "errors": row_errors.clone()
+    };
+    if dry_run {
+        return Ok(data_response(Bson::Document(summary)));
+    }
+    if !row_errors.is_empty() {
+        let (status, Json(mut body)) = error_response(StatusCode::BAD_REQUEST, "validation.failed", "import has invalid rows");
+        body.insert("errors", row_errors);
+        return Err((status, Json(body)));
+    }
+
+    // all-or-nothing: apply every change inside one transaction
+    let collection = db.collection::<Document>("menu");
+    let tx_err = |e: mongodb::error::Error| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string());
+    let mut session = db.client().start_session().await.map_err(tx_err)?;
+    session.start_transaction().await.map_err(tx_err)?;
+    let applied: mongodb::error::Result<()> = async {
+        if !creates.is_empty() {
+            collection.insert_many(creates).session(&mut session).await?;
+        }
+        for (oid, update) in updates {
+            collection.update_one(doc! { "_id": oid }, update).session(&mut session).await?;
+        }
+        for item in &deletes {
+            if let Some(oid) = item.get("_id") {
+                let archive = doc! { "$set": { "archived": true, "archivedAt": now_datetime() } };
+                collection.update_one(doc! { "_id": oid.clone() }, archive).session(&mut session).await?;
+            }
+        }
+        Ok(())
+    }.await;
+    match applied {
+        Ok(()) => session.commit_transaction().await.map_err(tx_err)?,
+        Err(e) => {
+            let _ = session.abort_transaction().await;
+            return Err(tx_err(e));
+        }
+    }
+    let deleted_ids: Vec<String> = deletes.iter().filter_map(|d| document_id(d)).collect();
+    remove_item_favorites(&db, &deleted_ids).await;
+    for (menu_doc, price) in &price_changes {
+        record_price_change(&db, menu_doc, *price, &claims.sub, "import").await;
+    }
+
+    Ok(data_response(Bson::Document(summary)))
+}
+
+pub fn menu_transfer_router(db: Database) -> Router{
+    Router::new()
+        .route("/menu/export", get(export_menu))
+        .route("/menu/import", post(import_menu))
+        .with_state(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_rows_skip_empty_cells_and_split_lists() {
+        let rows = parse_csv_rows("id,name,price,tags,description\n,Beef noodles, 120 ,spicy| soup |,\nm1,,90,,\n").unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get("name"), Some(&Value::String("Beef noodles".to_string())));
+        assert_eq!(rows[0].get("price"), Some(&Value::String("120".to_string())));
+        assert_eq!(rows[0].get("tags"), Some(&serde_json::json!(["spicy", "soup"])));
+        assert!(!rows[0].contains_key("id"));
+        assert!(!rows[0].contains_key("description"));
+        assert_eq!(row_id(&rows[1]), Some("m1".to_string()));
+        assert!(!rows[1].contains_key("name"));
+    }
+
+    #[test]
+    fn csv_rows_keep_quoted_commas() {
+        let rows = parse_csv_rows("name,description\n\"Rice, fried\",\"egg, scallion\"\n").unwrap();
+        assert_eq!(rows[0].get("name"), Some(&Value::String("Rice, fried".to_string())));
+    }
+
+    #[test]
+    fn csv_rows_with_the_wrong_cell_count_are_rejected() {
+        assert!(parse_csv_rows("name,price\nTea,30,extra\n").is_err());
+    }
+
+    #[test]
+    fn json_rows_accept_the_export_shape() {
+        assert_eq!(parse_json_rows(r#"[{"name":"Tea"}]"#).unwrap().len(), 1);
+        assert_eq!(parse_json_rows(r#"{"items":[{"name":"Tea"},{"name":"Milk"}]}"#).unwrap().len(), 2);
+        assert_eq!(parse_json_rows(r#"{"data":[{"name":"Tea"}]}"#).unwrap().len(), 1);
+        assert!(parse_json_rows(r#"{"name":"Tea"}"#).is_err());
+        assert!(parse_json_rows("not json").is_err());
+    }
+
+    #[test]
+    fn validate_row_converts_csv_text() {
+        let rows = parse_csv_rows("name,price,isAvailable,allergens\nTea,30,no,milk|soy\n").unwrap();
+        let (fields, errors) = validate_row(&rows[0]);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(fields.get_i64("price").ok(), Some(30));
+        assert_eq!(fields.get_bool("isAvailable").ok(), Some(false));
+        assert_eq!(fields.get_array("allergens").map(|a| a.len()).ok(), Some(2));
+    }
+
+    #[test]
+    fn validate_row_collects_every_problem() {
+        let rows = parse_csv_rows("name,price,stock,isAvailable,colour\nTea,-5,lots,maybe,red\n").unwrap();
+        let (_, errors) = validate_row(&rows[0]);
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert!(errors.contains(&"price must be a non-negative integer".to_string()));
+        assert!(errors.contains(&"unknown column colour".to_string()));
+    }
+}
@@ -1,13 +1,34 @@
 use axum::Router;
 use mongodb::Database;
 
+pub mod addresses;
 mod auth;
+mod bundles;
+mod cart;
 mod common;
 mod delivery;
+mod favorites;
+mod fees;
+pub mod geo;
+mod hours;
+mod i18n;
+pub mod inventory;
+pub mod locations;
 mod menu;
+mod menu_transfer;
+pub mod moderation;
+pub mod onboarding;
 mod orders;
+pub mod pricing;
+mod profile;
+pub mod promos;
+pub mod ratings;
 mod restaurant;
-mod retaurants;
+pub mod retaurants;
+mod reviews;
+mod schedule;
+mod storage;
+mod uploads;
 mod push;
 
 pub fn api_router(db: Database) -> Router{
@@ -17,7 +38,26 @@ pub fn api_router(db: Database) -> Router{
     .nest("/restaurants", retaurants::home_page_router(db.clone()))
     .nest("/restaurants", menu::menu_router(db.clone()))
     .nest("/orders", orders::orders_router(db.clone()))
+    .nest("/cart", cart::cart_router(db.clone()))
+    .nest("/promotions", promos::promotions_router(db.clone()))
     .nest("/delivery", delivery::delivery_router(db.clone()))
     .nest("/restaurant", restaurant::restaurant_router(db.clone()))
+    .nest("/restaurant", menu_transfer::menu_transfer_router(db.clone()))
+    .nest("/restaurant", uploads::uploads_router(db.clone()))
+    .nest("/restaurant", hours::hours_router(db.clone()))
+    .nest("/restaurant", reviews::reviews_router(db.clone()))
+    .nest("/restaurant", profile::profile_router(db.clone()))
+    .nest("/restaurant", onboarding::suspension_router(db.clone()))
+    .nest("/restaurant-applications", onboarding::applications_router(db.clone()))
+    .nest("/restaurant-applications", uploads::application_uploads_router(db.clone()))
+    .nest("/uploads", storage::files_router())
+    .nest("/reviews", moderation::review_reports_router(db.clone()))
+    .nest("/admin", moderation::moderation_admin_router(db.clone()))
+    .nest("/admin", onboarding::onboarding_admin_router(db.clone()))
+    .nest("/admin", locations::locations_admin_router(db.clone()))
+    .nest("/admin", fees::fees_admin_router(db.clone()))
+    .nest("/admin", promos::promotions_admin_router(db.clone()))
+    .nest("/me", favorites::favorites_router(db.clone()))
+    .nest("/me", addresses::addresses_router(db.clone()))
     .nest("/push", push::push_router(db.clone()))
 }
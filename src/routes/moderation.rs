@@ -0,0 +1,316 @@
+#![allow(non_snake_case)]
+
+use axum::{Router, routing::{get, post}, extract::{State, Path, Query}, Json, http::HeaderMap};
+use mongodb::{bson::{doc, Bson, Document}, options::IndexOptions, Database, IndexModel};
+use futures::stream::TryStreamExt;
+use serde::Deserialize;
+use axum::http::StatusCode;
+use crate::routes::common::{Claims, ApiResult, data_response, data_response_with_status, error_response, get_string, get_array, auth_claims, require_role, now_datetime, iso_from_bson, is_duplicate_key};
+use crate::routes::ratings::adjust_rating_summary;
+use crate::routes::reviews::{FoundReview, find_review};
+
+const MAX_REASON_CHARS: usize = 500;
+
+#[derive(Deserialize)]
+struct ReasonRequest {
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct BannedWordsRequest {
+    words: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ReportListQuery {
+    // open (default) | resolved | all
+    status: Option<String>,
+}
+
+fn reason_of(payload: &ReasonRequest) -> Result<String, (StatusCode, Json<Document>)>{
+    let reason = payload.reason.trim();
+    if reason.is_empty() || reason.chars().count() > MAX_REASON_CHARS {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("reason must be 1-{} characters", MAX_REASON_CHARS)));
+    }
+    Ok(reason.to_string())
+}
+
+// Banned words are kept in `moderation_settings`; BANNED_WORDS (comma separated) is used until an admin sets a list.
+async fn banned_words(db: &Database) -> Vec<String>{
+    let stored = db.collection::<Document>("moderation_settings")
+        .find_one(doc! { "id": "banned_words" })
+        .await
+        .ok()
+        .flatten()
+        .and_then(|d| get_array(&d, "words"));
+    match stored {
+        Some(words) => words.iter().filter_map(|w| w.as_str().map(|s| s.to_lowercase())).collect(),
+        None => std::env::var("BANNED_WORDS").unwrap_or_default()
+            .split(',')
+            .map(|w| w.trim().to_lowercase())
+            .filter(|w| !w.is_empty())
+            .collect(),
+    }
+}
+
+// Han and kana are written without spaces, so a banned word in them matches anywhere.
+fn unspaced(c: char) -> bool{
+    matches!(c, '\u{3040}'..='\u{30ff}' | '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' | '\u{f900}'..='\u{faff}')
+}
+
+fn word_char(c: char) -> bool{
+    c.is_alphanumeric() && !unspaced(c)
+}
+
+// `word` appears in `text` as a whole word: not run into letters or digits on either side
+// ("ass" doesn't match "class"). Both are expected in lowercase.
+fn contains_word(text: &str, word: &str) -> bool{
+    if word.is_empty() {
+        return false;
+    }
+    text.match_indices(word).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + word.len()..].chars().next();
+        let open = !word.chars().next().is_some_and(word_char) || !before.is_some_and(word_char);
+        let close = !word.chars().next_back().is_some_and(word_char) || !after.is_some_and(word_char);
+        open && close
+    })
+}
+
+pub async fn ensure_moderation_indexes(db: &Database) -> mongodb::error::Result<()>{
+    let one_open_report = IndexModel::builder()
+        .keys(doc! { "reviewId": 1, "reporterId": 1 })
+        .options(IndexOptions::builder().unique(true).partial_filter_expression(doc! { "status": "open" }).name("one_open_report".to_string()).build())
+        .build();
+    db.collection::<Document>("review_reports").create_index(one_open_report).await?;
+    Ok(())
+}
+
+// Reject public text (review comments, replies) that contains a banned word.
+pub async fn check_banned_words(db: &Database, text: Option<&str>) -> Result<(), (StatusCode, Json<Document>)>{
+    let Some(text) = text.map(str::to_lowercase) else { return Ok(()) };
+    if banned_words(db).await.iter().any(|w| contains_word(&text, w)) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "review.rejected", "text contains words that are not allowed"));
+    }
+    Ok(())
+}
+
+async fn record_audit(db: &Database, review: &FoundReview, action: &str, actor: &Claims, reason: Option<&str>){
+    let entry = doc! {
+        "id": mongodb::bson::oid::ObjectId::new().to_hex(),
+        "reviewId": &review.id,
+        "source": review.source,
+        "restaurantId": &review.restaurant_id,
+        "action": action,
+        "actorId": &actor.sub,
+        "actorRole": &actor.role,
+        "reason": reason,
+        "createdAt": now_datetime()
+    };
+    if let Err(e) = db.collection::<Document>("review_audit").insert_one(entry).await {
+        eprintln!("moderation.record_audit error: {}", e);
+    }
+}
+
+async fn load_review(db: &Database, id: &str) -> Result<FoundReview, (StatusCode, Json<Document>)>{
+    find_review(db, id).await?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "review.not_found", "Review not found"))
+}
+
+// POST /reviews/{id}/report
+async fn report_review(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<ReasonRequest>) -> ApiResult{
+    let claims = auth_claims(&headers)?;
+    let reason = reason_of(&payload)?;
+    let review = load_review(&db, &id).await?;
+    let reports = db.collection::<Document>("review_reports");
+    let report_id = mongodb::bson::oid::ObjectId::new().to_hex();
+    let report = doc! {
+        "id": &report_id,
+        "reviewId": &id,
+        "source": review.source,
+        "restaurantId": &review.restaurant_id,
+        "reporterId": &claims.sub,
+        "reason": &reason,
+        "status": "open",
+        "createdAt": now_datetime()
+    };
+    // one open report per user and review, held by the one_open_report index
+    reports.insert_one(report).await.map_err(|e| {
+        if is_duplicate_key(&e) {
+            error_response(StatusCode::CONFLICT, "review.already_reported", "You have already reported this review")
+        } else {
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string())
+        }
+    })?;
+    record_audit(&db, &review, "report", &claims, Some(&reason)).await;
+    Ok(data_response_with_status(StatusCode::CREATED, Bson::Document(doc! { "id": report_id, "status": "open" })))
+}
+
+// GET /admin/reviews/reports?status=open|resolved|all
+async fn list_reports(State(db): State<Database>, headers: HeaderMap, Query(query): Query<ReportListQuery>) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    let filter = match query.status.as_deref().unwrap_or("open") {
+        "all" => doc! {},
+        status @ ("open" | "resolved") => doc! { "status": status },
+        _ => return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "status must be open, resolved or all")),
+    };
+    let reports: Vec<Document> = db.collection::<Document>("review_reports").find(filter)
+        .sort(doc! { "createdAt": -1 })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .try_collect()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let items: Vec<Bson> = reports.iter().map(|r| Bson::Document(doc! {
+        "id": get_string(r, "id").unwrap_or_default(),
+        "reviewId": get_string(r, "reviewId").unwrap_or_default(),
+        "source": get_string(r, "source"),
+        "restaurantId": get_string(r, "restaurantId"),
+        "reporterId": get_string(r, "reporterId"),
+        "reason": get_string(r, "reason"),
+        "status": get_string(r, "status"),
+        "createdAt": r.get("createdAt").and_then(iso_from_bson),
+        "resolvedAt": r.get("resolvedAt").and_then(iso_from_bson)
+    })).collect();
+    Ok(data_response(Bson::Array(items)))
+}
+
+// Hide or unhide a review, keep the rating summary in step and close its open reports.
+async fn set_hidden(db: &Database, id: &str, claims: &Claims, hidden: bool, reason: &str) -> ApiResult{
+    let review = load_review(db, id).await?;
+    if review.hidden == hidden {
+        return Err(error_response(StatusCode::CONFLICT, "review.conflict", if hidden { "review is already hidden" } else { "review is not hidden" }));
+    }
+    let now = now_datetime();
+    let update = if hidden {
+        doc! { "$set": { review.field("hidden"): true, review.field("hiddenReason"): reason, review.field("hiddenAt"): now, review.field("hiddenBy"): &claims.sub } }
+    } else {
+        doc! {
+            "$set": { review.field("hidden"): false },
+            "$unset": { review.field("hiddenReason"): "", review.field("hiddenAt"): "", review.field("hiddenBy"): "" }
+        }
+    };
+    review.update(db, update)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let (old, new) = if hidden { (review.score, None) } else { (None, review.score) };
+    if let Err(e) = adjust_rating_summary(db, &review.restaurant_id, old, new).await {
+        eprintln!("moderation.set_hidden summary error: {}", e);
+    }
+    db.collection::<Document>("review_reports")
+        .update_many(doc! { "reviewId": id, "status": "open" }, doc! { "$set": { "status": "resolved", "resolvedAt": now, "resolvedBy": &claims.sub } })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    record_audit(db, &review, if hidden { "hide" } else { "unhide" }, claims, Some(reason)).await;
+    Ok(data_response(Bson::Document(doc! { "id": id, "hidden": hidden, "reason": reason })))
+}
+
+// POST /admin/reviews/{id}/hide
+async fn hide_review(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<ReasonRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["admin"])?;
+    let reason = reason_of(&payload)?;
+    set_hidden(&db, &id, &claims, true, &reason).await
+}
+
+// POST /admin/reviews/{id}/unhide
+async fn unhide_review(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<ReasonRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["admin"])?;
+    let reason = reason_of(&payload)?;
+    set_hidden(&db, &id, &claims, false, &reason).await
+}
+
+// GET /admin/reviews/{id}/audit
+async fn review_audit(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    let entries: Vec<Document> = db.collection::<Document>("review_audit").find(doc! { "reviewId": &id })
+        .sort(doc! { "createdAt": 1 })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .try_collect()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let items: Vec<Bson> = entries.iter().map(|e| Bson::Document(doc! {
+        "id": get_string(e, "id").unwrap_or_default(),
+        "action": get_string(e, "action"),
+        "actorId": get_string(e, "actorId"),
+        "actorRole": get_string(e, "actorRole"),
+        "reason": get_string(e, "reason"),
+        "createdAt": e.get("createdAt").and_then(iso_from_bson)
+    })).collect();
+    Ok(data_response(Bson::Array(items)))
+}
+
+// GET /admin/moderation/banned-words
+async fn get_banned_words(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    let words: Vec<Bson> = banned_words(&db).await.into_iter().map(Bson::String).collect();
+    Ok(data_response(Bson::Document(doc! { "words": words })))
+}
+
+// PUT /admin/moderation/banned-words
+async fn set_banned_words(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<BannedWordsRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["admin"])?;
+    let mut words: Vec<String> = payload.words.iter()
+        .map(|w| w.trim().to_lowercase())
+        .filter(|w| !w.is_empty())
+        .collect();
+    words.sort();
+    words.dedup();
+    db.collection::<Document>("moderation_settings")
+        .update_one(
+            doc! { "id": "banned_words" },
+            doc! { "$set": { "words": &words, "updatedAt": now_datetime(), "updatedBy": &claims.sub } },
+        )
+        .upsert(true)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(data_response(Bson::Document(doc! { "words": words })))
+}
+
+pub fn review_reports_router(db: Database) -> Router{
+    Router::new()
+        .route("/{id}/report", post(report_review))
+        .with_state(db)
+}
+
+pub fn moderation_admin_router(db: Database) -> Router{
+    Router::new()
+        .route("/reviews/reports", get(list_reports))
+        .route("/reviews/{id}/hide", post(hide_review))
+        .route("/reviews/{id}/unhide", post(unhide_review))
+        .route("/reviews/{id}/audit", get(review_audit))
+        .route("/moderation/banned-words", get(get_banned_words).put(set_banned_words))
+        .with_state(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_whole_words_only() {
+        assert!(contains_word("what a scam", "scam"));
+        assert!(contains_word("scam!", "scam"));
+        assert!(contains_word("total scam, avoid", "scam"));
+        assert!(!contains_word("scampi was great", "scam"));
+        assert!(!contains_word("a classic", "ass"));
+    }
+
+    #[test]
+    fn matches_phrases_across_spaces() {
+        assert!(contains_word("this is food poisoning, really", "food poisoning"));
+        assert!(!contains_word("seafood poisoning", "food poisoning"));
+    }
+
+    #[test]
+    fn matches_han_words_inside_a_sentence() {
+        assert!(contains_word("這家店是垃圾店", "垃圾"));
+        assert!(contains_word("垃圾scam", "垃圾"));
+        assert!(!contains_word("垃圾scammer", "scam"));
+    }
+
+    #[test]
+    fn ignores_empty_words() {
+        assert!(!contains_word("anything", ""));
+    }
+}
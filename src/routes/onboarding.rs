@@ -0,0 +1,443 @@
+#![allow(non_snake_case)]
+
+use axum::{Router, routing::{get, post}, extract::{State, Path, Query}, Json, http::HeaderMap};
+use bcrypt::{hash, DEFAULT_COST};
+use mongodb::{bson::{doc, Bson, Document, oid::ObjectId}, options::IndexOptions, Database, IndexModel};
+use futures::stream::TryStreamExt;
+use serde::Deserialize;
+use axum::http::StatusCode;
+use crate::routes::common::{Claims, ApiResult, data_response, data_response_with_status, error_response, get_string, get_f64, require_role, claims_restaurant_id, now_datetime, iso_from_bson, sign_token, is_duplicate_key, invalid, checked_text};
+use crate::routes::geo::geo_point;
+use crate::routes::hours::{load_shop, update_shop};
+use crate::routes::profile::{MAX_ADDRESS_CHARS, MAX_DESCRIPTION_CHARS, MAX_NAME_CHARS, valid_phone, valid_url, validate_latlng};
+
+// Shops in these states are left out of every customer endpoint. Shops without a status
+// predate onboarding and count as active.
+const HIDDEN_STATUSES: [&str; 1] = ["suspended"];
+pub const MAX_DOCUMENTS: usize = 10;
+// how long an applicant can follow their application and upload documents for it
+const APPLICANT_TOKEN_HOURS: u64 = 30 * 24;
+const MAX_REASON_CHARS: usize = 500;
+const MIN_PASSWORD_CHARS: usize = 8;
+
+#[derive(Deserialize)]
+struct ApplicantRequest {
+    name: String,
+    email: String,
+    phone: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct ShopDetailsRequest {
+    name: String,
+    address: String,
+    phone: String,
+    description: Option<String>,
+    lat: f64,
+    lng: f64,
+}
+
+#[derive(Deserialize)]
+struct ApplicationDocument {
+    // e.g. business_license, food_safety_certificate
+    r#type: String,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct ApplicationRequest {
+    applicant: ApplicantRequest,
+    shop: ShopDetailsRequest,
+    // links to files hosted elsewhere; files can also be uploaded to /restaurant-applications/{id}/documents
+    #[serde(default)]
+    documents: Vec<ApplicationDocument>,
+}
+
+#[derive(Deserialize)]
+struct ApplicationListQuery {
+    // pending (default) | approved | rejected | all
+    status: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ReasonRequest {
+    reason: Option<String>,
+}
+
+fn reason_of(payload: &ReasonRequest, required: bool) -> Result<Option<String>, (StatusCode, Json<Document>)>{
+    match payload.reason.as_deref().map(str::trim).filter(|r| !r.is_empty()) {
+        Some(reason) if reason.chars().count() > MAX_REASON_CHARS => Err(invalid(&format!("reason must be at most {} characters", MAX_REASON_CHARS))),
+        Some(reason) => Ok(Some(reason.to_string())),
+        None if required => Err(invalid("reason is required")),
+        None => Ok(None),
+    }
+}
+
+// `status` filter for customer-facing shop queries.
+pub fn listed_shop_filter() -> Document{
+    doc! { "status": { "$nin": HIDDEN_STATUSES.to_vec() } }
+}
+
+pub fn shop_is_listed(shop: &Document) -> bool{
+    !get_string(shop, "status").is_some_and(|s| HIDDEN_STATUSES.contains(&s.as_str()))
+}
+
+fn application_view(application: &Document) -> Document{
+    doc! {
+        "id": get_string(application, "id"),
+        "status": get_string(application, "status"),
+        "applicant": application.get_document("applicant").cloned().unwrap_or_default(),
+        "shop": application.get_document("shop").cloned().unwrap_or_default(),
+        "documents": application.get("documents").cloned().unwrap_or_else(|| Bson::Array(Vec::new())),
+        "rejectionReason": get_string(application, "rejectionReason"),
+        "restaurantId": get_string(application, "restaurantId"),
+        "createdAt": application.get("createdAt").and_then(iso_from_bson),
+        "reviewedAt": application.get("reviewedAt").and_then(iso_from_bson)
+    }
+}
+
+// Unique emails for accounts, and at most one pending application per email, so concurrent
+// submissions and approvals can't both get through.
+pub async fn ensure_onboarding_indexes(db: &Database) -> mongodb::error::Result<()>{
+    let unique_email = IndexModel::builder()
+        .keys(doc! { "email": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    db.collection::<Document>("users").create_index(unique_email).await?;
+    let one_pending = IndexModel::builder()
+        .keys(doc! { "applicant.email": 1 })
+        .options(IndexOptions::builder().unique(true).partial_filter_expression(doc! { "status": "pending" }).build())
+        .build();
+    db.collection::<Document>("restaurant_applications").create_index(one_pending).await?;
+    Ok(())
+}
+
+// The applicant token handed out on submission only opens its own application.
+pub fn applicant_claims(headers: &HeaderMap, application_id: &str) -> Result<Claims, (StatusCode, Json<Document>)>{
+    let claims = require_role(headers, &["applicant"])?;
+    if claims.sub != application_id {
+        return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
+    }
+    Ok(claims)
+}
+
+async fn email_taken(db: &Database, email: &str) -> Result<bool, (StatusCode, Json<Document>)>{
+    db.collection::<Document>("users").find_one(doc! { "email": email })
+        .await
+        .map(|user| user.is_some())
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))
+}
+
+async fn load_application(db: &Database, id: &str) -> Result<Document, (StatusCode, Json<Document>)>{
+    db.collection::<Document>("restaurant_applications").find_one(doc! { "id": id })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "application.not_found", "Application not found"))
+}
+
+// POST /restaurant-applications
+// No account is needed; the restaurant login is created from `applicant` once an admin approves.
+// The response carries an applicant token for following the application and uploading its documents.
+async fn submit_application(State(db): State<Database>, Json(payload): Json<ApplicationRequest>) -> ApiResult{
+    let applicant_name = checked_text("applicant.name", &payload.applicant.name, MAX_NAME_CHARS)?;
+    let email = payload.applicant.email.trim().to_lowercase();
+    if !email.contains('@') || email.chars().count() > MAX_ADDRESS_CHARS {
+        return Err(invalid("applicant.email is not a valid email address"));
+    }
+    if payload.applicant.password.chars().count() < MIN_PASSWORD_CHARS {
+        return Err(invalid(&format!("password must be at least {} characters", MIN_PASSWORD_CHARS)));
+    }
+    for phone in [&payload.applicant.phone, &payload.shop.phone] {
+        if !valid_phone(phone.trim()) {
+            return Err(invalid("phone must contain 6-15 digits and only digits, spaces, +, - or parentheses"));
+        }
+    }
+    let shop_name = checked_text("shop.name", &payload.shop.name, MAX_NAME_CHARS)?;
+    let address = checked_text("shop.address", &payload.shop.address, MAX_ADDRESS_CHARS)?;
+    let description = match payload.shop.description.as_deref().map(str::trim).filter(|d| !d.is_empty()) {
+        Some(d) => Some(checked_text("shop.description", d, MAX_DESCRIPTION_CHARS)?),
+        None => None,
+    };
+    validate_latlng(payload.shop.lat, payload.shop.lng)?;
+    if payload.documents.len() > MAX_DOCUMENTS {
+        return Err(invalid(&format!("documents must list at most {} files", MAX_DOCUMENTS)));
+    }
+    let mut documents: Vec<Bson> = Vec::new();
+    for document in &payload.documents {
+        let kind = checked_text("documents.type", &document.r#type, MAX_NAME_CHARS)?;
+        if !valid_url(document.url.trim()) {
+            return Err(invalid("documents.url must be an http(s) URL or an uploaded file path"));
+        }
+        documents.push(Bson::Document(doc! { "type": kind, "url": document.url.trim() }));
+    }
+
+    if email_taken(&db, &email).await? {
+        return Err(error_response(StatusCode::BAD_REQUEST, "auth.email_taken", "email exists"));
+    }
+    let applications = db.collection::<Document>("restaurant_applications");
+    let password_hash = hash(&payload.applicant.password, DEFAULT_COST)
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let application = doc! {
+        "id": ObjectId::new().to_hex(),
+        "status": "pending",
+        "applicant": { "name": applicant_name, "email": &email, "phone": payload.applicant.phone.trim() },
+        "passwordHash": password_hash,
+        "shop": {
+            "name": shop_name,
+            "address": address,
+            "phone": payload.shop.phone.trim(),
+            "description": description,
+            "lat": payload.shop.lat,
+            "lng": payload.shop.lng
+        },
+        "documents": documents,
+        "createdAt": now_datetime()
+    };
+    // the unique index on pending applications settles two submissions racing for one email
+    if let Err(e) = applications.insert_one(&application).await {
+        if is_duplicate_key(&e) {
+            return Err(error_response(StatusCode::CONFLICT, "application.conflict", "an application for this email is already pending"));
+        }
+        return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()));
+    }
+    let application_id = get_string(&application, "id").unwrap_or_default();
+    let token = sign_token(&application_id, &email, "applicant", None, APPLICANT_TOKEN_HOURS)
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let mut view = application_view(&application);
+    view.insert("applicantToken", token);
+    Ok(data_response_with_status(StatusCode::CREATED, Bson::Document(view)))
+}
+
+// GET /restaurant-applications/{id}
+// For the applicant to follow their application, with the token from submission; only the outcome is shown.
+async fn application_status(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    applicant_claims(&headers, &id)?;
+    let application = load_application(&db, &id).await?;
+    Ok(data_response(Bson::Document(doc! {
+        "id": &id,
+        "status": get_string(&application, "status"),
+        "documents": application.get("documents").cloned().unwrap_or_else(|| Bson::Array(Vec::new())),
+        "rejectionReason": get_string(&application, "rejectionReason"),
+        "reviewedAt": application.get("reviewedAt").and_then(iso_from_bson)
+    })))
+}
+
+// GET /admin/restaurant-applications?status=pending|approved|rejected|all
+async fn list_applications(State(db): State<Database>, headers: HeaderMap, Query(query): Query<ApplicationListQuery>) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    let filter = match query.status.as_deref().unwrap_or("pending") {
+        "all" => doc! {},
+        status @ ("pending" | "approved" | "rejected") => doc! { "status": status },
+        _ => return Err(invalid("status must be pending, approved, rejected or all")),
+    };
+    let applications: Vec<Document> = db.collection::<Document>("restaurant_applications").find(filter)
+        .sort(doc! { "createdAt": 1 })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .try_collect()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let items: Vec<Bson> = applications.iter().map(|a| Bson::Document(application_view(a))).collect();
+    Ok(data_response(Bson::Array(items)))
+}
+
+// GET /admin/restaurant-applications/{id}
+async fn get_application(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    let application = load_application(&db, &id).await?;
+    Ok(data_response(Bson::Document(application_view(&application))))
+}
+
+// POST /admin/restaurant-applications/{id}/approve
+// Creates the shop and its restaurant account and links the two.
+async fn approve_application(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["admin"])?;
+    let application = load_application(&db, &id).await?;
+    let applicant = application.get_document("applicant").cloned().unwrap_or_default();
+    let details = application.get_document("shop").cloned().unwrap_or_default();
+    let email = get_string(&applicant, "email").unwrap_or_default();
+    if application.get_array("documents").map_or(true, |d| d.is_empty()) {
+        return Err(invalid("application has no documents yet"));
+    }
+
+    // claim the application first so two admins can't approve it twice
+    let applications = db.collection::<Document>("restaurant_applications");
+    let restaurant_id = ObjectId::new().to_hex();
+    let user_id = ObjectId::new().to_hex();
+    let now = now_datetime();
+    let claimed = applications.find_one_and_update(
+        doc! { "id": &id, "status": "pending" },
+        doc! { "$set": { "status": "approved", "reviewedAt": now, "reviewedBy": &claims.sub, "restaurantId": &restaurant_id, "userId": &user_id } },
+    )
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    if claimed.is_none() {
+        return Err(error_response(StatusCode::CONFLICT, "application.conflict", "application is not pending"));
+    }
+
+    let (lat, lng) = (get_f64(&details, "lat").unwrap_or(0.0), get_f64(&details, "lng").unwrap_or(0.0));
+    let shop = doc! {
+        "id": &restaurant_id,
+        "name": get_string(&details, "name"),
+        "address": get_string(&details, "address"),
+        "phone": get_string(&details, "phone"),
+        "description": get_string(&details, "description"),
+        "lat": lat,
+        "lng": lng,
+        "location": geo_point(lat, lng),
+        "status": "active",
+        "ownerId": &user_id,
+        "applicationId": &id,
+        "createdAt": now
+    };
+    let user = doc! {
+        "id": &user_id,
+        "name": get_string(&applicant, "name"),
+        "email": &email,
+        "password": get_string(&application, "passwordHash"),
+        "phone": get_string(&applicant, "phone"),
+        "role": "restaurant",
+        "restaurantId": &restaurant_id
+    };
+    let created = match db.collection::<Document>("shops").insert_one(shop).await {
+        Ok(_) => db.collection::<Document>("users").insert_one(user).await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = created {
+        // hand the application back so it can be approved again
+        let _ = db.collection::<Document>("shops").delete_one(doc! { "id": &restaurant_id }).await;
+        let _ = applications.update_one(
+            doc! { "id": &id },
+            doc! { "$set": { "status": "pending" }, "$unset": { "reviewedAt": "", "reviewedBy": "", "restaurantId": "", "userId": "" } },
+        ).await;
+        // the unique email index catches an account created since the application was submitted
+        if is_duplicate_key(&e) {
+            return Err(error_response(StatusCode::CONFLICT, "auth.email_taken", "an account with the applicant's email already exists"));
+        }
+        return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()));
+    }
+    // the stored hash has done its job
+    let _ = applications.update_one(doc! { "id": &id }, doc! { "$unset": { "passwordHash": "" } }).await;
+
+    let approved = load_application(&db, &id).await?;
+    Ok(data_response(Bson::Document(application_view(&approved))))
+}
+
+// POST /admin/restaurant-applications/{id}/reject
+async fn reject_application(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<ReasonRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["admin"])?;
+    let reason = reason_of(&payload, true)?;
+    let rejected = db.collection::<Document>("restaurant_applications").find_one_and_update(
+        doc! { "id": &id, "status": "pending" },
+        doc! {
+            "$set": { "status": "rejected", "rejectionReason": reason, "reviewedAt": now_datetime(), "reviewedBy": &claims.sub },
+            "$unset": { "passwordHash": "" }
+        },
+    )
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    match rejected {
+        Some(application) => Ok(data_response(Bson::Document(application_view(&application)))),
+        None => {
+            load_application(&db, &id).await?;
+            Err(error_response(StatusCode::CONFLICT, "application.conflict", "application is not pending"))
+        }
+    }
+}
+
+fn suspension_view(shop: &Document) -> Document{
+    let suspension = shop.get_document("suspension").ok();
+    doc! {
+        "id": get_string(shop, "id"),
+        "status": get_string(shop, "status").unwrap_or_else(|| "active".to_string()),
+        "suspendedBy": suspension.and_then(|s| get_string(s, "by")),
+        "reason": suspension.and_then(|s| get_string(s, "reason")),
+        "suspendedAt": suspension.and_then(|s| s.get("at")).and_then(iso_from_bson)
+    }
+}
+
+async fn suspend_shop(db: &Database, restaurant_id: &str, by: &str, actor_id: &str, reason: Option<String>) -> ApiResult{
+    let shop = load_shop(db, restaurant_id).await?;
+    if get_string(&shop, "status").as_deref() == Some("suspended") {
+        return Err(error_response(StatusCode::CONFLICT, "restaurant.conflict", "restaurant is already suspended"));
+    }
+    let update = doc! { "$set": {
+        "status": "suspended",
+        "suspension": { "by": by, "actorId": actor_id, "reason": reason, "at": now_datetime() }
+    } };
+    let shop = update_shop(db, restaurant_id, update).await?;
+    Ok(data_response(Bson::Document(suspension_view(&shop))))
+}
+
+async fn reinstate_shop(db: &Database, restaurant_id: &str, as_admin: bool) -> ApiResult{
+    let shop = load_shop(db, restaurant_id).await?;
+    if get_string(&shop, "status").as_deref() != Some("suspended") {
+        return Err(error_response(StatusCode::CONFLICT, "restaurant.conflict", "restaurant is not suspended"));
+    }
+    let by_admin = shop.get_document("suspension").ok().and_then(|s| get_string(s, "by")).as_deref() == Some("admin");
+    if by_admin && !as_admin {
+        return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "only an admin can lift this suspension"));
+    }
+    let update = doc! { "$set": { "status": "active" }, "$unset": { "suspension": "" } };
+    let shop = update_shop(db, restaurant_id, update).await?;
+    Ok(data_response(Bson::Document(suspension_view(&shop))))
+}
+
+// POST /admin/restaurants/{id}/suspend
+async fn admin_suspend(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<ReasonRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["admin"])?;
+    let reason = reason_of(&payload, true)?;
+    suspend_shop(&db, &id, "admin", &claims.sub, reason).await
+}
+
+// POST /admin/restaurants/{id}/unsuspend
+async fn admin_reinstate(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    reinstate_shop(&db, &id, true).await
+}
+
+// GET /restaurant/suspension
+async fn get_suspension(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let shop = load_shop(&db, &claims_restaurant_id(&claims)).await?;
+    Ok(data_response(Bson::Document(suspension_view(&shop))))
+}
+
+// POST /restaurant/suspension
+async fn self_suspend(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<ReasonRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let reason = reason_of(&payload, false)?;
+    suspend_shop(&db, &claims_restaurant_id(&claims), "owner", &claims.sub, reason).await
+}
+
+// DELETE /restaurant/suspension
+async fn self_reinstate(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    reinstate_shop(&db, &claims_restaurant_id(&claims), false).await
+}
+
+pub fn applications_router(db: Database) -> Router{
+    Router::new()
+        .route("/", post(submit_application))
+        .route("/{id}", get(application_status))
+        .with_state(db)
+}
+
+pub fn onboarding_admin_router(db: Database) -> Router{
+    Router::new()
+        .route("/restaurant-applications", get(list_applications))
+        .route("/restaurant-applications/{id}", get(get_application))
+        .route("/restaurant-applications/{id}/approve", post(approve_application))
+        .route("/restaurant-applications/{id}/reject", post(reject_application))
+        .route("/restaurants/{id}/suspend", post(admin_suspend))
+        .route("/restaurants/{id}/unsuspend", post(admin_reinstate))
+        .with_state(db)
+}
+
+pub fn suspension_router(db: Database) -> Router{
+    Router::new()
+        .route("/suspension", get(get_suspension).post(self_suspend).delete(self_reinstate))
+        .with_state(db)
+}
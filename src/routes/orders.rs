@@ -5,49 +5,98 @@ use futures::stream::TryStreamExt;
 use futures::stream;
 use axum::http::StatusCode;
 use std::convert::Infallible;
-use crate::routes::common::{ApiResult, data_response, data_response_with_status, error_response, document_id, get_string, get_i64, get_f64, now_datetime, iso_from_bson, require_role, haversine_km};
-
-const PREP_MINUTES: i64 = 10;
-const WALK_SPEED_KMH: f64 = 5.0;
+use crate::routes::common::{Claims, ApiResult, data_response, data_response_with_status, error_response, document_id, get_string, get_i64, get_f64, now_datetime, iso_from_bson, require_role, haversine_km, menu_item_filter, menu_restaurant_id};
+use crate::routes::addresses::saved_delivery_location;
+use crate::routes::cart::{check_quantity, option_problem};
+use crate::routes::geo::{doc_latlng, eta_minutes};
+use crate::routes::locations::find_location_by_name;
+use crate::routes::onboarding::shop_is_listed;
+use crate::routes::profile::validate_latlng;
+use crate::routes::hours::{next_open, shop_open_at};
+use crate::routes::ratings::{adjust_rating_summary, counted_order_score, normalize_rating_tags, rating_edit_window_millis};
+use crate::routes::reviews::order_rating_view;
+use crate::routes::moderation::check_banned_words;
+use crate::routes::inventory::{stock_left, tracks_stock, reserve_stock, release_stock, restore_order_stock, line_stock_claims};
+use crate::routes::bundles::{is_bundle, expand_bundle};
+use crate::routes::pricing::resolve_price;
+use crate::routes::schedule::{item_in_window, load_category, order_time};
+use crate::routes::fees::{FeeQuote, load_fee_rules, quote_fee};
+use crate::routes::promos::{Discount, apply_promotion, claim_promotion, release_promotion, restore_order_promotion};
+
+
+#[derive(Deserialize, Clone)]
+pub struct DeliveryLocation {
+    pub name: String,
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    // drop-off instructions for the rider
+    pub note: Option<String>,
+}
 
-#[derive(Deserialize)]
-struct DeliveryLocation {
-    name: String,
-    lat: Option<f64>,
-    lng: Option<f64>,
+impl DeliveryLocation {
+    fn to_document(&self) -> Document{
+        let mut location_doc = doc! { "name": &self.name };
+        if let Some(lat) = self.lat {
+            location_doc.insert("lat", lat);
+        }
+        if let Some(lng) = self.lng {
+            location_doc.insert("lng", lng);
+        }
+        if let Some(note) = self.note.as_deref().filter(|n| !n.is_empty()) {
+            location_doc.insert("note", note);
+        }
+        location_doc
+    }
 }
 
 #[derive(Deserialize)]
-struct OrderItemRequest {
+pub struct OrderItemRequest {
     #[serde(rename = "menuItemId")]
-    menu_item_id: String,
-    quantity: Option<i64>,
-    size: Option<String>,
-    spiciness: Option<String>,
+    pub menu_item_id: String,
+    pub quantity: Option<i64>,
+    pub size: Option<String>,
+    pub spiciness: Option<String>,
     #[serde(rename = "addDrink")]
-    add_drink: Option<bool>,
+    pub add_drink: Option<bool>,
 }
 
 #[derive(Deserialize)]
-struct CreateOrderRequest {
+pub struct CreateOrderRequest {
     #[serde(rename = "restaurantId")]
-    restaurant_id: Option<String>,
+    pub restaurant_id: Option<String>,
+    // either a location, a saved address, or neither to use the customer's default address
     #[serde(rename = "deliveryLocation")]
-    delivery_location: DeliveryLocation,
-    items: Vec<OrderItemRequest>,
-    #[serde(rename = "deliveryFee")]
-    delivery_fee: i64,
-    #[serde(rename = "totalAmount")]
-    total_amount: i64,
-    notes: Option<String>,
+    pub delivery_location: Option<DeliveryLocation>,
+    #[serde(rename = "addressId")]
+    pub address_id: Option<String>,
+    // the server prices the order; a client-sent deliveryFee/totalAmount is ignored (see POST /orders/quote)
+    pub items: Vec<OrderItemRequest>,
+    pub notes: Option<String>,
     #[serde(rename = "requestedTime")]
-    requested_time: Option<String>,
+    pub requested_time: Option<String>,
+    // without a code the best automatic promotion, if any, is applied
+    #[serde(rename = "promoCode")]
+    pub promo_code: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct RatingRequest {
+    // the restaurant score
+    #[serde(alias = "restaurantScore")]
     score: i64,
+    #[serde(rename = "riderScore")]
+    rider_score: Option<i64>,
     comment: Option<String>,
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+struct ReorderRequest {
+    // place the order straight away when nothing has changed since last time
+    confirm: Option<bool>,
+    #[serde(rename = "deliveryLocation")]
+    delivery_location: Option<DeliveryLocation>,
+    notes: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -90,93 +139,275 @@ async fn load_rider_info(db: &Database, order_doc: &Document) -> Option<(String,
 
 async fn find_menu_item(db: &Database, menu_item_id: &str) -> Result<Option<Document>, (StatusCode, Json<Document>)>{
     let collection = db.collection::<Document>("menu");
-    let filter = doc! {
-        "$or": [
-            { "id": menu_item_id },
-            { "_id": mongodb::bson::oid::ObjectId::parse_str(menu_item_id).ok() }
-        ]
-    };
-    collection.find_one(filter)
+    collection.find_one(menu_item_filter(menu_item_id))
         .await
         .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))
 }
 
+// Freeze what the customer ordered, so later menu edits or archiving don't rewrite history.
+fn snapshot_menu_item(menu_doc: &Document) -> Document{
+    let mut snapshot = Document::new();
+    snapshot.insert("id", document_id(menu_doc).unwrap_or_default());
+    for key in ["name", "description", "price", "imageUrl", "thumbnailUrl", "category", "sizes", "spicinessOptions", "allergens", "tags", "type", "components"] {
+        if let Some(value) = menu_doc.get(key) {
+            snapshot.insert(key, value.clone());
+        }
+    }
+    snapshot.insert("capturedAt", now_datetime());
+    snapshot
+}
+
+async fn release_reserved(db: &Database, reserved: &[(String, i64)]){
+    for (menu_item_id, quantity) in reserved {
+        if let Err(e) = release_stock(db, menu_item_id, *quantity).await {
+            eprintln!("orders.release_reserved error: {}", e);
+        }
+    }
+}
+
 async fn create_order(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<CreateOrderRequest>) -> ApiResult{
     let claims = require_role(&headers, &["customer"])?;
-    if payload.items.is_empty() {
-        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "Order items required"));
-    }
+    let created = place_order(&db, &claims, payload).await?;
+    Ok(data_response_with_status(StatusCode::CREATED, Bson::Document(created)))
+}
 
-    let mut items: Vec<Bson> = Vec::new();
-    let mut restaurant_id: Option<String> = payload.restaurant_id.clone();
-    let mut restaurant_name: Option<String> = None;
-    let mut restaurant_latlng: Option<(f64, f64)> = None;
+// POST /orders/quote
+// Prices an order exactly as POST /orders would, without placing it or taking stock.
+async fn quote_order(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<CreateOrderRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let priced = price_order(&db, &claims, &payload).await?;
+    Ok(data_response(Bson::Document(priced.summary())))
+}
 
-    for item in &payload.items {
-        let menu_doc = find_menu_item(&db, &item.menu_item_id).await?;
-        let Some(menu_doc) = menu_doc else {
-            return Err(error_response(StatusCode::BAD_REQUEST, "menu.unavailable", "menu item unavailable"));
-        };
+// An order that has been checked and priced but not stored yet.
+pub struct PricedOrder {
+    items: Vec<Bson>,
+    restaurant_id: String,
+    shop: Document,
+    delivery_location: DeliveryLocation,
+    distance_km: f64,
+    subtotal: i64,
+    fee: FeeQuote,
+    discount: Option<Discount>,
+    // when the order is for: its requested time, or now
+    order_at: chrono::DateTime<chrono::FixedOffset>,
+}
 
-        let is_available = menu_doc.get_bool("isAvailable").unwrap_or(true);
-        if !is_available {
-            return Err(error_response(StatusCode::BAD_REQUEST, "menu.unavailable", "menu item unavailable"));
-        }
+impl PricedOrder {
+    fn discount_total(&self) -> i64{
+        self.discount.as_ref().map_or(0, |d| d.amount)
+    }
+
+    fn total_amount(&self) -> i64{
+        self.subtotal + self.fee.fee - self.discount_total()
+    }
 
-        if restaurant_id.is_none() {
-            restaurant_id = get_string(&menu_doc, "shop_id")
-                .or_else(|| get_string(&menu_doc, "restaurantId"))
-                .or_else(|| get_string(&menu_doc, "restaurant_id"));
+    // What the customer is told before placing: the quote and cart previews.
+    pub fn summary(&self) -> Document{
+        doc! {
+            "restaurantId": &self.restaurant_id,
+            "deliveryLocation": self.delivery_location.to_document(),
+            "subtotal": self.subtotal,
+            "deliveryFee": self.fee.fee,
+            "feeBreakdown": &self.fee.breakdown,
+            "discount": self.discount.as_ref().map(Discount::to_document),
+            "discountTotal": self.discount_total(),
+            "totalAmount": self.total_amount(),
+            "currency": "TWD",
+            "etaMinutes": eta_minutes(Some(self.distance_km))
         }
+    }
+}
 
-        let price = get_i64(&menu_doc, "price").unwrap_or_else(|| {
-            menu_doc.get("price").and_then(Bson::as_f64).map(|v| v.round() as i64).unwrap_or(0)
-        });
-        let quantity = item.quantity.unwrap_or(1);
-        let mut item_doc = Document::new();
-        item_doc.insert("menuItemId", &item.menu_item_id);
-        item_doc.insert("name", get_string(&menu_doc, "name").unwrap_or_default());
-        item_doc.insert("size", item.size.clone().unwrap_or_default());
-        item_doc.insert("spiciness", item.spiciness.clone().unwrap_or_default());
-        item_doc.insert("addDrink", item.add_drink.unwrap_or(false));
-        item_doc.insert("quantity", quantity);
-        item_doc.insert("price", price);
-        items.push(Bson::Document(item_doc));
+// Fee for delivering `subtotal` worth of food from `shop` to `location` at `at`.
+// Without both ends there is no distance to charge for, so the order is refused rather than priced at zero km.
+async fn delivery_quote(db: &Database, shop: &Document, location: &DeliveryLocation, subtotal: i64, at: &chrono::DateTime<chrono::FixedOffset>) -> Result<(f64, FeeQuote), (StatusCode, Json<Document>)>{
+    let Some((r_lat, r_lng)) = doc_latlng(shop) else {
+        eprintln!("orders.delivery_quote: shop {} has no coordinates", get_string(shop, "id").unwrap_or_default());
+        return Err(error_response(StatusCode::BAD_REQUEST, "restaurant.unavailable", "restaurant has no location set, so delivery can't be priced"));
+    };
+    let (Some(d_lat), Some(d_lng)) = (location.lat, location.lng) else {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "deliveryLocation needs lat/lng"));
+    };
+    let distance_km = haversine_km(r_lat, r_lng, d_lat, d_lng);
+    let rules = load_fee_rules(db, Some(shop)).await?;
+    Ok((distance_km, quote_fee(&rules, distance_km, subtotal, at)))
+}
+
+// Check one order line and price it at `order_at`. Gives the line as stored, the shop it is from, and its total.
+async fn price_line(db: &Database, item: &OrderItemRequest, order_at: &chrono::DateTime<chrono::FixedOffset>) -> Result<(Document, String, i64), (StatusCode, Json<Document>)>{
+    check_quantity(item.quantity.unwrap_or(1))?;
+    let menu_doc = find_menu_item(db, &item.menu_item_id).await?;
+    let Some(menu_doc) = menu_doc else {
+        return Err(error_response(StatusCode::BAD_REQUEST, "menu.unavailable", "menu item unavailable"));
+    };
+    let Some(item_restaurant) = menu_restaurant_id(&menu_doc) else {
+        return Err(error_response(StatusCode::BAD_REQUEST, "menu.unavailable", "menu item unavailable"));
+    };
+
+    let is_available = menu_doc.get_bool("isAvailable").unwrap_or(true) && !menu_doc.get_bool("archived").unwrap_or(false);
+    if !is_available {
+        return Err(error_response(StatusCode::BAD_REQUEST, "menu.unavailable", "menu item unavailable"));
+    }
+    let category = load_category(db, &menu_doc).await;
+    if !item_in_window(&menu_doc, category.as_ref(), order_at) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "menu.unavailable", "menu item not served at this time"));
+    }
+    if let Some(message) = option_problem(&menu_doc, item.size.as_deref(), item.spiciness.as_deref()) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &message));
     }
 
-    if restaurant_id.is_none() {
-        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "restaurantId required"));
+    // charge the price in effect when the order is due, promotions included
+    let (price, _) = resolve_price(&menu_doc, mongodb::bson::DateTime::from_millis(order_at.timestamp_millis()));
+    let quantity = item.quantity.unwrap_or(1);
+    let mut item_doc = Document::new();
+    item_doc.insert("menuItemId", &item.menu_item_id);
+    item_doc.insert("name", get_string(&menu_doc, "name").unwrap_or_default());
+    item_doc.insert("size", item.size.clone().unwrap_or_default());
+    item_doc.insert("spiciness", item.spiciness.clone().unwrap_or_default());
+    item_doc.insert("addDrink", item.add_drink.unwrap_or(false));
+    item_doc.insert("quantity", quantity);
+    item_doc.insert("price", price);
+    item_doc.insert("stockTracked", tracks_stock(&menu_doc));
+    item_doc.insert("options", doc! {
+        "size": item.size.clone(),
+        "spiciness": item.spiciness.clone(),
+        "addDrink": item.add_drink.unwrap_or(false)
+    });
+    item_doc.insert("snapshot", snapshot_menu_item(&menu_doc));
+    if is_bundle(&menu_doc) {
+        item_doc.insert("type", "bundle");
+        item_doc.insert("components", expand_bundle(db, &menu_doc, quantity, order_at).await?);
     }
+    Ok((item_doc, item_restaurant, price * quantity))
+}
+
+// Check every line and the restaurant, and work out what the order costs.
+pub async fn price_order(db: &Database, claims: &Claims, payload: &CreateOrderRequest) -> Result<PricedOrder, (StatusCode, Json<Document>)>{
+    let order_at = order_time(payload.requested_time.as_deref())?;
+    price_order_at(db, claims, payload, order_at).await
+}
 
-    if let Some(rest_id) = restaurant_id.as_deref() {
-        let collection = db.collection::<Document>("shops");
-        if let Some(rest) = collection.find_one(doc! { "id": rest_id }).await
-            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))? {
-            restaurant_name = get_string(&rest, "name");
-            if let (Some(lat), Some(lng)) = (get_f64(&rest, "lat"), get_f64(&rest, "lng")) {
-                restaurant_latlng = Some((lat, lng));
+// price_order for a moment the caller has already settled on.
+async fn price_order_at(db: &Database, claims: &Claims, payload: &CreateOrderRequest, order_at: chrono::DateTime<chrono::FixedOffset>) -> Result<PricedOrder, (StatusCode, Json<Document>)>{
+    if payload.items.is_empty() {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "Order items required"));
+    }
+    let delivery_location = match (payload.delivery_location.as_ref(), payload.address_id.as_deref()) {
+        (_, Some(address_id)) => saved_delivery_location(db, &claims.sub, Some(address_id)).await?,
+        (Some(location), None) => Some(location.clone()),
+        (None, None) => saved_delivery_location(db, &claims.sub, None).await?,
+    };
+    let Some(mut delivery_location) = delivery_location else {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "deliveryLocation or addressId required"));
+    };
+    // the fee depends on distance, so a drop-off needs coordinates; naming a campus spot is enough
+    if delivery_location.lat.is_none() || delivery_location.lng.is_none() {
+        let spot = find_location_by_name(db, &delivery_location.name)
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        let coords = spot.as_ref().and_then(|s| Some((get_f64(s, "lat")?, get_f64(s, "lng")?)));
+        let Some((lat, lng)) = coords else {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "deliveryLocation needs lat/lng or the name of a delivery location"));
+        };
+        delivery_location.lat = Some(lat);
+        delivery_location.lng = Some(lng);
+    }
+    if let (Some(lat), Some(lng)) = (delivery_location.lat, delivery_location.lng) {
+        validate_latlng(lat, lng)?;
+    }
+
+    let mut items: Vec<Bson> = Vec::new();
+    // the shop is the one the items belong to; a restaurantId from the client only has to agree with it
+    let mut restaurant_id: Option<String> = None;
+    let mut subtotal = 0i64;
+
+    for item in &payload.items {
+        let (item_doc, item_restaurant, line_total) = price_line(db, item, &order_at).await?;
+        match restaurant_id.as_deref() {
+            None => restaurant_id = Some(item_restaurant),
+            Some(rest_id) if rest_id != item_restaurant => {
+                return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "all items must come from the same restaurant"));
             }
+            Some(_) => {}
         }
+        subtotal += line_total;
+        items.push(Bson::Document(item_doc));
     }
 
-    let mut location_doc = doc! { "name": &payload.delivery_location.name };
-    if let Some(lat) = payload.delivery_location.lat {
-        location_doc.insert("lat", lat);
-    }
-    if let Some(lng) = payload.delivery_location.lng {
-        location_doc.insert("lng", lng);
+    let restaurant_id = restaurant_id.unwrap_or_default();
+    if payload.restaurant_id.as_deref().is_some_and(|r| !r.is_empty() && r != restaurant_id) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "restaurantId does not match the ordered items"));
     }
 
-    let distance_km = match (restaurant_latlng, payload.delivery_location.lat, payload.delivery_location.lng) {
-        (Some((r_lat, r_lng)), Some(d_lat), Some(d_lng)) => haversine_km(r_lat, r_lng, d_lat, d_lng),
-        _ => 0.0,
-    };
-    let travel_minutes = if distance_km > 0.0 {
-        ((distance_km / WALK_SPEED_KMH) * 60.0).ceil() as i64
-    } else {
-        15
+    let shop = db.collection::<Document>("shops").find_one(doc! { "id": &restaurant_id }).await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let Some(shop) = shop else {
+        return Err(error_response(StatusCode::BAD_REQUEST, "restaurant.unavailable", "restaurant not found"));
     };
-    let eta_minutes = PREP_MINUTES + travel_minutes;
+    if !shop_is_listed(&shop) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "restaurant.unavailable", "restaurant is not taking orders"));
+    }
+    // scheduled orders are judged by the time they are for, not when they are placed
+    if !shop_open_at(&shop, &order_at) {
+        let message = match next_open(&shop, &order_at) {
+            Some(at) => format!("restaurant is closed; next open at {}", at.to_rfc3339()),
+            None => "restaurant is closed".to_string(),
+        };
+        return Err(error_response(StatusCode::BAD_REQUEST, "restaurant.closed", &message));
+    }
+
+    let (distance_km, fee) = delivery_quote(db, &shop, &delivery_location, subtotal, &order_at).await?;
+    let discount = apply_promotion(db, &claims.sub, payload.promo_code.as_deref(), &restaurant_id, subtotal, fee.fee).await?;
+    Ok(PricedOrder {
+        items,
+        restaurant_id,
+        shop,
+        delivery_location,
+        distance_km,
+        subtotal,
+        fee,
+        discount,
+        order_at,
+    })
+}
+
+// Check, price and store an order for the caller; also used to place reorders.
+pub async fn place_order(db: &Database, claims: &Claims, payload: CreateOrderRequest) -> Result<Document, (StatusCode, Json<Document>)>{
+    let order_at = order_time(payload.requested_time.as_deref())?;
+    place_order_at(db, claims, payload, order_at).await
+}
+
+async fn place_order_at(db: &Database, claims: &Claims, payload: CreateOrderRequest, order_at: chrono::DateTime<chrono::FixedOffset>) -> Result<Document, (StatusCode, Json<Document>)>{
+    let priced = price_order_at(db, claims, &payload, order_at).await?;
+    let discount_total = priced.discount_total();
+    let total_amount = priced.total_amount();
+    let PricedOrder { items, restaurant_id, shop, delivery_location, distance_km, subtotal, fee, discount, order_at } = priced;
+    let location_doc = delivery_location.to_document();
+    let eta_minutes = eta_minutes(Some(distance_km));
+
+    // take stock only once every line is valid, and hand it back if anything below fails
+    let mut reserved: Vec<(String, i64)> = Vec::new();
+    let stock_claims: Vec<(String, i64)> = items.iter().filter_map(Bson::as_document).flat_map(line_stock_claims).collect();
+    for (menu_item_id, quantity) in stock_claims {
+        match reserve_stock(db, &menu_item_id, quantity).await {
+            Ok(true) => reserved.push((menu_item_id, quantity)),
+            Ok(false) => {
+                release_reserved(db, &reserved).await;
+                return Err(error_response(StatusCode::BAD_REQUEST, "menu.sold_out", "menu item sold out"));
+            }
+            Err(e) => {
+                release_reserved(db, &reserved).await;
+                return Err(e);
+            }
+        }
+    }
+    // the budget is only taken once stock is held, and given back with it on failure
+    if let Some(discount) = discount.as_ref()
+        && let Err(e) = claim_promotion(db, &claims.sub, discount).await {
+        release_reserved(db, &reserved).await;
+        return Err(e);
+    }
 
     let now = now_datetime();
     let status = "available";
@@ -187,14 +418,21 @@ async fn create_order(State(db): State<Database>, headers: HeaderMap, Json(paylo
 
     let order_id = mongodb::bson::oid::ObjectId::new().to_hex();
     let code = order_id.chars().take(6).collect::<String>().to_uppercase();
-    let customer_info = load_customer(&db, &claims.sub).await.unwrap_or_else(|| {
+    let customer_info = load_customer(db, &claims.sub).await.unwrap_or_else(|| {
         let mut d = Document::new();
         d.insert("id", &claims.sub);
         d
     });
+    let restaurant_name = get_string(&shop, "name");
     let mut merchant_info = Document::new();
     merchant_info.insert("name", restaurant_name.clone().unwrap_or_default());
-    if let Some((lat, lng)) = restaurant_latlng {
+    if let Some(address) = get_string(&shop, "address") {
+        merchant_info.insert("address", address);
+    }
+    if let Some(phone) = get_string(&shop, "phone") {
+        merchant_info.insert("phone", phone);
+    }
+    if let Some((lat, lng)) = doc_latlng(&shop) {
         merchant_info.insert("lat", lat);
         merchant_info.insert("lng", lng);
     }
@@ -207,14 +445,20 @@ async fn create_order(State(db): State<Database>, headers: HeaderMap, Json(paylo
         "merchant": merchant_info,
         "deliveryLocation": location_doc,
         "items": items,
-        "deliveryFee": payload.delivery_fee,
-        "totalAmount": payload.total_amount,
+        "subtotal": subtotal,
+        "deliveryFee": fee.fee,
+        "feeBreakdown": fee.breakdown,
+        "discount": discount.as_ref().map(Discount::to_document),
+        "discountTotal": discount_total,
+        "totalAmount": total_amount,
         "status": status,
         "statusHistory": status_history,
         "notes": payload.notes,
-        "restaurantId": restaurant_id.unwrap_or_default(),
+        "restaurantId": restaurant_id,
         "restaurantName": restaurant_name.unwrap_or_default(),
         "requestedTime": payload.requested_time,
+        // queryable form of requestedTime; the auto-cancel task counts from here
+        "dueAt": mongodb::bson::DateTime::from_millis(order_at.timestamp_millis()),
         "placedAt": now,
         "createdAt": now,
         "etaMinutes": eta_minutes,
@@ -222,15 +466,23 @@ async fn create_order(State(db): State<Database>, headers: HeaderMap, Json(paylo
     };
 
     let orders = db.collection::<Document>("orders");
-    orders.insert_one(order_doc)
-        .await
-        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    if let Err(e) = orders.insert_one(order_doc).await {
+        release_reserved(db, &reserved).await;
+        if let Some(discount) = discount.as_ref()
+            && let Err(e) = release_promotion(db, &discount.promotion_id, &claims.sub, discount.amount).await {
+            eprintln!("orders.release_promotion error: {}", e);
+        }
+        return Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()));
+    }
 
-    Ok(data_response_with_status(StatusCode::CREATED, Bson::Document(doc! {
+    Ok(doc! {
         "id": order_id,
         "status": status,
-        "etaMinutes": eta_minutes
-    })))
+        "etaMinutes": eta_minutes,
+        "deliveryFee": fee.fee,
+        "discountTotal": discount_total,
+        "totalAmount": total_amount
+    })
 }
 
 async fn list_orders(State(db): State<Database>, Query(query): Query<OrderListQuery>, headers: HeaderMap) -> ApiResult{
@@ -265,16 +517,15 @@ async fn list_orders(State(db): State<Database>, Query(query): Query<OrderListQu
         if let Some(placed_at) = doc.get("placedAt").and_then(iso_from_bson) {
             item.insert("placedAt", placed_at);
         }
-        if let Some(rating) = doc.get("rating") {
-            item.insert("rating", rating.clone());
+        if let Ok(rating) = doc.get_document("rating") {
+            item.insert("rating", order_rating_view(rating));
         }
         if let Some(customer) = doc.get("customer") {
             item.insert("customer", customer.clone());
         } else {
-            if let Some(user_id) = get_string(&doc, "userId") {
-                if let Some(c) = load_customer(&db, &user_id).await {
-                    item.insert("customer", c);
-                }
+            if let Some(user_id) = get_string(&doc, "userId")
+                && let Some(c) = load_customer(&db, &user_id).await {
+                item.insert("customer", c);
             }
         }
         orders.push(Bson::Document(item));
@@ -299,16 +550,22 @@ async fn get_order(Path(id): Path<String>, State(db): State<Database>, headers:
     data.insert("id", document_id(&order_doc).unwrap_or_default());
     data.insert("restaurantName", get_string(&order_doc, "restaurantName").unwrap_or_default());
     data.insert("deliveryFee", get_i64(&order_doc, "deliveryFee").unwrap_or(0));
+    if let Ok(breakdown) = order_doc.get_document("feeBreakdown") {
+        data.insert("feeBreakdown", breakdown.clone());
+    }
+    if let Ok(discount) = order_doc.get_document("discount") {
+        data.insert("discount", discount.clone());
+        data.insert("discountTotal", get_i64(&order_doc, "discountTotal").unwrap_or(0));
+    }
     data.insert("totalAmount", get_i64(&order_doc, "totalAmount").unwrap_or(0));
     data.insert("status", get_string(&order_doc, "status").unwrap_or_default());
     data.insert("etaMinutes", get_i64(&order_doc, "etaMinutes").unwrap_or(0));
     let mut rider_name = get_string(&order_doc, "riderName").unwrap_or_default();
     let mut rider_phone = get_string(&order_doc, "riderPhone").unwrap_or_default();
-    if rider_name.is_empty() || rider_phone.is_empty() {
-        if let Some((name, phone)) = load_rider_info(&db, &order_doc).await {
-            if rider_name.is_empty() { rider_name = name; }
-            if rider_phone.is_empty() { rider_phone = phone; }
-        }
+    if (rider_name.is_empty() || rider_phone.is_empty())
+        && let Some((name, phone)) = load_rider_info(&db, &order_doc).await {
+        if rider_name.is_empty() { rider_name = name; }
+        if rider_phone.is_empty() { rider_phone = phone; }
     }
     data.insert("riderName", rider_name);
     data.insert("riderPhone", rider_phone);
@@ -326,19 +583,17 @@ async fn get_order(Path(id): Path<String>, State(db): State<Database>, headers:
     }
     if let Some(customer) = order_doc.get("customer") {
         data.insert("customer", customer.clone());
-    } else if let Some(uid) = get_string(&order_doc, "userId") {
-        if let Some(c) = load_customer(&db, &uid).await {
-            data.insert("customer", c);
-        }
+    } else if let Some(uid) = get_string(&order_doc, "userId")
+        && let Some(c) = load_customer(&db, &uid).await {
+        data.insert("customer", c);
     }
-    if let Some(order_user_id) = get_string(&order_doc, "userId") {
-        if order_user_id != claims.sub {
-            return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
-        }
+    if let Some(order_user_id) = get_string(&order_doc, "userId")
+        && order_user_id != claims.sub {
+        return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
     }
 
-    if let Some(rating) = order_doc.get("rating") {
-        data.insert("rating", rating.clone());
+    if let Ok(rating) = order_doc.get_document("rating") {
+        data.insert("rating", order_rating_view(rating));
     }
 
     if let Ok(items) = order_doc.get_array("items") {
@@ -353,6 +608,15 @@ async fn get_order(Path(id): Path<String>, State(db): State<Database>, headers:
                 out.insert("addDrink", item_doc.get_bool("addDrink").unwrap_or(false));
                 out.insert("quantity", get_i64(item_doc, "quantity").unwrap_or(1));
                 out.insert("price", get_i64(item_doc, "price").unwrap_or(0));
+                if let Some(options) = item_doc.get("options") {
+                    out.insert("options", options.clone());
+                }
+                if let Some(snapshot) = item_doc.get("snapshot") {
+                    out.insert("snapshot", snapshot.clone());
+                }
+                if let Some(components) = item_doc.get("components") {
+                    out.insert("components", components.clone());
+                }
                 out_items.push(Bson::Document(out));
             }
         }
@@ -384,6 +648,10 @@ async fn add_rating(Path(id): Path<String>, State(db): State<Database>, headers:
     if payload.score < 1 || payload.score > 5 {
         return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "score must be 1-5"));
     }
+    if payload.rider_score.is_some_and(|s| !(1..=5).contains(&s)) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "riderScore must be 1-5"));
+    }
+    let tags = normalize_rating_tags(payload.tags.as_deref().unwrap_or_default())?;
     let collection = db.collection::<Document>("orders");
     let existing = collection.find_one(doc! { "id": &id })
         .await
@@ -397,23 +665,214 @@ async fn add_rating(Path(id): Path<String>, State(db): State<Database>, headers:
     if get_string(&order_doc, "status").as_deref() != Some("delivered") {
         return Err(error_response(StatusCode::BAD_REQUEST, "order.conflict", "order not delivered"));
     }
+    let rider_id = get_string(&order_doc, "delivererId").filter(|d| !d.is_empty());
+    if payload.rider_score.is_some() && rider_id.is_none() {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "order has no rider to rate"));
+    }
 
-    let rating_doc = doc! {
-        "score": payload.score,
-        "comment": payload.comment.clone()
-    };
+    check_banned_words(&db, payload.comment.as_deref()).await?;
 
-    let update = doc! { "$set": { "rating": rating_doc } };
-    let result = collection.update_one(doc! { "id": &id }, update)
+    let now = now_datetime();
+    let cutoff = mongodb::bson::DateTime::from_millis(now.timestamp_millis() - rating_edit_window_millis());
+    // first rating, or an edit still inside the window counted from the first rating
+    let filter = doc! {
+        "id": &id,
+        "$or": [
+            { "rating.score": { "$exists": false } },
+            { "rating.createdAt": { "$gte": cutoff } }
+        ]
+    };
+    // field by field, so a restaurant reply on an earlier rating survives
+    let update = doc! {
+        "$set": {
+            "rating.score": payload.score,
+            "rating.riderScore": payload.rider_score,
+            "rating.comment": payload.comment.clone(),
+            "rating.tags": &tags,
+            "rating.updatedAt": now
+        },
+        "$min": { "rating.createdAt": now }
+    };
+    // the document as it was, so a re-rating replaces the earlier score in the summary
+    let previous = collection.find_one_and_update(filter, update)
         .await
         .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
-    if result.matched_count == 0 {
-        return Err(error_response(StatusCode::NOT_FOUND, "order.not_found", "Order not found"));
+    let Some(previous) = previous else {
+        return Err(error_response(StatusCode::CONFLICT, "rating.locked", "rating can no longer be changed"));
+    };
+    if let Some(rest_id) = get_string(&previous, "restaurantId") {
+        // a hidden rating stays hidden, and out of the summary, when it is changed
+        let was_hidden = previous.get_document("rating").is_ok_and(|r| r.get_bool("hidden").unwrap_or(false));
+        let new_score = if was_hidden { None } else { Some(payload.score) };
+        if let Err(e) = adjust_rating_summary(&db, &rest_id, counted_order_score(&previous), new_score).await {
+            eprintln!("orders.add_rating summary error: {}", e);
+        }
     }
 
+    let updated = collection.find_one(doc! { "id": &id })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "order.not_found", "Order not found"))?;
+    let rating = updated.get_document("rating").cloned().unwrap_or_default();
+    Ok(data_response(Bson::Document(order_rating_view(&rating))))
+}
+
+fn line_price(line: &Document) -> Option<i64>{
+    get_f64(line, "price").map(|p| p.round() as i64)
+}
+
+// What stops a past line from being ordered again as it was, if anything.
+async fn reorder_line_change(db: &Database, line: &Document, at: &chrono::DateTime<chrono::FixedOffset>) -> Result<(Option<&'static str>, Option<i64>), (StatusCode, Json<Document>)>{
+    let menu_item_id = get_string(line, "menuItemId").unwrap_or_default();
+    let Some(menu_doc) = find_menu_item(db, &menu_item_id).await? else {
+        return Ok((Some("removed"), None));
+    };
+    if menu_doc.get_bool("archived").unwrap_or(false) {
+        return Ok((Some("removed"), None));
+    }
+    let (price, _) = resolve_price(&menu_doc, mongodb::bson::DateTime::from_millis(at.timestamp_millis()));
+    let category = load_category(db, &menu_doc).await;
+    let quantity = get_i64(line, "quantity").unwrap_or(1);
+    let reason = if !menu_doc.get_bool("isAvailable").unwrap_or(true) || !item_in_window(&menu_doc, category.as_ref(), at) {
+        Some("unavailable")
+    } else if stock_left(&menu_doc).is_some_and(|left| left < quantity) {
+        Some("sold_out")
+    } else if line_price(line) != Some(price) {
+        Some("price_changed")
+    } else {
+        None
+    };
+    Ok((reason, Some(price)))
+}
+
+// POST /orders/{id}/reorder
+// Checks a past order against today's menu. Returns a draft the client can send to POST /orders,
+// listing what changed; with `confirm` and no changes the order is placed directly.
+async fn reorder(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, payload: Option<Json<ReorderRequest>>) -> ApiResult{
+    let claims = require_role(&headers, &["customer"])?;
+    let payload = payload.map(|Json(p)| p).unwrap_or_default();
+    let previous = db.collection::<Document>("orders").find_one(doc! { "id": &id })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "order.not_found", "Order not found"))?;
+    if get_string(&previous, "userId").as_deref() != Some(&claims.sub) {
+        return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
+    }
+
+    // one moment for every check below, so the change list, the draft and a placed order all agree
+    let at = order_time(None)?;
+    let mut lines: Vec<OrderItemRequest> = Vec::new();
+    let mut changes: Vec<Bson> = Vec::new();
+    for line in previous.get_array("items").map(|v| v.as_slice()).unwrap_or_default().iter().filter_map(Bson::as_document) {
+        let menu_item_id = get_string(line, "menuItemId").unwrap_or_default();
+        let old_price = line_price(line);
+        let options = line.get_document("options").ok();
+        let option = |key: &str| get_string(line, key).filter(|v| !v.is_empty()).or_else(|| options.and_then(|o| get_string(o, key)));
+        let request = OrderItemRequest {
+            menu_item_id: menu_item_id.clone(),
+            quantity: Some(get_i64(line, "quantity").unwrap_or(1)),
+            size: option("size"),
+            spiciness: option("spiciness"),
+            add_drink: Some(line.get_bool("addDrink").unwrap_or(false)),
+        };
+        let (mut reason, current) = reorder_line_change(&db, line, &at).await?;
+        let mut message = None;
+        // a line that still looks orderable has to pass every check POST /orders makes, too
+        if matches!(reason, None | Some("price_changed")) {
+            match price_line(&db, &request, &at).await {
+                Ok(_) => {}
+                Err(e) if e.0.is_server_error() => return Err(e),
+                Err((_, Json(body))) => {
+                    reason = Some("unavailable");
+                    message = get_string(&body, "message");
+                }
+            }
+        }
+        if let Some(reason) = reason {
+            changes.push(Bson::Document(doc! {
+                "menuItemId": &menu_item_id,
+                "name": get_string(line, "name"),
+                "reason": reason,
+                "message": message,
+                "oldPrice": old_price,
+                "newPrice": current
+            }));
+        }
+        // lines that can't be ordered at all are left out of the draft
+        if matches!(reason, None | Some("price_changed")) {
+            lines.push(request);
+        }
+    }
+
+    let location = previous.get_document("deliveryLocation").ok();
+    let delivery_location = payload.delivery_location.unwrap_or_else(|| DeliveryLocation {
+        name: location.and_then(|l| get_string(l, "name")).unwrap_or_default(),
+        lat: location.and_then(|l| l.get("lat")).and_then(Bson::as_f64),
+        lng: location.and_then(|l| l.get("lng")).and_then(Bson::as_f64),
+        note: location.and_then(|l| get_string(l, "note")),
+    });
+    let request = CreateOrderRequest {
+        restaurant_id: get_string(&previous, "restaurantId"),
+        delivery_location: Some(delivery_location),
+        address_id: None,
+        items: lines,
+        notes: payload.notes.or_else(|| get_string(&previous, "notes")),
+        requested_time: None,
+        promo_code: None,
+    };
+
+    if payload.confirm == Some(true) && changes.is_empty() && !request.items.is_empty() {
+        let created = place_order_at(&db, &claims, request, at).await?;
+        return Ok(data_response_with_status(StatusCode::CREATED, Bson::Document(doc! {
+            "created": true,
+            "order": created,
+            "changes": changes
+        })));
+    }
+
+    let mut draft = doc! {
+        "restaurantId": &request.restaurant_id,
+        "deliveryLocation": request.delivery_location.as_ref().map(DeliveryLocation::to_document),
+        "items": [],
+        "notes": &request.notes
+    };
+    // priced exactly as POST /orders would charge it today, fees and promotions included;
+    // a problem with the order as a whole, such as the shop being closed, is reported with the draft
+    let priced = if request.items.is_empty() {
+        None
+    } else {
+        match price_order_at(&db, &claims, &request, at).await {
+            Ok(priced) => Some(priced),
+            Err(e) if e.0.is_server_error() => return Err(e),
+            Err((_, Json(body))) => {
+                draft.insert("error", body);
+                None
+            }
+        }
+    };
+    if let Some(priced) = priced {
+        let items: Vec<Bson> = priced.items.iter().filter_map(Bson::as_document).map(|line| Bson::Document(doc! {
+            "menuItemId": get_string(line, "menuItemId"),
+            "name": get_string(line, "name"),
+            "quantity": get_i64(line, "quantity"),
+            "size": get_string(line, "size").filter(|v| !v.is_empty()),
+            "spiciness": get_string(line, "spiciness").filter(|v| !v.is_empty()),
+            "addDrink": line.get_bool("addDrink").unwrap_or(false),
+            "price": get_i64(line, "price")
+        })).collect();
+        draft.insert("items", items);
+        draft.insert("subtotal", priced.subtotal);
+        draft.insert("deliveryFee", priced.fee.fee);
+        draft.insert("feeBreakdown", priced.fee.breakdown.clone());
+        draft.insert("discount", priced.discount.as_ref().map(Discount::to_document));
+        draft.insert("discountTotal", priced.discount_total());
+        draft.insert("totalAmount", priced.total_amount());
+    }
     Ok(data_response(Bson::Document(doc! {
-        "score": payload.score,
-        "comment": payload.comment
+        "created": false,
+        "sourceOrderId": &id,
+        "draft": draft,
+        "changes": changes
     })))
 }
 
@@ -447,6 +906,12 @@ async fn cancel_order(Path(id): Path<String>, State(db): State<Database>, header
     if result.matched_count == 0 {
         return Err(error_response(StatusCode::NOT_FOUND, "order.not_found", "Order not found"));
     }
+    restore_order_stock(&db, &id)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    restore_order_promotion(&db, &id)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
 
     Ok(data_response(Bson::Document(doc! { "status": "cancelled" })))
 }
@@ -460,9 +925,11 @@ async fn stream_orders(headers: HeaderMap) -> Sse<impl futures::Stream<Item = Re
 pub fn orders_router(db: Database) -> Router{
     Router::new()
         .route("/", post(create_order).get(list_orders))
+        .route("/quote", post(quote_order))
         .route("/stream", get(stream_orders))
         .route("/{id}", get(get_order))
         .route("/{id}/rating", post(add_rating))
+        .route("/{id}/reorder", post(reorder))
         .route("/{id}/cancel", patch(cancel_order))
         .with_state(db)
 }
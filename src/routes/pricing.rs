@@ -0,0 +1,141 @@
+use mongodb::{bson::{doc, Bson, Document, DateTime}, Database};
+use futures::stream::TryStreamExt;
+use crate::routes::common::{document_id, get_string, now_datetime, menu_restaurant_id};
+
+pub fn base_price(menu_doc: &Document) -> i64{
+    match menu_doc.get("price") {
+        Some(Bson::Int32(v)) => *v as i64,
+        Some(Bson::Int64(v)) => *v,
+        Some(Bson::Double(v)) => v.round() as i64,
+        Some(Bson::String(s)) => s.parse::<i64>().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+fn entry_price(entry: &Document) -> Option<i64>{
+    match entry.get("price") {
+        Some(Bson::Int32(v)) => Some(*v as i64),
+        Some(Bson::Int64(v)) => Some(*v),
+        _ => None,
+    }
+}
+
+// Latest-starting entry that is running at `at`: promotions (with `endsAt`) or permanent changes.
+fn latest_started(menu_doc: &Document, at: DateTime, promotions: bool) -> Option<&Document>{
+    menu_doc.get_array("priceSchedule").ok()?
+        .iter()
+        .filter_map(Bson::as_document)
+        .filter(|entry| entry_price(entry).is_some())
+        .filter(|entry| entry.get_datetime("startsAt").is_ok_and(|s| *s <= at))
+        .filter(|entry| match entry.get_datetime("endsAt") {
+            Ok(ends) => promotions && *ends > at,
+            Err(_) => !promotions,
+        })
+        .max_by_key(|entry| entry.get_datetime("startsAt").ok().copied())
+}
+
+// Price of an item at `at`. A running promotion beats a scheduled permanent change that has
+// come due, which beats the base price. Returns the promotion entry too when one applies.
+pub fn resolve_price(menu_doc: &Document, at: DateTime) -> (i64, Option<Document>){
+    if let Some(promo) = latest_started(menu_doc, at, true) {
+        return (entry_price(promo).unwrap_or_default(), Some(promo.clone()));
+    }
+    match latest_started(menu_doc, at, false) {
+        Some(change) => (entry_price(change).unwrap_or_default(), None),
+        None => (base_price(menu_doc), None),
+    }
+}
+
+pub async fn record_price_change(db: &Database, menu_doc: &Document, new_price: i64, changed_by: &str, source: &str){
+    let old_price = base_price(menu_doc);
+    if old_price == new_price {
+        return;
+    }
+    let entry = doc! {
+        "menuItemId": document_id(menu_doc).unwrap_or_default(),
+        "restaurantId": menu_restaurant_id(menu_doc).unwrap_or_default(),
+        "oldPrice": old_price,
+        "newPrice": new_price,
+        "changedBy": changed_by,
+        "source": source,
+        "changedAt": now_datetime()
+    };
+    if let Err(e) = db.collection::<Document>("menu_price_history").insert_one(entry).await {
+        eprintln!("pricing.record_price_change error: {}", e);
+    }
+}
+
+// Fold scheduled permanent changes that have come due into `price`, and drop finished promotions.
+pub async fn apply_scheduled_prices(db: &Database) -> mongodb::error::Result<u64>{
+    let menu = db.collection::<Document>("menu");
+    let now = now_datetime();
+    let due = doc! { "priceSchedule": { "$elemMatch": { "endsAt": { "$exists": false }, "startsAt": { "$lte": now } } } };
+    let items: Vec<Document> = menu.find(due).await?.try_collect().await?;
+    let mut applied = 0;
+    for menu_doc in items {
+        // a running promotion only overrides the price; the base moves to the permanent change
+        let Some(change) = latest_started(&menu_doc, now, false) else { continue };
+        let price = entry_price(change).unwrap_or_default();
+        let changed_by = get_string(change, "createdBy").unwrap_or_default();
+        let update = doc! {
+            "$set": { "price": price },
+            "$pull": { "priceSchedule": { "endsAt": { "$exists": false }, "startsAt": { "$lte": now } } }
+        };
+        menu.update_one(doc! { "_id": menu_doc.get("_id").cloned().unwrap_or(Bson::Null) }, update).await?;
+        record_price_change(db, &menu_doc, price, &changed_by, "schedule").await;
+        applied += 1;
+    }
+    menu.update_many(
+        doc! { "priceSchedule.endsAt": { "$lte": now } },
+        doc! { "$pull": { "priceSchedule": { "endsAt": { "$lte": now } } } },
+    ).await?;
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 24 * 60 * 60 * 1000;
+
+    fn day(n: i64) -> DateTime{
+        DateTime::from_millis(n * DAY)
+    }
+
+    #[test]
+    fn base_price_reads_any_stored_number() {
+        assert_eq!(base_price(&doc! { "price": 80_i32 }), 80);
+        assert_eq!(base_price(&doc! { "price": 80.4 }), 80);
+        assert_eq!(base_price(&doc! { "price": "95" }), 95);
+        assert_eq!(base_price(&doc! {}), 0);
+    }
+
+    #[test]
+    fn due_changes_replace_the_base_price() {
+        let item = doc! { "price": 100_i64, "priceSchedule": [
+            { "price": 110_i64, "startsAt": day(10) },
+            { "price": 120_i64, "startsAt": day(20) },
+        ] };
+        assert_eq!(resolve_price(&item, day(5)), (100, None));
+        assert_eq!(resolve_price(&item, day(15)), (110, None));
+        assert_eq!(resolve_price(&item, day(25)), (120, None));
+    }
+
+    #[test]
+    fn running_promotions_beat_changes_until_they_end() {
+        let item = doc! { "price": 100_i64, "priceSchedule": [
+            { "price": 110_i64, "startsAt": day(10) },
+            { "price": 70_i64, "startsAt": day(12), "endsAt": day(14) },
+        ] };
+        let (price, promo) = resolve_price(&item, day(13));
+        assert_eq!(price, 70);
+        assert!(promo.is_some());
+        assert_eq!(resolve_price(&item, day(14)), (110, None));
+    }
+
+    #[test]
+    fn entries_without_a_whole_price_are_ignored() {
+        let item = doc! { "price": 100_i64, "priceSchedule": [{ "price": "cheap", "startsAt": day(1) }] };
+        assert_eq!(resolve_price(&item, day(5)), (100, None));
+    }
+}
@@ -0,0 +1,153 @@
+#![allow(non_snake_case)]
+
+use axum::{Router, routing::get, extract::State, Json, http::HeaderMap};
+use mongodb::{bson::{doc, Bson, Document}, Database};
+use serde::Deserialize;
+use axum::http::StatusCode;
+use std::collections::HashMap;
+use crate::routes::common::{ApiResult, data_response, get_string, require_role, claims_restaurant_id, now_datetime, iso_from_bson, invalid};
+use crate::routes::geo::{doc_latlng, geo_point};
+use crate::routes::hours::{load_shop, update_shop};
+use crate::routes::i18n::{Translation, translation_updates};
+
+pub const MAX_NAME_CHARS: usize = 100;
+pub const MAX_ADDRESS_CHARS: usize = 200;
+pub const MAX_DESCRIPTION_CHARS: usize = 1000;
+const MAX_PHONE_CHARS: usize = 20;
+const MAX_URL_CHARS: usize = 2048;
+
+// Every field is optional; an empty string clears address, phone, description or imageUrl.
+#[derive(Deserialize)]
+struct ProfilePatch {
+    name: Option<String>,
+    address: Option<String>,
+    phone: Option<String>,
+    imageUrl: Option<String>,
+    description: Option<String>,
+    lat: Option<f64>,
+    lng: Option<f64>,
+    translations: Option<HashMap<String, Translation>>,
+}
+
+pub fn valid_phone(phone: &str) -> bool{
+    let digits = phone.chars().filter(char::is_ascii_digit).count();
+    (6..=15).contains(&digits) && phone.chars().all(|c| c.is_ascii_digit() || "+-() ".contains(c))
+}
+
+// http(s) links, or paths to files this server stores
+pub fn valid_url(url: &str) -> bool{
+    (url.starts_with("https://") || url.starts_with("http://") || url.starts_with('/')) && !url.contains(char::is_whitespace)
+}
+
+pub fn validate_latlng(lat: f64, lng: f64) -> Result<(), (StatusCode, Json<Document>)>{
+    if !lat.is_finite() || !(-90.0..=90.0).contains(&lat) {
+        return Err(invalid("lat must be between -90 and 90"));
+    }
+    if !lng.is_finite() || !(-180.0..=180.0).contains(&lng) {
+        return Err(invalid("lng must be between -180 and 180"));
+    }
+    Ok(())
+}
+
+// Put a trimmed optional text field into `$set`, or into `$unset` when it is blank.
+fn text_update(field: &str, value: &str, max_chars: usize, set: &mut Document, unset: &mut Document) -> Result<(), (StatusCode, Json<Document>)>{
+    let value = value.trim();
+    if value.chars().count() > max_chars {
+        return Err(invalid(&format!("{} must be at most {} characters", field, max_chars)));
+    }
+    if value.is_empty() {
+        unset.insert(field, "");
+    } else {
+        set.insert(field, value);
+    }
+    Ok(())
+}
+
+fn profile_view(shop: &Document) -> Document{
+    let latlng = doc_latlng(shop);
+    doc! {
+        "id": get_string(shop, "id"),
+        "name": get_string(shop, "name"),
+        "description": get_string(shop, "description"),
+        "address": get_string(shop, "address"),
+        "phone": get_string(shop, "phone"),
+        "imageUrl": get_string(shop, "imageUrl"),
+        "thumbnailUrl": get_string(shop, "thumbnailUrl"),
+        "lat": latlng.map(|(lat, _)| lat),
+        "lng": latlng.map(|(_, lng)| lng),
+        "translations": shop.get("translations").cloned().unwrap_or_else(|| Bson::Document(Document::new())),
+        "updatedAt": shop.get("updatedAt").and_then(iso_from_bson)
+    }
+}
+
+// GET /restaurant/profile
+async fn get_profile(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let shop = load_shop(&db, &claims_restaurant_id(&claims)).await?;
+    Ok(data_response(Bson::Document(profile_view(&shop))))
+}
+
+// PATCH /restaurant/profile
+async fn update_profile(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<ProfilePatch>) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let mut set = Document::new();
+    let mut unset = Document::new();
+
+    if let Some(name) = payload.name.as_deref().map(str::trim) {
+        if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+            return Err(invalid(&format!("name must be 1-{} characters", MAX_NAME_CHARS)));
+        }
+        set.insert("name", name);
+    }
+    if let Some(address) = &payload.address {
+        text_update("address", address, MAX_ADDRESS_CHARS, &mut set, &mut unset)?;
+    }
+    if let Some(description) = &payload.description {
+        text_update("description", description, MAX_DESCRIPTION_CHARS, &mut set, &mut unset)?;
+    }
+    if let Some(phone) = payload.phone.as_deref().map(str::trim) {
+        if !phone.is_empty() && !valid_phone(phone) {
+            return Err(invalid("phone must contain 6-15 digits and only digits, spaces, +, - or parentheses"));
+        }
+        text_update("phone", phone, MAX_PHONE_CHARS, &mut set, &mut unset)?;
+    }
+    if let Some(url) = payload.imageUrl.as_deref().map(str::trim) {
+        if !url.is_empty() && !valid_url(url) {
+            return Err(invalid("imageUrl must be an http(s) URL or an uploaded file path"));
+        }
+        text_update("imageUrl", url, MAX_URL_CHARS, &mut set, &mut unset)?;
+        // the stored thumbnail belongs to the old image
+        unset.insert("thumbnailUrl", "");
+    }
+    match (payload.lat, payload.lng) {
+        (None, None) => {}
+        (Some(lat), Some(lng)) => {
+            validate_latlng(lat, lng)?;
+            set.insert("lat", lat);
+            set.insert("lng", lng);
+            // keep the GeoJSON point used by the nearby queries in step
+            set.insert("location", geo_point(lat, lng));
+        }
+        _ => return Err(invalid("lat and lng must be given together")),
+    }
+    if let Some(translations) = &payload.translations {
+        translation_updates(translations, &mut set, &mut unset)?;
+    }
+    if set.is_empty() && unset.is_empty() {
+        return Err(invalid("No fields to update"));
+    }
+
+    set.insert("updatedAt", now_datetime());
+    let mut update = doc! { "$set": set };
+    if !unset.is_empty() {
+        update.insert("$unset", unset);
+    }
+    let shop = update_shop(&db, &claims_restaurant_id(&claims), update).await?;
+    Ok(data_response(Bson::Document(profile_view(&shop))))
+}
+
+pub fn profile_router(db: Database) -> Router{
+    Router::new()
+        .route("/profile", get(get_profile).patch(update_profile))
+        .with_state(db)
+}
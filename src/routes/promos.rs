@@ -0,0 +1,691 @@
+#![allow(non_snake_case)]
+
+use axum::{Router, routing::{get, patch}, extract::{State, Path, Query}, Json, http::HeaderMap};
+use mongodb::{bson::{doc, Bson, DateTime, Document}, Database};
+use futures::stream::TryStreamExt;
+use serde::Deserialize;
+use axum::http::StatusCode;
+use std::collections::BTreeMap;
+use crate::routes::common::{ApiResult, data_response, data_response_with_status, error_response, get_string, get_bool, get_i64, iso_from_bson, require_role, now_datetime, date_range_to_bson, is_duplicate_key};
+use crate::routes::restaurant::parse_instant;
+
+// Promotions are discounts applied while an order is priced. One with a `code` only applies when the
+// customer enters it; ones without a code are automatic, and the best automatic one is picked per order.
+// `promotions`: { id, code?, name, kind: percent | amount | free_delivery, value, maxDiscount?, restaurantId?,
+// minSubtotal, startsAt, endsAt?, perUserLimit?, firstOrderOnly, budget?, spent, usedCount, enabled }.
+// Orders keep a `discount` snapshot; its amount goes back to the budget when the order is cancelled.
+// `promotion_redemptions` counts each customer's uses of a limited promotion, keyed `<promotionId>:<userId>`.
+
+const KINDS: [&str; 3] = ["percent", "amount", "free_delivery"];
+const MAX_NAME_CHARS: usize = 100;
+const MAX_CODE_CHARS: usize = 32;
+
+#[derive(Deserialize)]
+struct PromotionRequest {
+    // leave out for an automatic promotion
+    code: Option<String>,
+    name: String,
+    kind: String,
+    // percent off the subtotal, or TWD off; unused for free_delivery
+    value: Option<i64>,
+    maxDiscount: Option<i64>,
+    restaurantId: Option<String>,
+    minSubtotal: Option<i64>,
+    startsAt: Option<String>,
+    endsAt: Option<String>,
+    perUserLimit: Option<i64>,
+    firstOrderOnly: Option<bool>,
+    budget: Option<i64>,
+    enabled: Option<bool>,
+}
+
+// What a running campaign may still change; kind and value stay fixed so the report stays meaningful.
+#[derive(Deserialize)]
+struct PromotionUpdateRequest {
+    name: Option<String>,
+    minSubtotal: Option<i64>,
+    startsAt: Option<String>,
+    endsAt: Option<String>,
+    perUserLimit: Option<i64>,
+    budget: Option<i64>,
+    enabled: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ActivePromotionsQuery {
+    restaurantId: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PromotionReportQuery {
+    // YYYY-MM-DD, both required to filter
+    from: Option<String>,
+    to: Option<String>,
+}
+
+pub struct Discount {
+    pub promotion_id: String,
+    pub code: Option<String>,
+    pub name: String,
+    pub kind: String,
+    pub amount: i64,
+    // uses allowed per customer; first-order promotions allow one
+    pub per_user_limit: Option<i64>,
+}
+
+impl Discount {
+    pub fn to_document(&self) -> Document{
+        doc! {
+            "promotionId": &self.promotion_id,
+            "code": &self.code,
+            "name": &self.name,
+            "kind": &self.kind,
+            "amount": self.amount
+        }
+    }
+}
+
+fn normalize_code(code: &str) -> String{
+    code.trim().to_uppercase()
+}
+
+fn valid_code(code: &str) -> bool{
+    (3..=MAX_CODE_CHARS).contains(&code.chars().count())
+        && code.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn promotion_view(promo: &Document) -> Document{
+    let budget = get_i64(promo, "budget");
+    let spent = get_i64(promo, "spent").unwrap_or(0);
+    doc! {
+        "id": get_string(promo, "id"),
+        "code": get_string(promo, "code"),
+        "name": get_string(promo, "name"),
+        "kind": get_string(promo, "kind"),
+        "value": get_i64(promo, "value"),
+        "maxDiscount": get_i64(promo, "maxDiscount"),
+        "restaurantId": get_string(promo, "restaurantId"),
+        "minSubtotal": get_i64(promo, "minSubtotal").unwrap_or(0),
+        "startsAt": promo.get("startsAt").and_then(iso_from_bson),
+        "endsAt": promo.get("endsAt").and_then(iso_from_bson),
+        "perUserLimit": get_i64(promo, "perUserLimit"),
+        "firstOrderOnly": get_bool(promo, "firstOrderOnly").unwrap_or(false),
+        "budget": budget,
+        "spent": spent,
+        "remainingBudget": budget.map(|b| (b - spent).max(0)),
+        "usedCount": get_i64(promo, "usedCount").unwrap_or(0),
+        "enabled": get_bool(promo, "enabled").unwrap_or(true)
+    }
+}
+
+// What the customer sees: no budget or usage figures.
+fn public_promotion_view(promo: &Document) -> Document{
+    doc! {
+        "id": get_string(promo, "id"),
+        "name": get_string(promo, "name"),
+        "kind": get_string(promo, "kind"),
+        "value": get_i64(promo, "value"),
+        "maxDiscount": get_i64(promo, "maxDiscount"),
+        "restaurantId": get_string(promo, "restaurantId"),
+        "minSubtotal": get_i64(promo, "minSubtotal").unwrap_or(0),
+        "endsAt": promo.get("endsAt").and_then(iso_from_bson),
+        "firstOrderOnly": get_bool(promo, "firstOrderOnly").unwrap_or(false)
+    }
+}
+
+fn discount_amount(promo: &Document, subtotal: i64, delivery_fee: i64) -> i64{
+    let value = get_i64(promo, "value").unwrap_or(0);
+    let amount = match get_string(promo, "kind").as_deref() {
+        Some("percent") => (subtotal as f64 * value as f64 / 100.0).round() as i64,
+        Some("amount") => value.min(subtotal),
+        Some("free_delivery") => delivery_fee,
+        _ => 0,
+    };
+    match get_i64(promo, "maxDiscount") {
+        Some(max) => amount.min(max),
+        None => amount,
+    }
+}
+
+// Automatic promotions that are switched on and running now, for `restaurant_id` or for every shop.
+fn running_filter(restaurant_id: Option<&str>) -> Document{
+    let now = now_datetime();
+    let mut filter = doc! {
+        "code": Bson::Null,
+        "enabled": { "$ne": false },
+        "startsAt": { "$lte": now },
+        "$or": [{ "endsAt": Bson::Null }, { "endsAt": { "$gt": now } }]
+    };
+    if let Some(restaurant_id) = restaurant_id {
+        filter.insert("restaurantId", doc! { "$in": [Bson::Null, Bson::String(restaurant_id.to_string())] });
+    }
+    filter
+}
+
+// Why `promo` can't be used on this order, if it can't.
+async fn ineligible_reason(db: &Database, promo: &Document, user_id: &str, restaurant_id: &str, subtotal: i64, amount: i64) -> Result<Option<String>, (StatusCode, Json<Document>)>{
+    let now = now_datetime();
+    if get_string(promo, "restaurantId").is_some_and(|id| id != restaurant_id) {
+        return Ok(Some("promotion is not valid at this restaurant".to_string()));
+    }
+    let started = promo.get_datetime("startsAt").is_ok_and(|s| *s <= now);
+    let ended = promo.get_datetime("endsAt").is_ok_and(|e| *e <= now);
+    if !started || ended {
+        return Ok(Some("promotion is not running".to_string()));
+    }
+    let min_subtotal = get_i64(promo, "minSubtotal").unwrap_or(0);
+    if subtotal < min_subtotal {
+        return Ok(Some(format!("spend at least {} to use this promotion", min_subtotal)));
+    }
+    if amount <= 0 {
+        return Ok(Some("promotion gives no discount on this order".to_string()));
+    }
+    if get_i64(promo, "budget").is_some_and(|budget| budget - get_i64(promo, "spent").unwrap_or(0) < amount) {
+        return Ok(Some("promotion budget has run out".to_string()));
+    }
+    let orders = db.collection::<Document>("orders");
+    if get_bool(promo, "firstOrderOnly").unwrap_or(false) {
+        let placed = orders.count_documents(doc! { "userId": user_id, "status": { "$ne": "cancelled" } })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        if placed > 0 {
+            return Ok(Some("promotion is for first orders only".to_string()));
+        }
+    }
+    if let Some(limit) = get_i64(promo, "perUserLimit") {
+        let used = orders.count_documents(doc! {
+            "userId": user_id,
+            "discount.promotionId": get_string(promo, "id"),
+            "status": { "$ne": "cancelled" }
+        })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        if used as i64 >= limit {
+            return Ok(Some("promotion has already been used".to_string()));
+        }
+    }
+    Ok(None)
+}
+
+fn per_user_limit(promo: &Document) -> Option<i64>{
+    let limit = get_i64(promo, "perUserLimit");
+    if get_bool(promo, "firstOrderOnly").unwrap_or(false) {
+        return Some(limit.map_or(1, |l| l.min(1)));
+    }
+    limit
+}
+
+fn redemption_key(promotion_id: &str, user_id: &str) -> String{
+    format!("{}:{}", promotion_id, user_id)
+}
+
+fn discount_from(promo: &Document, amount: i64) -> Discount{
+    Discount {
+        promotion_id: get_string(promo, "id").unwrap_or_default(),
+        code: get_string(promo, "code"),
+        name: get_string(promo, "name").unwrap_or_default(),
+        kind: get_string(promo, "kind").unwrap_or_default(),
+        amount,
+        per_user_limit: per_user_limit(promo),
+    }
+}
+
+// The discount for an order: the entered code (which must apply), otherwise the best automatic promotion.
+// Discounts don't stack.
+pub async fn apply_promotion(db: &Database, user_id: &str, code: Option<&str>, restaurant_id: &str, subtotal: i64, delivery_fee: i64) -> Result<Option<Discount>, (StatusCode, Json<Document>)>{
+    let collection = db.collection::<Document>("promotions");
+    if let Some(code) = code.map(normalize_code).filter(|c| !c.is_empty()) {
+        let promo = collection.find_one(doc! { "code": &code, "enabled": { "$ne": false } })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+            .ok_or_else(|| error_response(StatusCode::BAD_REQUEST, "promo.not_found", "promo code not found"))?;
+        let amount = discount_amount(&promo, subtotal, delivery_fee);
+        if let Some(reason) = ineligible_reason(db, &promo, user_id, restaurant_id, subtotal, amount).await? {
+            return Err(error_response(StatusCode::BAD_REQUEST, "promo.not_applicable", &reason));
+        }
+        return Ok(Some(discount_from(&promo, amount)));
+    }
+
+    let candidates: Vec<Document> = collection.find(running_filter(Some(restaurant_id)))
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .try_collect()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let mut best: Option<Discount> = None;
+    for promo in &candidates {
+        let amount = discount_amount(promo, subtotal, delivery_fee);
+        if best.as_ref().is_some_and(|b| b.amount >= amount) {
+            continue;
+        }
+        if ineligible_reason(db, promo, user_id, restaurant_id, subtotal, amount).await?.is_none() {
+            best = Some(discount_from(promo, amount));
+        }
+    }
+    Ok(best)
+}
+
+// Count one use by `user_id` against the per-customer limit; false once the limit is reached.
+// The upsert can't match a full counter, so it tries to insert a second doc with the same key and fails.
+async fn claim_redemption(db: &Database, promotion_id: &str, user_id: &str, limit: i64) -> Result<bool, (StatusCode, Json<Document>)>{
+    let result = db.collection::<Document>("promotion_redemptions")
+        .update_one(
+            doc! { "_id": redemption_key(promotion_id, user_id), "count": { "$lt": limit } },
+            doc! {
+                "$inc": { "count": 1_i64 },
+                "$set": { "promotionId": promotion_id, "userId": user_id, "updatedAt": now_datetime() }
+            },
+        )
+        .upsert(true)
+        .await;
+    match result {
+        Ok(_) => Ok(true),
+        Err(e) if is_duplicate_key(&e) => Ok(false),
+        Err(e) => Err(error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string())),
+    }
+}
+
+async fn release_redemption(db: &Database, promotion_id: &str, user_id: &str) -> mongodb::error::Result<()>{
+    db.collection::<Document>("promotion_redemptions")
+        .update_one(
+            doc! { "_id": redemption_key(promotion_id, user_id), "count": { "$gt": 0 } },
+            doc! { "$inc": { "count": -1_i64 } },
+        )
+        .await?;
+    Ok(())
+}
+
+// Take one of `user_id`'s uses and the discount out of the promotion's budget, both atomically,
+// so concurrent checkouts can't go past either limit.
+pub async fn claim_promotion(db: &Database, user_id: &str, discount: &Discount) -> Result<(), (StatusCode, Json<Document>)>{
+    if let Some(limit) = discount.per_user_limit
+        && !claim_redemption(db, &discount.promotion_id, user_id, limit).await? {
+        return Err(error_response(StatusCode::CONFLICT, "promo.not_applicable", "promotion has already been used"));
+    }
+    let claimed = db.collection::<Document>("promotions")
+        .find_one_and_update(
+            doc! {
+                "id": &discount.promotion_id,
+                "enabled": { "$ne": false },
+                "$or": [
+                    { "budget": Bson::Null },
+                    { "$expr": { "$lte": [{ "$add": [{ "$ifNull": ["$spent", 0] }, discount.amount] }, "$budget"] } }
+                ]
+            },
+            doc! { "$inc": { "spent": discount.amount, "usedCount": 1_i64 } },
+        )
+        .await;
+    let exhausted = match claimed {
+        Ok(found) => found.is_none().then(|| error_response(StatusCode::CONFLICT, "promo.exhausted", "promotion budget has run out")),
+        Err(e) => Some(error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string())),
+    };
+    if let Some(error) = exhausted {
+        if discount.per_user_limit.is_some()
+            && let Err(e) = release_redemption(db, &discount.promotion_id, user_id).await {
+            eprintln!("promos.release_redemption error: {}", e);
+        }
+        return Err(error);
+    }
+    Ok(())
+}
+
+// Undo `claim_promotion`.
+pub async fn release_promotion(db: &Database, promotion_id: &str, user_id: &str, amount: i64) -> mongodb::error::Result<()>{
+    db.collection::<Document>("promotions")
+        .update_one(doc! { "id": promotion_id }, doc! { "$inc": { "spent": -amount, "usedCount": -1_i64 } })
+        .await?;
+    release_redemption(db, promotion_id, user_id).await
+}
+
+// Give back the discount of a cancelled order to its promotion. Safe to call more than once per order.
+pub async fn restore_order_promotion(db: &Database, order_id: &str) -> mongodb::error::Result<()>{
+    let claimed = db.collection::<Document>("orders").find_one_and_update(
+        doc! { "id": order_id, "discount.promotionId": { "$exists": true }, "promotionRestored": { "$ne": true } },
+        doc! { "$set": { "promotionRestored": true } },
+    ).await?;
+    let Some(order_doc) = claimed else {
+        return Ok(());
+    };
+    let user_id = get_string(&order_doc, "userId").unwrap_or_default();
+    let Ok(discount) = order_doc.get_document("discount") else {
+        return Ok(());
+    };
+    if let Some(promotion_id) = get_string(discount, "promotionId") {
+        release_promotion(db, &promotion_id, &user_id, get_i64(discount, "amount").unwrap_or(0)).await?;
+    }
+    Ok(())
+}
+
+// GET /promotions?restaurantId=
+// Automatic promotions running now, for banners; codes are never listed.
+async fn list_active_promotions(State(db): State<Database>, Query(query): Query<ActivePromotionsQuery>) -> ApiResult{
+    let promos: Vec<Document> = db.collection::<Document>("promotions").find(running_filter(query.restaurantId.as_deref()))
+        .sort(doc! { "endsAt": 1 })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .try_collect()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let items: Vec<Bson> = promos.iter()
+        .filter(|p| get_i64(p, "budget").is_none_or(|b| b > get_i64(p, "spent").unwrap_or(0)))
+        .map(|p| Bson::Document(public_promotion_view(p)))
+        .collect();
+    Ok(data_response(Bson::Array(items)))
+}
+
+// GET /admin/promotions
+async fn list_promotions(State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    let promos: Vec<Document> = db.collection::<Document>("promotions").find(doc! {})
+        .sort(doc! { "createdAt": -1 })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .try_collect()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let items: Vec<Bson> = promos.iter().map(|p| Bson::Document(promotion_view(p))).collect();
+    Ok(data_response(Bson::Array(items)))
+}
+
+fn check_name(name: &str) -> Result<String, (StatusCode, Json<Document>)>{
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("name must be 1-{} characters", MAX_NAME_CHARS)));
+    }
+    Ok(name.to_string())
+}
+
+fn check_positive(value: Option<i64>, field: &str) -> Result<(), (StatusCode, Json<Document>)>{
+    if value.is_some_and(|v| v < 1) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("{} must be at least 1", field)));
+    }
+    Ok(())
+}
+
+fn check_window(starts_at: DateTime, ends_at: Option<DateTime>) -> Result<(), (StatusCode, Json<Document>)>{
+    if ends_at.is_some_and(|e| e <= starts_at) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "endsAt must be after startsAt"));
+    }
+    Ok(())
+}
+
+// POST /admin/promotions
+async fn create_promotion(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<PromotionRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["admin"])?;
+    let name = check_name(&payload.name)?;
+    if !KINDS.contains(&payload.kind.as_str()) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "kind must be percent, amount or free_delivery"));
+    }
+    let value = match payload.kind.as_str() {
+        "percent" => match payload.value {
+            Some(v) if (1..=100).contains(&v) => Some(v),
+            _ => return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "value must be 1-100 for percent")),
+        },
+        "amount" => match payload.value {
+            Some(v) if v >= 1 => Some(v),
+            _ => return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "value must be at least 1 for amount")),
+        },
+        _ => None,
+    };
+    check_positive(payload.maxDiscount, "maxDiscount")?;
+    check_positive(payload.perUserLimit, "perUserLimit")?;
+    check_positive(payload.budget, "budget")?;
+    if payload.minSubtotal.is_some_and(|v| v < 0) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "minSubtotal must not be negative"));
+    }
+    let starts_at = match payload.startsAt.as_deref() {
+        Some(value) => parse_instant(value, "startsAt")?,
+        None => now_datetime(),
+    };
+    let ends_at = payload.endsAt.as_deref().map(|v| parse_instant(v, "endsAt")).transpose()?;
+    check_window(starts_at, ends_at)?;
+
+    let collection = db.collection::<Document>("promotions");
+    let code = payload.code.as_deref().map(normalize_code).filter(|c| !c.is_empty());
+    if let Some(code) = code.as_deref() {
+        if !valid_code(code) {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("code must be 3-{} letters, digits, - or _", MAX_CODE_CHARS)));
+        }
+        let taken = collection.find_one(doc! { "code": code })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        if taken.is_some() {
+            return Err(error_response(StatusCode::CONFLICT, "promo.code_taken", "promo code already exists"));
+        }
+    }
+    if let Some(restaurant_id) = payload.restaurantId.as_deref() {
+        let shop = db.collection::<Document>("shops").find_one(doc! { "id": restaurant_id })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        if shop.is_none() {
+            return Err(error_response(StatusCode::NOT_FOUND, "restaurant.not_found", "Restaurant not found"));
+        }
+    }
+
+    let mut promo = doc! {
+        "id": mongodb::bson::oid::ObjectId::new().to_hex(),
+        "name": name,
+        "kind": &payload.kind,
+        "minSubtotal": payload.minSubtotal.unwrap_or(0),
+        "startsAt": starts_at,
+        "firstOrderOnly": payload.firstOrderOnly.unwrap_or(false),
+        "spent": 0_i64,
+        "usedCount": 0_i64,
+        "enabled": payload.enabled.unwrap_or(true),
+        "createdAt": now_datetime(),
+        "createdBy": &claims.sub
+    };
+    // optional rules are left out rather than stored as null
+    let optional: [(&str, Option<Bson>); 7] = [
+        ("code", code.map(Bson::String)),
+        ("value", value.map(Bson::Int64)),
+        ("maxDiscount", payload.maxDiscount.map(Bson::Int64)),
+        ("restaurantId", payload.restaurantId.map(Bson::String)),
+        ("endsAt", ends_at.map(Bson::DateTime)),
+        ("perUserLimit", payload.perUserLimit.map(Bson::Int64)),
+        ("budget", payload.budget.map(Bson::Int64)),
+    ];
+    for (key, value) in optional {
+        if let Some(value) = value {
+            promo.insert(key, value);
+        }
+    }
+    collection.insert_one(&promo)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(data_response_with_status(StatusCode::CREATED, Bson::Document(promotion_view(&promo))))
+}
+
+// PATCH /admin/promotions/{id}
+async fn update_promotion(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<PromotionUpdateRequest>) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    check_positive(payload.perUserLimit, "perUserLimit")?;
+    check_positive(payload.budget, "budget")?;
+    if payload.minSubtotal.is_some_and(|v| v < 0) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "minSubtotal must not be negative"));
+    }
+    let mut set = Document::new();
+    if let Some(name) = payload.name.as_deref() {
+        set.insert("name", check_name(name)?);
+    }
+    if let Some(value) = payload.minSubtotal {
+        set.insert("minSubtotal", value);
+    }
+    let starts_at = payload.startsAt.as_deref().map(|v| parse_instant(v, "startsAt")).transpose()?;
+    let ends_at = payload.endsAt.as_deref().map(|v| parse_instant(v, "endsAt")).transpose()?;
+    // the window is checked against whatever half isn't being changed, in the update's own filter
+    let mut filter = doc! { "id": &id };
+    match (starts_at, ends_at) {
+        (Some(starts_at), ends_at) => {
+            check_window(starts_at, ends_at)?;
+            set.insert("startsAt", starts_at);
+            if let Some(ends_at) = ends_at {
+                set.insert("endsAt", ends_at);
+            } else {
+                filter.insert("$or", vec![
+                    Bson::Document(doc! { "endsAt": Bson::Null }),
+                    Bson::Document(doc! { "endsAt": { "$gt": starts_at } }),
+                ]);
+            }
+        }
+        (None, Some(ends_at)) => {
+            set.insert("endsAt", ends_at);
+            filter.insert("startsAt", doc! { "$lt": ends_at });
+        }
+        (None, None) => {}
+    }
+    if let Some(value) = payload.perUserLimit {
+        set.insert("perUserLimit", value);
+    }
+    if let Some(value) = payload.budget {
+        set.insert("budget", value);
+    }
+    if let Some(value) = payload.enabled {
+        set.insert("enabled", value);
+    }
+    if set.is_empty() {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "nothing to update"));
+    }
+    set.insert("updatedAt", now_datetime());
+    let collection = db.collection::<Document>("promotions");
+    let updated = collection
+        .find_one_and_update(filter, doc! { "$set": set })
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let Some(updated) = updated else {
+        let exists = collection.find_one(doc! { "id": &id })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+            .is_some();
+        return Err(if exists {
+            error_response(StatusCode::BAD_REQUEST, "validation.failed", "endsAt must be after startsAt")
+        } else {
+            error_response(StatusCode::NOT_FOUND, "promo.not_found", "Promotion not found")
+        });
+    };
+    Ok(data_response(Bson::Document(promotion_view(&updated))))
+}
+
+// GET /admin/promotions/report?from=&to=
+// What discounts cost, per restaurant and per promotion, over orders that weren't cancelled.
+async fn promotion_report(State(db): State<Database>, headers: HeaderMap, Query(query): Query<PromotionReportQuery>) -> ApiResult{
+    require_role(&headers, &["admin"])?;
+    let mut matched = doc! { "discount.promotionId": { "$exists": true }, "status": { "$ne": "cancelled" } };
+    if let Some((start, end)) = date_range_to_bson(query.from.as_deref(), query.to.as_deref()) {
+        matched.insert("createdAt", doc! { "$gte": start, "$lte": end });
+    }
+    let pipeline = vec![
+        doc! { "$match": matched },
+        doc! { "$group": {
+            "_id": { "restaurantId": "$restaurantId", "promotionId": "$discount.promotionId" },
+            "restaurantName": { "$first": "$restaurantName" },
+            "code": { "$first": "$discount.code" },
+            "name": { "$first": "$discount.name" },
+            "orderCount": { "$sum": 1_i64 },
+            "discountTotal": { "$sum": "$discount.amount" }
+        } },
+    ];
+    let rows: Vec<Document> = db.collection::<Document>("orders").aggregate(pipeline)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .try_collect()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+
+    // restaurant id -> (name, order count, discount total, promotions)
+    let mut restaurants: BTreeMap<String, (Option<String>, i64, i64, Vec<Bson>)> = BTreeMap::new();
+    for row in &rows {
+        let key = row.get_document("_id").ok();
+        let restaurant_id = key.and_then(|k| get_string(k, "restaurantId")).unwrap_or_default();
+        let order_count = get_i64(row, "orderCount").unwrap_or(0);
+        let discount_total = get_i64(row, "discountTotal").unwrap_or(0);
+        let entry = restaurants.entry(restaurant_id).or_insert((get_string(row, "restaurantName"), 0, 0, Vec::new()));
+        entry.1 += order_count;
+        entry.2 += discount_total;
+        entry.3.push(Bson::Document(doc! {
+            "promotionId": key.and_then(|k| get_string(k, "promotionId")),
+            "code": get_string(row, "code"),
+            "name": get_string(row, "name"),
+            "orderCount": order_count,
+            "discountTotal": discount_total
+        }));
+    }
+    let total: i64 = restaurants.values().map(|r| r.2).sum();
+    let mut items: Vec<Document> = restaurants.into_iter().map(|(restaurant_id, (name, order_count, discount_total, promotions))| doc! {
+        "restaurantId": restaurant_id,
+        "restaurantName": name,
+        "orderCount": order_count,
+        "discountTotal": discount_total,
+        "promotions": promotions
+    }).collect();
+    items.sort_by_key(|r| std::cmp::Reverse(get_i64(r, "discountTotal").unwrap_or(0)));
+    Ok(data_response(Bson::Document(doc! {
+        "from": query.from,
+        "to": query.to,
+        "discountTotal": total,
+        "restaurants": items
+    })))
+}
+
+pub fn promotions_router(db: Database) -> Router{
+    Router::new()
+        .route("/", get(list_active_promotions))
+        .with_state(db)
+}
+
+pub fn promotions_admin_router(db: Database) -> Router{
+    Router::new()
+        .route("/promotions", get(list_promotions).post(create_promotion))
+        .route("/promotions/report", get(promotion_report))
+        .route("/promotions/{id}", patch(update_promotion))
+        .with_state(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_discounts_are_rounded_to_whole_dollars() {
+        let promo = doc! { "kind": "percent", "value": 15_i64 };
+        // 15% of 130 is 19.5
+        assert_eq!(discount_amount(&promo, 130, 20), 20);
+    }
+
+    #[test]
+    fn amount_discounts_never_exceed_the_subtotal() {
+        let promo = doc! { "kind": "amount", "value": 50_i64 };
+        assert_eq!(discount_amount(&promo, 200, 20), 50);
+        assert_eq!(discount_amount(&promo, 30, 20), 30);
+    }
+
+    #[test]
+    fn free_delivery_covers_the_delivery_fee() {
+        let promo = doc! { "kind": "free_delivery" };
+        assert_eq!(discount_amount(&promo, 200, 35), 35);
+        assert_eq!(discount_amount(&promo, 200, 0), 0);
+    }
+
+    #[test]
+    fn max_discount_caps_any_kind() {
+        let promo = doc! { "kind": "percent", "value": 50_i64, "maxDiscount": 60_i64 };
+        assert_eq!(discount_amount(&promo, 400, 20), 60);
+        let promo = doc! { "kind": "free_delivery", "maxDiscount": 15_i64 };
+        assert_eq!(discount_amount(&promo, 400, 20), 15);
+    }
+
+    #[test]
+    fn unknown_kinds_give_nothing() {
+        assert_eq!(discount_amount(&doc! { "kind": "bogo", "value": 10_i64 }, 200, 20), 0);
+    }
+
+    #[test]
+    fn window_must_end_after_it_starts() {
+        let starts = DateTime::from_millis(1_000_000);
+        assert!(check_window(starts, None).is_ok());
+        assert!(check_window(starts, Some(DateTime::from_millis(2_000_000))).is_ok());
+        assert!(check_window(starts, Some(starts)).is_err());
+        assert!(check_window(starts, Some(DateTime::from_millis(500_000))).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use axum::Json;
+use axum::http::StatusCode;
+use mongodb::{bson::{doc, Bson, Document}, Database};
+use futures::stream::TryStreamExt;
+use std::collections::HashMap;
+use crate::routes::common::{error_response, get_f64, get_string, now_datetime};
+
+// Shops keep `ratingSummary: { count, sum, histogram: { "1".."5" } }`, covering order ratings
+// and the legacy `reviews` collection, minus anything a moderator has hidden. Orders adjust it as they are rated; the rebuild recomputes it.
+
+const STARS: [i64; 5] = [1, 2, 3, 4, 5];
+
+// Tags a customer can attach to a rating.
+pub const RATING_TAGS: [&str; 9] = [
+    "cold_food", "late", "wrong_item", "missing_item", "poor_packaging",
+    "tasty", "well_packed", "friendly_rider", "fast_delivery",
+];
+
+// How long after the first rating it can still be changed; RATING_EDIT_WINDOW_HOURS, default 24.
+pub fn rating_edit_window_millis() -> i64{
+    let hours = std::env::var("RATING_EDIT_WINDOW_HOURS").ok().and_then(|v| v.parse::<i64>().ok()).unwrap_or(24);
+    hours.max(0) * 60 * 60 * 1000
+}
+
+pub fn normalize_rating_tags(tags: &[String]) -> Result<Vec<String>, (StatusCode, Json<Document>)>{
+    let mut out: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_lowercase().replace([' ', '-'], "_");
+        if !RATING_TAGS.contains(&tag.as_str()) {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("unknown tag {}, expected one of {}", tag, RATING_TAGS.join(", "))));
+        }
+        if !out.contains(&tag) {
+            out.push(tag);
+        }
+    }
+    Ok(out)
+}
+
+fn score_of(value: Option<&Bson>) -> Option<i64>{
+    let score = match value? {
+        Bson::Int32(v) => *v as i64,
+        Bson::Int64(v) => *v,
+        Bson::Double(v) => v.round() as i64,
+        _ => return None,
+    };
+    STARS.contains(&score).then_some(score)
+}
+
+pub fn order_score(order_doc: &Document) -> Option<i64>{
+    score_of(order_doc.get_document("rating").ok()?.get("score"))
+}
+
+pub fn review_score(review: &Document) -> Option<i64>{
+    score_of(review.get("rating"))
+}
+
+// The score an order contributes to its restaurant's summary; hidden ratings contribute nothing.
+pub fn counted_order_score(order_doc: &Document) -> Option<i64>{
+    let hidden = order_doc.get_document("rating").is_ok_and(|r| r.get_bool("hidden").unwrap_or(false));
+    if hidden { None } else { order_score(order_doc) }
+}
+
+// Move a restaurant's summary from `old` to `new` score; `None` means "no rating" on that side.
+pub async fn adjust_rating_summary(db: &Database, restaurant_id: &str, old: Option<i64>, new: Option<i64>) -> mongodb::error::Result<()>{
+    if old == new {
+        return Ok(());
+    }
+    let mut inc = Document::new();
+    let count = new.is_some() as i64 - old.is_some() as i64;
+    inc.insert("ratingSummary.count", count);
+    inc.insert("ratingSummary.sum", new.unwrap_or(0) - old.unwrap_or(0));
+    if let Some(old) = old {
+        inc.insert(format!("ratingSummary.histogram.{}", old), -1i64);
+    }
+    if let Some(new) = new {
+        inc.insert(format!("ratingSummary.histogram.{}", new), 1i64);
+    }
+    db.collection::<Document>("shops")
+        .update_one(doc! { "id": restaurant_id }, doc! { "$inc": inc, "$set": { "ratingSummary.updatedAt": now_datetime() } })
+        .await?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct Tally {
+    count: i64,
+    sum: i64,
+    histogram: [i64; 5],
+}
+
+impl Tally {
+    fn add(&mut self, score: i64){
+        self.count += 1;
+        self.sum += score;
+        self.histogram[(score - 1) as usize] += 1;
+    }
+
+    fn to_bson(&self) -> Document{
+        let mut histogram = Document::new();
+        for star in STARS {
+            histogram.insert(star.to_string(), self.histogram[(star - 1) as usize]);
+        }
+        doc! { "count": self.count, "sum": self.sum, "histogram": histogram, "updatedAt": now_datetime() }
+    }
+}
+
+// Recompute every shop's summary from scratch; used by the maintenance job.
+pub async fn rebuild_rating_summaries(db: &Database) -> mongodb::error::Result<u64>{
+    let mut tallies: HashMap<String, Tally> = HashMap::new();
+
+    let mut orders = db.collection::<Document>("orders")
+        .find(doc! { "rating.score": { "$exists": true }, "rating.hidden": { "$ne": true } })
+        .projection(doc! { "restaurantId": 1, "rating": 1 })
+        .await?;
+    while let Some(order_doc) = orders.try_next().await? {
+        if let (Some(rest_id), Some(score)) = (get_string(&order_doc, "restaurantId"), order_score(&order_doc)) {
+            tallies.entry(rest_id).or_default().add(score);
+        }
+    }
+
+    let mut reviews = db.collection::<Document>("reviews").find(doc! { "hidden": { "$ne": true } }).await?;
+    while let Some(review) = reviews.try_next().await? {
+        let rest_id = get_string(&review, "restaurantId")
+            .or_else(|| get_string(&review, "shop_id"))
+            .or_else(|| get_string(&review, "restaurant_id"));
+        if let (Some(rest_id), Some(score)) = (rest_id, review_score(&review)) {
+            tallies.entry(rest_id).or_default().add(score);
+        }
+    }
+
+    let shops = db.collection::<Document>("shops");
+    let ids: Vec<String> = shops.distinct("id", doc! {}).await?
+        .iter()
+        .filter_map(|id| id.as_str().map(|s| s.to_string()))
+        .collect();
+    let mut updated = 0;
+    for id in ids {
+        let summary = tallies.remove(&id).unwrap_or_default().to_bson();
+        shops.update_one(doc! { "id": &id }, doc! { "$set": { "ratingSummary": summary } }).await?;
+        updated += 1;
+    }
+    Ok(updated)
+}
+
+// `rating` (average), `ratingCount` and `ratingHistogram` for the customer endpoints.
+// Shops that have never been rated fall back to a hand-set `rating` field if there is one.
+pub fn rating_view(shop: &Document) -> Document{
+    let summary = shop.get_document("ratingSummary").cloned().unwrap_or_default();
+    let stored = summary.get_document("histogram").cloned().unwrap_or_default();
+    let count = get_f64(&summary, "count").unwrap_or(0.0) as i64;
+    let sum = get_f64(&summary, "sum").unwrap_or(0.0);
+    let mut histogram = Document::new();
+    for star in STARS {
+        histogram.insert(star.to_string(), get_f64(&stored, &star.to_string()).unwrap_or(0.0) as i64);
+    }
+    let average = if count > 0 {
+        Some(((sum / count as f64) * 10.0).round() / 10.0)
+    } else {
+        get_f64(shop, "rating")
+    };
+    doc! { "rating": average, "ratingCount": count, "ratingHistogram": histogram }
+}
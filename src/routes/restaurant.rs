@@ -1,12 +1,19 @@
 #![allow(non_snake_case)]
 
-use axum::{Router, routing::{get, patch}, extract::{State, Path, Query}, Json, http::HeaderMap};
+use axum::{Router, routing::{get, patch, post, put, delete}, extract::{State, Path, Query}, Json, http::HeaderMap};
 use mongodb::{bson::{doc, Bson, Document}, Database};
 use serde::Deserialize;
 use futures::stream::TryStreamExt;
 use axum::http::StatusCode;
 use std::collections::HashMap;
-use crate::routes::common::{ApiResult, data_response, data_response_with_status, error_response, document_id, get_string, get_i64, get_bool, get_array, now_datetime, iso_from_bson, now_millis, require_role};
+use crate::routes::common::{Claims, ApiResult, data_response, data_response_with_status, error_response, document_id, get_string, get_i64, get_bool, get_array, now_datetime, iso_from_bson, now_millis, require_role, taipei_now, claims_restaurant_id, requested_restaurant_id, menu_restaurant_id};
+use crate::routes::bundles::{BundleComponent, is_bundle, validate_components};
+use crate::routes::favorites::remove_item_favorites;
+use crate::routes::i18n::{Translation, translation_updates, translations_to_bson};
+use crate::routes::inventory::restore_order_stock;
+use crate::routes::promos::restore_order_promotion;
+use crate::routes::pricing::{record_price_change, resolve_price};
+use crate::routes::schedule::{TimeWindow, validate_windows, windows_to_bson};
 
 #[derive(Deserialize)]
 struct OrderListQuery {
@@ -17,6 +24,7 @@ struct OrderListQuery {
 #[derive(Deserialize)]
 struct MenuListQuery {
     restaurantId: Option<String>,
+    includeArchived: Option<bool>,
 }
 
 #[derive(Deserialize)]
@@ -32,6 +40,17 @@ struct MenuItemRequest {
     allergens: Option<Vec<String>>,
     tags: Option<Vec<String>>,
     restaurantId: Option<String>,
+    stock: Option<i64>,
+    dailyStock: Option<i64>,
+    lowStockThreshold: Option<i64>,
+    stockResetHour: Option<i64>,
+    category: Option<String>,
+    availability: Option<Vec<TimeWindow>>,
+    // "item" (default) or "bundle"
+    r#type: Option<String>,
+    components: Option<Vec<BundleComponent>>,
+    // per-language name/description, e.g. { "en": { "name": "..." } }
+    translations: Option<HashMap<String, Translation>>,
 }
 
 #[derive(Deserialize)]
@@ -46,6 +65,33 @@ struct MenuItemPatch {
     sortOrder: Option<i64>,
     allergens: Option<Vec<String>>,
     tags: Option<Vec<String>>,
+    stock: Option<i64>,
+    dailyStock: Option<i64>,
+    lowStockThreshold: Option<i64>,
+    stockResetHour: Option<i64>,
+    trackStock: Option<bool>,
+    category: Option<String>,
+    availability: Option<Vec<TimeWindow>>,
+    // "item" (default) or "bundle"
+    r#type: Option<String>,
+    components: Option<Vec<BundleComponent>>,
+    // per-language name/description, e.g. { "en": { "name": "..." } }
+    translations: Option<HashMap<String, Translation>>,
+}
+
+#[derive(Deserialize)]
+struct PriceScheduleRequest {
+    price: i64,
+    // RFC 3339; defaults to now
+    startsAt: Option<String>,
+    // set for a time-limited promotional price, omit for a permanent change
+    endsAt: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CategoryRequest {
+    availability: Vec<TimeWindow>,
+    restaurantId: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -53,6 +99,11 @@ struct StatusUpdateRequest {
     status: String,
 }
 
+#[derive(Deserialize)]
+struct NotificationsQuery {
+    sinceId: Option<String>,
+}
+
 #[derive(Deserialize)]
 struct ReportQuery {
     range: Option<String>,
@@ -92,12 +143,13 @@ fn can_transition(current: &str, next: &str) -> bool{
     }
 }
 
-fn map_menu_item(doc: &Document) -> Document{
+pub fn map_menu_item(doc: &Document) -> Document{
     let mut item = Document::new();
     let id = document_id(doc);
     item.insert("id", id.unwrap_or_default());
     item.insert("name", get_string(doc, "name").unwrap_or_default());
     item.insert("description", get_string(doc, "description").unwrap_or_default());
+    item.insert("translations", doc.get_document("translations").cloned().unwrap_or_default());
     let price = match doc.get("price") {
         Some(Bson::Int32(v)) => *v as i64,
         Some(Bson::Int64(v)) => *v,
@@ -106,6 +158,17 @@ fn map_menu_item(doc: &Document) -> Document{
         _ => get_i64(doc, "price").unwrap_or(0),
     };
     item.insert("price", price);
+    item.insert("currentPrice", resolve_price(doc, now_datetime()).0);
+    let schedule: Vec<Bson> = get_array(doc, "priceSchedule").unwrap_or_default().iter()
+        .filter_map(Bson::as_document)
+        .map(|entry| Bson::Document(doc! {
+            "id": get_string(entry, "id").unwrap_or_default(),
+            "price": entry.get("price").cloned().unwrap_or(Bson::Null),
+            "startsAt": entry.get("startsAt").and_then(iso_from_bson),
+            "endsAt": entry.get("endsAt").and_then(iso_from_bson)
+        }))
+        .collect();
+    item.insert("priceSchedule", schedule);
     if let Some(sizes) = get_array(doc, "sizes").or_else(|| get_array(doc, "size")) {
         item.insert("sizes", Bson::Array(sizes));
     }
@@ -113,17 +176,46 @@ fn map_menu_item(doc: &Document) -> Document{
         item.insert("spicinessOptions", Bson::Array(spiciness));
     }
     item.insert("imageUrl", get_string(doc, "imageUrl").unwrap_or_default());
+    item.insert("thumbnailUrl", get_string(doc, "thumbnailUrl").unwrap_or_default());
     item.insert("isAvailable", Bson::Boolean(get_bool(doc, "isAvailable").unwrap_or(true)));
     item.insert("sortOrder", Bson::Int64(get_i64(doc, "sortOrder").unwrap_or(0)));
     item.insert("allergens", Bson::Array(get_array(doc, "allergens").unwrap_or_default()));
     item.insert("tags", Bson::Array(get_array(doc, "tags").unwrap_or_default()));
+    item.insert("stock", get_i64(doc, "stock").map(Bson::Int64).unwrap_or(Bson::Null));
+    item.insert("dailyStock", get_i64(doc, "dailyStock").map(Bson::Int64).unwrap_or(Bson::Null));
+    item.insert("lowStockThreshold", get_i64(doc, "lowStockThreshold").map(Bson::Int64).unwrap_or(Bson::Null));
+    item.insert("stockResetHour", get_i64(doc, "stockResetHour").map(Bson::Int64).unwrap_or(Bson::Null));
+    item.insert("category", get_string(doc, "category").map(Bson::String).unwrap_or(Bson::Null));
+    item.insert("availability", Bson::Array(get_array(doc, "availability").unwrap_or_default()));
+    item.insert("archived", Bson::Boolean(get_bool(doc, "archived").unwrap_or(false)));
+    item.insert("type", get_string(doc, "type").unwrap_or_else(|| "item".to_string()));
+    if let Some(components) = get_array(doc, "components") {
+        item.insert("components", Bson::Array(components));
+    }
     item
 }
 
+fn validate_item_type(item_type: Option<&str>) -> Result<(), (StatusCode, Json<Document>)>{
+    match item_type {
+        None | Some("item") | Some("bundle") => Ok(()),
+        Some(_) => Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "type must be item or bundle")),
+    }
+}
+
+fn validate_stock_fields(stock: Option<i64>, daily_stock: Option<i64>, threshold: Option<i64>, reset_hour: Option<i64>) -> Result<(), (StatusCode, Json<Document>)>{
+    if stock.is_some_and(|v| v < 0) || daily_stock.is_some_and(|v| v < 0) || threshold.is_some_and(|v| v < 0) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "stock values must not be negative"));
+    }
+    if reset_hour.is_some_and(|h| !(0..24).contains(&h)) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "stockResetHour must be 0-23"));
+    }
+    Ok(())
+}
+
 async fn list_orders(State(db): State<Database>, Query(query): Query<OrderListQuery>, headers: HeaderMap) -> ApiResult{
     let claims = require_role(&headers, &["restaurant"])?;
     let mut filter = Document::new();
-    let restaurant_id = query.restaurantId.unwrap_or_else(|| claims.sub.clone());
+    let restaurant_id = requested_restaurant_id(&claims, query.restaurantId.as_deref())?;
     filter.insert("restaurantId", restaurant_id);
 
     if let Some(status) = query.status {
@@ -186,6 +278,15 @@ async fn list_orders(State(db): State<Database>, Query(query): Query<OrderListQu
                     out.insert("spiciness", get_string(item_doc, "spiciness").unwrap_or_default());
                     out.insert("quantity", get_i64(item_doc, "quantity").unwrap_or(1));
                     out.insert("price", get_i64(item_doc, "price").unwrap_or(0));
+                    if let Some(options) = item_doc.get("options") {
+                        out.insert("options", options.clone());
+                    }
+                    if let Some(snapshot) = item_doc.get("snapshot") {
+                        out.insert("snapshot", snapshot.clone());
+                    }
+                    if let Some(components) = item_doc.get("components") {
+                        out.insert("components", components.clone());
+                    }
                     out_items.push(Bson::Document(out));
                 }
             }
@@ -207,10 +308,9 @@ async fn get_order(Path(id): Path<String>, State(db): State<Database>, headers:
     let Some(order_doc) = order else {
         return Err(error_response(StatusCode::NOT_FOUND, "order.not_found", "Order not found"));
     };
-    if let Some(rest_id) = get_string(&order_doc, "restaurantId") {
-        if rest_id != claims.sub && Some(rest_id.clone()) != get_string(&order_doc, "shop_id") {
-            return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
-        }
+    if let Some(rest_id) = get_string(&order_doc, "restaurantId")
+        && rest_id != claims_restaurant_id(&claims) && Some(rest_id.clone()) != get_string(&order_doc, "shop_id") {
+        return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
     }
 
     let mut data = Document::new();
@@ -249,6 +349,15 @@ async fn get_order(Path(id): Path<String>, State(db): State<Database>, headers:
                 out.insert("spiciness", get_string(item_doc, "spiciness").unwrap_or_default());
                 out.insert("quantity", get_i64(item_doc, "quantity").unwrap_or(1));
                 out.insert("price", get_i64(item_doc, "price").unwrap_or(0));
+                if let Some(options) = item_doc.get("options") {
+                    out.insert("options", options.clone());
+                }
+                if let Some(snapshot) = item_doc.get("snapshot") {
+                    out.insert("snapshot", snapshot.clone());
+                }
+                if let Some(components) = item_doc.get("components") {
+                    out.insert("components", components.clone());
+                }
                 out_items.push(Bson::Document(out));
             }
         }
@@ -285,7 +394,7 @@ async fn update_order_status(Path(id): Path<String>, State(db): State<Database>,
         return Err(error_response(StatusCode::NOT_FOUND, "order.not_found", "Order not found"));
     };
     let rest_id = get_string(&order_doc, "restaurantId").unwrap_or_default();
-    if !rest_id.is_empty() && rest_id != claims.sub {
+    if !rest_id.is_empty() && rest_id != claims_restaurant_id(&claims) {
         return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
     }
     let current = get_string(&order_doc, "status").unwrap_or_default();
@@ -307,6 +416,14 @@ async fn update_order_status(Path(id): Path<String>, State(db): State<Database>,
     if result.matched_count == 0 {
         return Err(error_response(StatusCode::NOT_FOUND, "order.not_found", "Order not found"));
     }
+    if payload.status == "cancelled" {
+        restore_order_stock(&db, &id)
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        restore_order_promotion(&db, &id)
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    }
 
     Ok(data_response(Bson::Document(doc! { "status": payload.status })))
 }
@@ -314,8 +431,11 @@ async fn update_order_status(Path(id): Path<String>, State(db): State<Database>,
 async fn list_menu(State(db): State<Database>, Query(query): Query<MenuListQuery>, headers: HeaderMap) -> ApiResult{
     let claims = require_role(&headers, &["restaurant"])?;
     let collection = db.collection::<Document>("menu");
-    let restaurant_id = query.restaurantId.unwrap_or_else(|| claims.sub.clone());
-    let filter = doc! { "$or": [ { "shop_id": &restaurant_id }, { "restaurantId": &restaurant_id }, { "restaurant_id": &restaurant_id } ] };
+    let restaurant_id = requested_restaurant_id(&claims, query.restaurantId.as_deref())?;
+    let mut filter = doc! { "$or": [ { "shop_id": &restaurant_id }, { "restaurantId": &restaurant_id }, { "restaurant_id": &restaurant_id } ] };
+    if !query.includeArchived.unwrap_or(false) {
+        filter.insert("archived", doc! { "$ne": true });
+    }
 
     let mut cursor = collection.find(filter)
         .await
@@ -332,11 +452,18 @@ async fn list_menu(State(db): State<Database>, Query(query): Query<MenuListQuery
 
 async fn create_menu_item(State(db): State<Database>, headers: HeaderMap, Json(payload): Json<MenuItemRequest>) -> ApiResult{
     let claims = require_role(&headers, &["restaurant"])?;
-    let restaurant_id = payload.restaurantId.clone().unwrap_or_else(|| claims.sub.clone());
+    validate_stock_fields(payload.stock, payload.dailyStock, payload.lowStockThreshold, payload.stockResetHour)?;
+    if let Some(windows) = payload.availability.as_ref() {
+        validate_windows(windows)?;
+    }
+    validate_item_type(payload.r#type.as_deref())?;
+    let restaurant_id = requested_restaurant_id(&claims, payload.restaurantId.as_deref())?;
     let collection = db.collection::<Document>("menu");
     let id = mongodb::bson::oid::ObjectId::new().to_hex();
 
-    let menu_doc = doc! {
+    // a daily stock with no explicit starting count starts full
+    let stock = payload.stock.or(payload.dailyStock);
+    let mut menu_doc = doc! {
         "id": &id,
         "name": payload.name.clone(),
         "description": payload.description.clone(),
@@ -348,8 +475,39 @@ async fn create_menu_item(State(db): State<Database>, headers: HeaderMap, Json(p
         "sortOrder": payload.sortOrder,
         "allergens": payload.allergens.clone(),
         "tags": payload.tags.clone(),
-        "restaurantId": restaurant_id
+        "restaurantId": &restaurant_id
     };
+    if let Some(stock) = stock {
+        menu_doc.insert("stock", stock);
+        if stock == 0 {
+            menu_doc.insert("isAvailable", false);
+            menu_doc.insert("soldOut", true);
+        }
+    }
+    if let Some(daily_stock) = payload.dailyStock {
+        menu_doc.insert("dailyStock", daily_stock);
+        menu_doc.insert("stockResetOn", taipei_now().format("%Y-%m-%d").to_string());
+    }
+    if let Some(threshold) = payload.lowStockThreshold {
+        menu_doc.insert("lowStockThreshold", threshold);
+    }
+    if let Some(reset_hour) = payload.stockResetHour {
+        menu_doc.insert("stockResetHour", reset_hour);
+    }
+    if let Some(category) = payload.category.clone() {
+        menu_doc.insert("category", category);
+    }
+    if let Some(windows) = payload.availability.as_ref() {
+        menu_doc.insert("availability", windows_to_bson(windows));
+    }
+    if let Some(translations) = payload.translations.as_ref() {
+        menu_doc.insert("translations", translations_to_bson(translations)?);
+    }
+    if payload.r#type.as_deref() == Some("bundle") {
+        let components = validate_components(&db, &restaurant_id, payload.components.as_deref().unwrap_or_default()).await?;
+        menu_doc.insert("type", "bundle");
+        menu_doc.insert("components", components);
+    }
 
     collection.insert_one(menu_doc.clone())
         .await
@@ -360,7 +518,12 @@ async fn create_menu_item(State(db): State<Database>, headers: HeaderMap, Json(p
 
 async fn update_menu_item(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<MenuItemPatch>) -> ApiResult{
     let claims = require_role(&headers, &["restaurant"])?;
+    validate_stock_fields(payload.stock, payload.dailyStock, payload.lowStockThreshold, payload.stockResetHour)?;
+    if let Some(windows) = payload.availability.as_ref() {
+        validate_windows(windows)?;
+    }
     let mut update_doc = Document::new();
+    let mut unset_doc = Document::new();
     if let Some(name) = payload.name {
         update_doc.insert("name", name);
     }
@@ -391,31 +554,88 @@ async fn update_menu_item(Path(id): Path<String>, State(db): State<Database>, he
     if let Some(tags) = payload.tags {
         update_doc.insert("tags", tags);
     }
+    if let Some(stock) = payload.stock {
+        update_doc.insert("stock", stock);
+        if payload.isAvailable.is_none() {
+            // a manual restock undoes an automatic sell-out, and a zero count is one
+            if stock > 0 {
+                unset_doc.insert("soldOut", "");
+            } else {
+                update_doc.insert("isAvailable", false);
+                update_doc.insert("soldOut", true);
+            }
+        }
+    }
+    if let Some(daily_stock) = payload.dailyStock {
+        update_doc.insert("dailyStock", daily_stock);
+    }
+    if let Some(threshold) = payload.lowStockThreshold {
+        update_doc.insert("lowStockThreshold", threshold);
+    }
+    if let Some(reset_hour) = payload.stockResetHour {
+        update_doc.insert("stockResetHour", reset_hour);
+    }
+    if let Some(category) = payload.category {
+        update_doc.insert("category", category);
+    }
+    if let Some(windows) = payload.availability.as_ref() {
+        update_doc.insert("availability", windows_to_bson(windows));
+    }
+    if let Some(translations) = payload.translations.as_ref() {
+        translation_updates(translations, &mut update_doc, &mut unset_doc)?;
+    }
+    if payload.trackStock == Some(false) {
+        for key in ["stock", "dailyStock", "lowStockThreshold", "stockResetHour", "stockResetOn", "soldOut"] {
+            update_doc.remove(key);
+            unset_doc.insert(key, "");
+        }
+    }
 
-    if update_doc.is_empty() {
+    if update_doc.is_empty() && unset_doc.is_empty() && payload.r#type.is_none() && payload.components.is_none() {
         return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "No fields to update"));
     }
 
     let collection = db.collection::<Document>("menu");
-    let existing = collection.find_one(doc! { "id": &id })
-        .await
-        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
-    let Some(menu_doc) = existing else {
-        return Err(error_response(StatusCode::NOT_FOUND, "menu.unavailable", "Menu item not found"));
+    let menu_doc = find_owned_menu_item(&db, &id, &claims).await?;
+    validate_item_type(payload.r#type.as_deref())?;
+    let becomes_bundle = match payload.r#type.as_deref() {
+        Some(t) => t == "bundle",
+        None => is_bundle(&menu_doc),
     };
-    if let Some(rest_id) = get_string(&menu_doc, "restaurantId").or_else(|| get_string(&menu_doc, "shop_id")) {
-        if rest_id != claims.sub {
-            return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
-        }
+    if becomes_bundle && (payload.components.is_some() || !is_bundle(&menu_doc)) {
+        let restaurant_id = menu_restaurant_id(&menu_doc).unwrap_or_else(|| claims_restaurant_id(&claims));
+        let components = validate_components(&db, &restaurant_id, payload.components.as_deref().unwrap_or_default()).await?;
+        update_doc.insert("type", "bundle");
+        update_doc.insert("components", components);
+    } else if !becomes_bundle && is_bundle(&menu_doc) {
+        update_doc.insert("type", "item");
+        unset_doc.insert("components", "");
     }
 
-    let update = doc! { "$set": update_doc };
+    if let Some(price) = payload.price
+        && price < 0 {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "price must not be negative"));
+    }
+    if unset_doc.contains_key("soldOut") && menu_doc.get_bool("soldOut").unwrap_or(false) && payload.isAvailable.is_none() {
+        update_doc.insert("isAvailable", true);
+    }
+    let mut update = Document::new();
+    if !update_doc.is_empty() {
+        update.insert("$set", update_doc);
+    }
+    if !unset_doc.is_empty() {
+        update.insert("$unset", unset_doc);
+    }
     let result = collection.update_one(doc! { "id": &id }, update)
         .await
         .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
     if result.matched_count == 0 {
         return Err(error_response(StatusCode::NOT_FOUND, "menu.unavailable", "Menu item not found"));
     }
+    // only log the change once it has actually been saved
+    if let Some(price) = payload.price {
+        record_price_change(&db, &menu_doc, price, &claims.sub, "manual").await;
+    }
 
     let updated = collection.find_one(doc! { "id": &id })
         .await
@@ -424,29 +644,205 @@ async fn update_menu_item(Path(id): Path<String>, State(db): State<Database>, he
     Ok(data_response(Bson::Document(map_menu_item(&updated))))
 }
 
+async fn find_owned_menu_item(db: &Database, id: &str, claims: &Claims) -> Result<Document, (StatusCode, Json<Document>)>{
+    let collection = db.collection::<Document>("menu");
+    let existing = collection.find_one(doc! { "id": id })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let Some(menu_doc) = existing else {
+        return Err(error_response(StatusCode::NOT_FOUND, "menu.unavailable", "Menu item not found"));
+    };
+    if let Some(rest_id) = menu_restaurant_id(&menu_doc)
+        && rest_id != claims_restaurant_id(claims) {
+        return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
+    }
+    Ok(menu_doc)
+}
+
+// DELETE /restaurant/menu/{id}: archive rather than remove, so past orders and reports keep their item
 async fn delete_menu_item(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
     let claims = require_role(&headers, &["restaurant"])?;
+    find_owned_menu_item(&db, &id, &claims).await?;
     let collection = db.collection::<Document>("menu");
-    let existing = collection.find_one(doc! { "id": &id })
+    let update = doc! { "$set": { "archived": true, "archivedAt": now_datetime() } };
+    let result = collection.update_one(doc! { "id": &id }, update)
         .await
         .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
-    let Some(menu_doc) = existing else {
+    if result.matched_count == 0 {
         return Err(error_response(StatusCode::NOT_FOUND, "menu.unavailable", "Menu item not found"));
+    }
+    remove_item_favorites(&db, &[id]).await;
+    Ok(data_response(Bson::Document(doc! { "ok": true, "archived": true })))
+}
+
+// POST /restaurant/menu/{id}/restore
+async fn restore_menu_item(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    find_owned_menu_item(&db, &id, &claims).await?;
+    let collection = db.collection::<Document>("menu");
+    let update = doc! { "$set": { "archived": false }, "$unset": { "archivedAt": "" } };
+    let updated = collection.find_one_and_update(doc! { "id": &id }, update)
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "menu.unavailable", "Menu item not found"))?;
+    Ok(data_response(Bson::Document(map_menu_item(&updated))))
+}
+
+pub fn parse_instant(value: &str, field: &str) -> Result<mongodb::bson::DateTime, (StatusCode, Json<Document>)>{
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|dt| mongodb::bson::DateTime::from_millis(dt.timestamp_millis()))
+        .map_err(|_| error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("{} must be an RFC 3339 timestamp", field)))
+}
+
+// GET /restaurant/menu/{id}/prices: current price, pending changes and the change log
+async fn list_prices(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let menu_doc = find_owned_menu_item(&db, &id, &claims).await?;
+    let collection = db.collection::<Document>("menu_price_history");
+    let mut cursor = collection.find(doc! { "menuItemId": &id })
+        .sort(doc! { "changedAt": -1 })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let mut history: Vec<Bson> = Vec::new();
+    while let Some(doc) = cursor.try_next()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))? {
+        history.push(Bson::Document(doc! {
+            "oldPrice": get_i64(&doc, "oldPrice").unwrap_or(0),
+            "newPrice": get_i64(&doc, "newPrice").unwrap_or(0),
+            "changedBy": get_string(&doc, "changedBy").unwrap_or_default(),
+            "source": get_string(&doc, "source").unwrap_or_default(),
+            "changedAt": doc.get("changedAt").and_then(iso_from_bson)
+        }));
+    }
+    let item = map_menu_item(&menu_doc);
+    Ok(data_response(Bson::Document(doc! {
+        "price": item.get("price").cloned().unwrap_or(Bson::Null),
+        "currentPrice": item.get("currentPrice").cloned().unwrap_or(Bson::Null),
+        "scheduled": item.get("priceSchedule").cloned().unwrap_or(Bson::Array(Vec::new())),
+        "history": history
+    })))
+}
+
+// POST /restaurant/menu/{id}/prices: schedule a permanent change, or a promotion when endsAt is set
+async fn schedule_price(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<PriceScheduleRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    find_owned_menu_item(&db, &id, &claims).await?;
+    if payload.price < 0 {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "price must not be negative"));
+    }
+    let now = now_datetime();
+    let starts_at = match payload.startsAt.as_deref() {
+        Some(value) => parse_instant(value, "startsAt")?,
+        None => now,
+    };
+    let mut entry = doc! {
+        "id": mongodb::bson::oid::ObjectId::new().to_hex(),
+        "price": payload.price,
+        "startsAt": starts_at,
+        "createdBy": &claims.sub,
+        "createdAt": now
     };
-    if let Some(rest_id) = get_string(&menu_doc, "restaurantId").or_else(|| get_string(&menu_doc, "shop_id")) {
-        if rest_id != claims.sub {
-            return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
+    if let Some(value) = payload.endsAt.as_deref() {
+        let ends_at = parse_instant(value, "endsAt")?;
+        if ends_at <= starts_at || ends_at <= now {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "endsAt must be after startsAt and in the future"));
         }
+        entry.insert("endsAt", ends_at);
     }
-    let result = collection.delete_one(doc! { "id": &id })
+    let collection = db.collection::<Document>("menu");
+    let updated = collection.find_one_and_update(doc! { "id": &id }, doc! { "$push": { "priceSchedule": entry } })
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "menu.unavailable", "Menu item not found"))?;
+    Ok(data_response_with_status(StatusCode::CREATED, Bson::Document(map_menu_item(&updated))))
+}
+
+// DELETE /restaurant/menu/{id}/prices/{schedule_id}
+async fn cancel_scheduled_price(Path((id, schedule_id)): Path<(String, String)>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    find_owned_menu_item(&db, &id, &claims).await?;
+    let collection = db.collection::<Document>("menu");
+    let result = collection.update_one(doc! { "id": &id, "priceSchedule.id": &schedule_id }, doc! { "$pull": { "priceSchedule": { "id": &schedule_id } } })
         .await
         .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
-    if result.deleted_count == 0 {
-        return Err(error_response(StatusCode::NOT_FOUND, "menu.unavailable", "Menu item not found"));
+    if result.matched_count == 0 {
+        return Err(error_response(StatusCode::NOT_FOUND, "menu.price_not_found", "Scheduled price not found"));
     }
     Ok(data_response(Bson::Document(doc! { "ok": true })))
 }
 
+async fn list_categories(State(db): State<Database>, Query(query): Query<MenuListQuery>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let restaurant_id = requested_restaurant_id(&claims, query.restaurantId.as_deref())?;
+    let collection = db.collection::<Document>("menu_categories");
+    let mut cursor = collection.find(doc! { "restaurantId": &restaurant_id })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let mut categories: Vec<Bson> = Vec::new();
+    while let Some(doc) = cursor.try_next()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))? {
+        categories.push(Bson::Document(doc! {
+            "name": get_string(&doc, "name").unwrap_or_default(),
+            "availability": Bson::Array(get_array(&doc, "availability").unwrap_or_default())
+        }));
+    }
+    Ok(data_response(Bson::Array(categories)))
+}
+
+// PUT /restaurant/menu/categories/{name}: set the serving windows shared by a category's items
+async fn upsert_category(Path(name): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<CategoryRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    validate_windows(&payload.availability)?;
+    let restaurant_id = requested_restaurant_id(&claims, payload.restaurantId.as_deref())?;
+    let collection = db.collection::<Document>("menu_categories");
+    let update = doc! {
+        "$set": { "availability": windows_to_bson(&payload.availability), "updatedAt": now_datetime() },
+        "$setOnInsert": { "restaurantId": &restaurant_id, "name": &name }
+    };
+    collection.update_one(doc! { "restaurantId": &restaurant_id, "name": &name }, update)
+        .upsert(true)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(data_response(Bson::Document(doc! {
+        "name": name,
+        "availability": windows_to_bson(&payload.availability)
+    })))
+}
+
+async fn list_notifications(State(db): State<Database>, headers: HeaderMap, Query(query): Query<NotificationsQuery>) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let restaurant_id = claims_restaurant_id(&claims);
+    let collection = db.collection::<Document>("restaurant_notifications");
+    let mut filter = doc! { "restaurantId": { "$in": [&claims.sub, &restaurant_id] } };
+    if let Some(since_id) = query.sinceId {
+        filter.insert("id", doc! { "$gt": since_id });
+    }
+    let mut cursor = collection.find(filter)
+        .sort(doc! { "id": 1 })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let mut notifications: Vec<Bson> = Vec::new();
+    while let Some(doc) = cursor.try_next()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))? {
+        let mut item = Document::new();
+        item.insert("id", get_string(&doc, "id").unwrap_or_default());
+        item.insert("type", get_string(&doc, "type").unwrap_or_default());
+        item.insert("menuItemId", get_string(&doc, "menuItemId").unwrap_or_default());
+        item.insert("name", get_string(&doc, "name").unwrap_or_default());
+        item.insert("stock", get_i64(&doc, "stock").unwrap_or(0));
+        if let Some(created_at) = doc.get("createdAt").and_then(iso_from_bson) {
+            item.insert("createdAt", created_at);
+        }
+        notifications.push(Bson::Document(item));
+    }
+    Ok(data_response(Bson::Array(notifications)))
+}
+
 async fn reports(State(db): State<Database>, headers: HeaderMap, Query(query): Query<ReportQuery>) -> ApiResult{
     let claims = require_role(&headers, &["restaurant"])?;
     let range = query.range.unwrap_or_else(|| "30d".to_string());
@@ -460,7 +856,7 @@ async fn reports(State(db): State<Database>, headers: HeaderMap, Query(query): Q
     let start_millis = now_millis - (duration_days as i64 * 24 * 60 * 60 * 1000);
 
     let mut filter = Document::new();
-    let restaurant_id = query.restaurantId.unwrap_or_else(|| claims.sub.clone());
+    let restaurant_id = requested_restaurant_id(&claims, query.restaurantId.as_deref())?;
     filter.insert("restaurantId", restaurant_id);
 
     let collection = db.collection::<Document>("orders");
@@ -470,6 +866,9 @@ async fn reports(State(db): State<Database>, headers: HeaderMap, Query(query): Q
 
     let mut total_revenue = 0i64;
     let mut order_count = 0i64;
+    // promotion discounts given on this shop's orders, cancelled ones excluded
+    let mut discount_total = 0i64;
+    let mut discounted_orders = 0i64;
     let mut items_map: HashMap<String, (String, i64, i64)> = HashMap::new();
 
     while let Some(doc) = cursor.try_next()
@@ -485,17 +884,53 @@ async fn reports(State(db): State<Database>, headers: HeaderMap, Query(query): Q
         }
         order_count += 1;
         total_revenue += get_i64(&doc, "totalAmount").unwrap_or(0);
+        if let Ok(discount) = doc.get_document("discount")
+            && get_string(&doc, "status").as_deref() != Some("cancelled") {
+            discount_total += get_i64(discount, "amount").unwrap_or(0);
+            discounted_orders += 1;
+        }
 
         if let Ok(items) = doc.get_array("items") {
             for item in items {
                 if let Bson::Document(item_doc) = item {
                     let id = get_string(item_doc, "menuItemId").unwrap_or_default();
-                    let name = get_string(item_doc, "name").unwrap_or_default();
+                    // the snapshot keeps the name the item had when it was sold
+                    let name = item_doc.get_document("snapshot").ok()
+                        .and_then(|snap| get_string(snap, "name"))
+                        .or_else(|| get_string(item_doc, "name"))
+                        .unwrap_or_default();
                     let quantity = get_i64(item_doc, "quantity").unwrap_or(1);
                     let price = get_i64(item_doc, "price").unwrap_or(0);
-                    let entry = items_map.entry(id.clone()).or_insert((name, 0, 0));
-                    entry.1 += quantity;
-                    entry.2 += quantity * price;
+                    let components: Vec<&Document> = item_doc.get_array("components")
+                        .map(|list| list.iter().filter_map(Bson::as_document).collect())
+                        .unwrap_or_default();
+                    if components.is_empty() {
+                        let entry = items_map.entry(id.clone()).or_insert((name, 0, 0));
+                        entry.1 += quantity;
+                        entry.2 += quantity * price;
+                        continue;
+                    }
+                    // bundles count as their components, with the bundle revenue split by list price
+                    let line_revenue = quantity * price;
+                    let list_total: i64 = components.iter()
+                        .map(|c| get_i64(c, "unitPrice").unwrap_or(0) * get_i64(c, "quantity").unwrap_or(1))
+                        .sum();
+                    let mut allocated = 0i64;
+                    for (index, component) in components.iter().enumerate() {
+                        let component_quantity = get_i64(component, "quantity").unwrap_or(1);
+                        let share = if index + 1 == components.len() {
+                            line_revenue - allocated
+                        } else if list_total > 0 {
+                            line_revenue * get_i64(component, "unitPrice").unwrap_or(0) * component_quantity / list_total
+                        } else {
+                            line_revenue / components.len() as i64
+                        };
+                        allocated += share;
+                        let entry = items_map.entry(get_string(component, "menuItemId").unwrap_or_default())
+                            .or_insert((get_string(component, "name").unwrap_or_default(), 0, 0));
+                        entry.1 += component_quantity;
+                        entry.2 += share;
+                    }
                 }
             }
         }
@@ -516,6 +951,8 @@ async fn reports(State(db): State<Database>, headers: HeaderMap, Query(query): Q
         "range": range,
         "totalRevenue": total_revenue,
         "orderCount": order_count,
+        "discountTotal": discount_total,
+        "discountedOrderCount": discounted_orders,
         "topItems": Bson::Array(top_items)
     };
 
@@ -528,7 +965,13 @@ pub fn restaurant_router(db: Database) -> Router{
         .route("/orders/{id}", get(get_order))
         .route("/orders/{id}/status", patch(update_order_status))
         .route("/menu", get(list_menu).post(create_menu_item))
+        .route("/menu/categories", get(list_categories))
+        .route("/menu/categories/{name}", put(upsert_category))
         .route("/menu/{id}", patch(update_menu_item).delete(delete_menu_item))
+        .route("/menu/{id}/restore", post(restore_menu_item))
+        .route("/menu/{id}/prices", get(list_prices).post(schedule_price))
+        .route("/menu/{id}/prices/{schedule_id}", delete(cancel_scheduled_price))
+        .route("/notifications", get(list_notifications))
         .route("/reports", get(reports))
         .with_state(db)
 }
@@ -1,80 +1,259 @@
-use axum::{Router, routing::get, extract::{State, Path}};
-use mongodb::{bson::{doc, Bson, Document}, Database};
+#![allow(non_snake_case)]
+
+use axum::{Router, routing::get, extract::{State, Path, Query}, http::HeaderMap, response::IntoResponse, Json};
+use mongodb::{bson::{doc, Bson, Document}, Database, IndexModel};
 use futures::stream::TryStreamExt;
-use crate::routes::common::{ApiResult, data_response, error_response, get_string, document_id, iso_from_bson, get_i64};
+use serde::Deserialize;
+use crate::routes::common::{escape_regex, ApiResult, data_response, error_response, get_string, get_bool, get_f64, get_array, document_id, get_i64, taipei_now};
 use axum::http::StatusCode;
+use crate::routes::favorites::favorite_ids;
+use crate::routes::onboarding::{listed_shop_filter, shop_is_listed};
+use crate::routes::geo::{doc_latlng, eta_minutes, geo_point};
+use crate::routes::hours::{open_status, shop_open_at};
+use crate::routes::ratings::rating_view;
+use crate::routes::reviews::{ReviewFeedQuery, feed_response, review_feed};
+use crate::routes::i18n::{DEFAULT_LANGUAGE, SUPPORTED_LANGUAGES, localized, preferred_languages};
 
-async fn rating_from_orders(db: &Database, restaurant_id: &str) -> Option<f64>{
-    let orders = db.collection::<Document>("orders");
-    let mut cursor = orders.find(doc! { "restaurantId": restaurant_id, "rating.score": { "$exists": true } })
-        .await
-        .ok()?;
-    let mut sum = 0f64;
-    let mut count = 0f64;
-    while let Some(doc) = cursor.try_next().await.ok()? {
-        let score = doc.get("rating").and_then(Bson::as_document).and_then(|d| {
-            match d.get("score") {
-                Some(Bson::Int32(v)) => Some(*v as f64),
-                Some(Bson::Int64(v)) => Some(*v as f64),
-                Some(Bson::Double(v)) => Some(*v),
-                _ => None,
-            }
-        });
-        if let Some(s) = score {
-            sum += s;
-            count += 1.0;
+#[derive(Deserialize)]
+struct RestaurantListQuery {
+    // matches shop names (any language) and cuisine tags
+    q: Option<String>,
+    // comma separated cuisine tags; a shop has to carry every one
+    tags: Option<String>,
+    openNow: Option<bool>,
+    minRating: Option<f64>,
+    deliveryAvailable: Option<bool>,
+    // rating | distance | popularity | name
+    sort: Option<String>,
+    // "lat,lng"; required for distance sorting and radius filtering, and limits the list to shops with coordinates
+    near: Option<String>,
+    radiusKm: Option<f64>,
+    page: Option<u64>,
+    pageSize: Option<u64>,
+}
+
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+fn parse_near(near: &str) -> Option<(f64, f64)>{
+    let (lat, lng) = near.split_once(',')?;
+    let lat = lat.trim().parse::<f64>().ok()?;
+    let lng = lng.trim().parse::<f64>().ok()?;
+    ((-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lng)).then_some((lat, lng))
+}
+
+// Popularity counts a shop's orders through this index instead of grouping the whole collection.
+pub async fn ensure_popularity_index(db: &Database) -> mongodb::error::Result<()>{
+    let by_shop = IndexModel::builder().keys(doc! { "restaurantId": 1, "status": 1 }).build();
+    db.collection::<Document>("orders").create_index(by_shop).await?;
+    Ok(())
+}
+
+// Lowercased shop name in the caller's languages, following the same fallback as `localized`.
+fn name_sort_key(languages: &[&str]) -> Document{
+    let mut candidates: Vec<Bson> = languages.iter()
+        .map(|lang| Bson::String(if *lang == DEFAULT_LANGUAGE { "$name".to_string() } else { format!("$translations.{}.name", lang) }))
+        .collect();
+    candidates.push(Bson::String("$name".to_string()));
+    candidates.push(Bson::String(String::new()));
+    doc! { "$toLower": { "$ifNull": candidates } }
+}
+
+// GET /restaurants?q=&tags=&openNow=&minRating=&deliveryAvailable=&sort=&near=&radiusKm=&page=&pageSize=
+async fn list_restaurants(State(db): State<Database>, headers: HeaderMap, Query(query): Query<RestaurantListQuery>) -> ApiResult{
+    let languages = preferred_languages(&headers);
+    // nearby searches list the closest shops first unless told otherwise
+    let sort = query.sort.as_deref().unwrap_or(if query.near.is_some() { "distance" } else { "default" });
+    if !["default", "rating", "distance", "popularity", "name"].contains(&sort) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "sort must be rating, distance, popularity or name"));
+    }
+    let near = match query.near.as_deref() {
+        Some(raw) => Some(parse_near(raw).ok_or_else(|| error_response(StatusCode::BAD_REQUEST, "validation.failed", "near must be lat,lng"))?),
+        None => None,
+    };
+    if sort == "distance" && near.is_none() {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "near is required to sort by distance"));
+    }
+    if let Some(radius) = query.radiusKm {
+        if near.is_none() {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "near is required with radiusKm"));
+        }
+        if radius.is_nan() || radius <= 0.0 {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "radiusKm must be positive"));
         }
     }
-    if count > 0.0 { Some(sum / count) } else { None }
-}
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query.pageSize.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let skip = (page - 1) * page_size;
 
-// GET /restaurants
-async fn list_restaurants(State(db): State<Database>) -> ApiResult{
-    let collections = db.list_collection_names()
-        .await
-        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("List collections error: {}", e)))?;
+    let mut filter = Document::new();
+    // pending and suspended shops stay out of the listing
+    let mut conditions: Vec<Bson> = vec![Bson::Document(listed_shop_filter())];
+    if let Some(q) = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+        let pattern = doc! { "$regex": escape_regex(q), "$options": "i" };
+        let mut fields: Vec<Bson> = vec![
+            Bson::Document(doc! { "name": pattern.clone() }),
+            Bson::Document(doc! { "tags": pattern.clone() }),
+        ];
+        for lang in SUPPORTED_LANGUAGES {
+            fields.push(Bson::Document(doc! { format!("translations.{}.name", lang): pattern.clone() }));
+        }
+        conditions.push(Bson::Document(doc! { "$or": fields }));
+    }
+    for tag in query.tags.as_deref().unwrap_or_default().split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        conditions.push(Bson::Document(doc! { "tags": { "$regex": format!("^{}$", escape_regex(tag)), "$options": "i" } }));
+    }
+    if let Some(delivery) = query.deliveryAvailable {
+        // shops without the flag deliver
+        conditions.push(Bson::Document(if delivery {
+            doc! { "deliveryAvailable": { "$ne": false } }
+        } else {
+            doc! { "deliveryAvailable": false }
+        }));
+    }
+    filter.insert("$and", conditions);
 
-    if collections.is_empty() {
-        return Ok(data_response(Bson::Array(Vec::new())));
+    // Nearby searches start from the 2dsphere index, which hands shops over closest first;
+    // shops without coordinates can't be placed and drop out of them.
+    let mut pipeline: Vec<Document> = Vec::new();
+    match near {
+        Some((lat, lng)) => {
+            let mut geo_near = doc! {
+                "near": geo_point(lat, lng),
+                "key": "location",
+                "distanceField": "distanceMeters",
+                "spherical": true,
+                "query": filter
+            };
+            if let Some(radius) = query.radiusKm {
+                geo_near.insert("maxDistance", radius * 1000.0);
+            }
+            pipeline.push(doc! { "$geoNear": geo_near });
+        }
+        None => pipeline.push(doc! { "$match": filter }),
+    }
+    if query.minRating.is_some() || sort == "rating" {
+        // the average rating_view reports: from the review summary, else the seeded rating
+        pipeline.push(doc! { "$set": { "ratingAverage": { "$cond": [
+            { "$gt": ["$ratingSummary.count", 0] },
+            { "$round": [{ "$divide": ["$ratingSummary.sum", "$ratingSummary.count"] }, 1] },
+            "$rating"
+        ] } } });
+    }
+    if let Some(min) = query.minRating {
+        pipeline.push(doc! { "$match": { "ratingAverage": { "$gte": min } } });
+    }
+    match sort {
+        "rating" => pipeline.push(doc! { "$sort": { "ratingAverage": -1, "_id": 1 } }),
+        // $geoNear has already ordered them
+        "distance" => {}
+        "popularity" => {
+            // only the orders of the shops that passed the filters are counted
+            pipeline.push(doc! { "$lookup": {
+                "from": "orders",
+                "localField": "id",
+                "foreignField": "restaurantId",
+                "pipeline": [
+                    { "$match": { "status": { "$ne": "cancelled" } } },
+                    { "$count": "count" }
+                ],
+                "as": "orderStats"
+            } });
+            pipeline.push(doc! { "$set": { "orderCount": { "$ifNull": [{ "$first": "$orderStats.count" }, 0] } } });
+            pipeline.push(doc! { "$sort": { "orderCount": -1, "_id": 1 } });
+        }
+        "name" => {
+            pipeline.push(doc! { "$set": { "nameKey": name_sort_key(&languages) } });
+            pipeline.push(doc! { "$sort": { "nameKey": 1, "_id": 1 } });
+        }
+        _ if near.is_none() => pipeline.push(doc! { "$sort": { "_id": 1 } }),
+        _ => {}
     }
 
-    let coll_name = if collections.iter().any(|c| c == "shops") {
-        "shops"
+    let (shops, total) = if query.openNow == Some(true) {
+        // Opening hours, closures and pauses are only understood here, so the page is cut
+        // while streaming the sorted shops rather than by the database.
+        let mut cursor = db.collection::<Document>("shops").aggregate(pipeline)
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Find error: {}", e)))?;
+        let now = taipei_now();
+        let mut shops: Vec<Document> = Vec::new();
+        let mut total: u64 = 0;
+        while let Some(shop) = cursor.try_next()
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Cursor error: {}", e)))? {
+            if !shop_open_at(&shop, &now) {
+                continue;
+            }
+            if total >= skip && total < skip + page_size {
+                shops.push(shop);
+            }
+            total += 1;
+        }
+        (shops, total)
     } else {
-        &collections[2]
+        pipeline.push(doc! { "$facet": {
+            "items": [{ "$skip": skip as i64 }, { "$limit": page_size as i64 }],
+            "total": [{ "$count": "count" }]
+        } });
+        let result = db.collection::<Document>("shops").aggregate(pipeline)
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Find error: {}", e)))?
+            .try_next()
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Cursor error: {}", e)))?
+            .unwrap_or_default();
+        let shops: Vec<Document> = get_array(&result, "items").unwrap_or_default()
+            .into_iter()
+            .filter_map(|shop| match shop { Bson::Document(shop) => Some(shop), _ => None })
+            .collect();
+        let total = get_array(&result, "total").unwrap_or_default()
+            .first()
+            .and_then(Bson::as_document)
+            .and_then(|row| get_i64(row, "count"))
+            .unwrap_or(0) as u64;
+        (shops, total)
     };
 
-    let collection = db.collection::<Document>(coll_name);
-    let mut cursor = collection.find(doc! {})
-        .await
-        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Find error: {}", e)))?;
-
+    let favorites = favorite_ids(&db, &headers, "shop").await?;
     let mut items: Vec<Bson> = Vec::new();
-    while let Some(doc) = cursor.try_next().await.map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Cursor error: {}", e)))? {
+    for doc in shops {
         // mapping attributes
         let id = document_id(&doc);
-        let name = get_string(&doc, "name");
+        // only for signed-in callers
+        let is_favorite = favorites.as_ref().map(|f| id.as_ref().is_some_and(|id| f.contains(id)));
+        let name = localized(&doc, "name", &languages);
         let image = get_string(&doc, "imageUrl");
-        let mut rating = doc.get("rating").and_then(Bson::as_f64)
-            .or_else(|| get_i64(&doc, "rating").map(|v| v as f64));
-        if rating.is_none() {
-            rating = rating_from_orders(&db, id.as_deref().unwrap_or("")).await;
-        }
+        let thumbnail = get_string(&doc, "thumbnailUrl");
+        let distance = get_f64(&doc, "distanceMeters").map(|m| m / 1000.0);
 
         let mut item = Document::new();
         item.insert("id", match id { Some(v) => Bson::String(v), None => Bson::Null });
         item.insert("name", match name { Some(v) => Bson::String(v), None => Bson::Null });
         item.insert("imageUrl", match image { Some(v) => Bson::String(v), None => Bson::Null });
-        item.insert("rating", match rating { Some(v) => Bson::Double(v), None => Bson::Null });
+        item.insert("thumbnailUrl", match thumbnail { Some(v) => Bson::String(v), None => Bson::Null });
+        item.extend(rating_view(&doc));
+        item.insert("tags", Bson::Array(get_array(&doc, "tags").unwrap_or_default()));
+        item.extend(open_status(&doc));
+        item.insert("deliveryAvailable", Bson::Boolean(get_bool(&doc, "deliveryAvailable").unwrap_or(true)));
+        item.insert("distanceKm", match distance { Some(v) => Bson::Double((v * 100.0).round() / 100.0), None => Bson::Null });
+        item.insert("etaMinutes", match (near, distance) { (Some(_), d) => Bson::Int64(eta_minutes(d)), (None, _) => Bson::Null });
+        if let Some(is_favorite) = is_favorite {
+            item.insert("isFavorite", is_favorite);
+        }
 
         items.push(Bson::Document(item));
     }
 
-    Ok(data_response(Bson::Array(items)))
+    Ok(Json(doc! {
+        "data": items,
+        "meta": { "page": page as i64, "pageSize": page_size as i64, "total": total as i64 }
+    }).into_response())
 }
 
 // GET /restaurants/{id}
-async fn get_restaurant_by_id(Path(id): Path<String>, State(db): State<Database>) -> ApiResult{
+async fn get_restaurant_by_id(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let languages = preferred_languages(&headers);
     let collection = db.collection::<Document>("shops");
 
     // matching by id
@@ -83,26 +262,31 @@ async fn get_restaurant_by_id(Path(id): Path<String>, State(db): State<Database>
     let found = collection.find_one(filter)
         .await
         .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
-    match found {
+    match found.filter(shop_is_listed) {
         Some(doc) => {
             let id = document_id(&doc);
-            let name = get_string(&doc, "name");
+            let name = localized(&doc, "name", &languages);
             let image = get_string(&doc, "imageUrl");
+            let thumbnail = get_string(&doc, "thumbnailUrl");
+            let description = localized(&doc, "description", &languages);
             let address = get_string(&doc, "address");
             let phone = get_string(&doc, "phone");
-            let mut rating = doc.get("rating").and_then(Bson::as_f64)
-                .or_else(|| get_i64(&doc, "rating").map(|v| v as f64));
-            if rating.is_none() {
-                rating = rating_from_orders(&db, id.as_deref().unwrap_or("")).await;
-            }
 
             let mut body = Document::new();
             body.insert("id", match id { Some(v) => Bson::String(v), None => Bson::Null });
             body.insert("name", match name { Some(v) => Bson::String(v), None => Bson::Null });
+            body.insert("description", match description { Some(v) => Bson::String(v), None => Bson::Null });
             body.insert("imageUrl", match image { Some(v) => Bson::String(v), None => Bson::Null });
+            body.insert("thumbnailUrl", match thumbnail { Some(v) => Bson::String(v), None => Bson::Null });
             body.insert("address", match address { Some(v) => Bson::String(v), None => Bson::Null });
             body.insert("phone", match phone { Some(v) => Bson::String(v), None => Bson::Null });
-            body.insert("rating", match rating { Some(v) => Bson::Double(v), None => Bson::Null });
+            let latlng = doc_latlng(&doc);
+            body.insert("lat", latlng.map(|(lat, _)| lat));
+            body.insert("lng", latlng.map(|(_, lng)| lng));
+            body.extend(rating_view(&doc));
+            body.insert("openingHours", Bson::Array(get_array(&doc, "openingHours").unwrap_or_default()));
+            body.insert("closures", Bson::Array(get_array(&doc, "closures").unwrap_or_default()));
+            body.extend(open_status(&doc));
 
             Ok(data_response(Bson::Document(body)))
         }
@@ -112,79 +296,10 @@ async fn get_restaurant_by_id(Path(id): Path<String>, State(db): State<Database>
     }
 }
 
-// GET /restaurants/{id}/reviews
-async fn list_reviews(Path(id): Path<String>, State(db): State<Database>) -> ApiResult{
-    let collection = db.collection::<Document>("reviews");
-    let filter = doc! {
-        "$or": [
-            { "restaurantId": &id },
-            { "shop_id": &id },
-            { "restaurant_id": &id }
-        ]
-    };
-
-    let mut items: Vec<Bson> = Vec::new();
-
-    // legacy reviews collection
-    let mut cursor = collection.find(filter.clone())
-        .await
-        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Find error: {}", e)))?;
-    while let Some(doc) = cursor.try_next()
-        .await
-        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Cursor error: {}", e)))? {
-        let mut item = Document::new();
-        let id = document_id(&doc);
-        let user_name = get_string(&doc, "userName").or_else(|| get_string(&doc, "user_name"));
-        let rating = doc.get("rating").and_then(Bson::as_i32).map(|v| v as i64)
-            .or_else(|| doc.get("rating").and_then(Bson::as_i64));
-        let comment = get_string(&doc, "comment");
-        let created_at = doc.get("createdAt").and_then(iso_from_bson);
-
-        item.insert("id", match id { Some(v) => Bson::String(v), None => Bson::Null });
-        item.insert("userName", match user_name { Some(v) => Bson::String(v), None => Bson::Null });
-        item.insert("rating", match rating { Some(v) => Bson::Int64(v), None => Bson::Null });
-        item.insert("comment", match comment { Some(v) => Bson::String(v), None => Bson::Null });
-        item.insert("createdAt", match created_at { Some(v) => Bson::String(v), None => Bson::Null });
-        items.push(Bson::Document(item));
-    }
-
-    // ratings stored on orders
-    let orders = db.collection::<Document>("orders");
-    let mut order_cursor = orders.find(doc! {
-        "restaurantId": &id,
-        "rating": { "$exists": true }
-    }).await.map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Find error: {}", e)))?;
-
-    while let Some(doc) = order_cursor.try_next()
-        .await
-        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &format!("Cursor error: {}", e)))? {
-        let rating_doc = doc.get("rating").and_then(Bson::as_document);
-        let score = rating_doc.and_then(|d| match d.get("score") {
-            Some(Bson::Int32(v)) => Some(*v as i64),
-            Some(Bson::Int64(v)) => Some(*v),
-            Some(Bson::Double(v)) => Some(v.round() as i64),
-            _ => None,
-        });
-        if score.is_none() {
-            continue;
-        }
-        let comment = rating_doc.and_then(|d| d.get("comment")).and_then(Bson::as_str).map(|s| s.to_string());
-        let created_at = doc.get("placedAt").and_then(iso_from_bson).or_else(|| doc.get("createdAt").and_then(iso_from_bson));
-        let user_name = doc.get("customer")
-            .and_then(Bson::as_document)
-            .and_then(|c| c.get("name"))
-            .and_then(Bson::as_str)
-            .map(|s| s.to_string());
-        let mut item = Document::new();
-        item.insert("id", Bson::String(document_id(&doc).unwrap_or_default()));
-        item.insert("userName", match user_name { Some(v) => Bson::String(v), None => Bson::Null });
-        item.insert("rating", Bson::Int64(score.unwrap_or(0)));
-        item.insert("comment", match comment { Some(v) => Bson::String(v), None => Bson::Null });
-        item.insert("createdAt", match created_at { Some(v) => Bson::String(v), None => Bson::Null });
-        items.push(Bson::Document(item));
-    }
-
-    Ok(data_response(Bson::Array(items)))
+// GET /restaurants/{id}/reviews?sort=newest|rating&stars=&cursor=&limit=
+async fn list_reviews(Path(id): Path<String>, State(db): State<Database>, Query(query): Query<ReviewFeedQuery>) -> ApiResult{
+    let (items, next_cursor) = review_feed(&db, &id, &query).await?;
+    Ok(feed_response(items, next_cursor))
 }
 
 pub fn home_page_router(db: Database) -> Router{
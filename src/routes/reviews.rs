@@ -0,0 +1,360 @@
+#![allow(non_snake_case)]
+
+use axum::{Router, routing::{get, put}, extract::{State, Path, Query}, Json, http::HeaderMap, response::IntoResponse};
+use mongodb::{bson::{doc, Bson, Document, DateTime}, Database};
+use futures::stream::TryStreamExt;
+use serde::Deserialize;
+use axum::http::StatusCode;
+use crate::routes::moderation::check_banned_words;
+use crate::routes::ratings::{order_score, rating_edit_window_millis, review_score};
+use crate::routes::common::{ApiResult, data_response, error_response, get_string, require_role, claims_restaurant_id, now_datetime, iso_from_bson, id_filter};
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 50;
+const MAX_REPLY_CHARS: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct ReviewFeedQuery {
+    // newest (default) | rating
+    sort: Option<String>,
+    // comma separated star values, e.g. 1,2
+    stars: Option<String>,
+    // `nextCursor` from the previous page
+    cursor: Option<String>,
+    limit: Option<i64>,
+    // restaurant side only: just reviews still waiting for a reply
+    unreplied: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct ReplyRequest {
+    text: String,
+}
+
+// Where a page ends: (score for rating sort, createdAt millis, reviewId).
+struct FeedCursor {
+    score: Option<i64>,
+    created_at: i64,
+    review_id: String,
+}
+
+impl FeedCursor {
+    fn encode(&self) -> String{
+        match self.score {
+            Some(score) => format!("{}.{}.{}", score, self.created_at, self.review_id),
+            None => format!("{}.{}", self.created_at, self.review_id),
+        }
+    }
+
+    fn decode(raw: &str, by_rating: bool) -> Option<Self>{
+        let mut parts = raw.splitn(if by_rating { 3 } else { 2 }, '.');
+        let score = if by_rating { Some(parts.next()?.parse().ok()?) } else { None };
+        let created_at = parts.next()?.parse().ok()?;
+        let review_id = parts.next()?.to_string();
+        Some(FeedCursor { score, created_at, review_id })
+    }
+
+    // Everything strictly after this position in descending (score,) createdAt, reviewId order.
+    fn after(&self) -> Document{
+        let created_at = DateTime::from_millis(self.created_at);
+        let same_time = doc! { "createdAt": created_at, "reviewId": { "$lt": &self.review_id } };
+        let by_time = vec![Bson::Document(doc! { "createdAt": { "$lt": created_at } }), Bson::Document(same_time)];
+        match self.score {
+            Some(score) => doc! { "$or": [
+                { "score": { "$lt": score } },
+                { "score": score, "$or": by_time }
+            ] },
+            None => doc! { "$or": by_time },
+        }
+    }
+}
+
+fn restaurant_match(restaurant_id: &str) -> Document{
+    doc! {
+        "$or": [
+            { "restaurantId": restaurant_id },
+            { "shop_id": restaurant_id },
+            { "restaurant_id": restaurant_id }
+        ]
+    }
+}
+
+// Reviews come from the legacy `reviews` collection and from ratings stored on orders;
+// both are projected to one shape so they can be sorted and paged together.
+fn feed_pipeline(restaurant_id: &str) -> Vec<Document>{
+    let epoch = DateTime::from_millis(0);
+    vec![
+        doc! { "$match": { "$and": [restaurant_match(restaurant_id), { "hidden": { "$ne": true } }] } },
+        doc! { "$project": {
+            "_id": 0,
+            "reviewId": { "$ifNull": ["$id", { "$toString": "$_id" }] },
+            "source": "review",
+            "score": { "$toInt": { "$round": [{ "$convert": { "input": "$rating", "to": "double", "onError": 0, "onNull": 0 } }, 0] } },
+            "comment": "$comment",
+            "userName": { "$ifNull": ["$userName", "$user_name"] },
+            "createdAt": { "$ifNull": [{ "$convert": { "input": "$createdAt", "to": "date", "onError": null, "onNull": null } }, epoch] },
+            "reply": "$reply"
+        } },
+        doc! { "$unionWith": {
+            "coll": "orders",
+            "pipeline": [
+                { "$match": { "restaurantId": restaurant_id, "rating.score": { "$exists": true }, "rating.hidden": { "$ne": true } } },
+                { "$project": {
+                    "_id": 0,
+                    "reviewId": "$id",
+                    "source": "order",
+                    "score": { "$toInt": { "$round": [{ "$convert": { "input": "$rating.score", "to": "double", "onError": 0, "onNull": 0 } }, 0] } },
+                    "comment": "$rating.comment",
+                    "tags": "$rating.tags",
+                    "userName": "$customer.name",
+                    "createdAt": { "$ifNull": ["$rating.createdAt", "$placedAt", "$createdAt", epoch] },
+                    "reply": "$rating.reply"
+                } }
+            ]
+        } },
+    ]
+}
+
+// A review looked up by id, from either source.
+pub struct FoundReview {
+    pub id: String,
+    // "order" or "review"
+    pub source: &'static str,
+    pub restaurant_id: String,
+    pub score: Option<i64>,
+    pub hidden: bool,
+}
+
+impl FoundReview {
+    // Field path on the stored document: order ratings live under `rating`.
+    pub fn field(&self, name: &str) -> String{
+        match self.source {
+            "order" => format!("rating.{}", name),
+            _ => name.to_string(),
+        }
+    }
+
+    pub async fn update(&self, db: &Database, update: Document) -> mongodb::error::Result<()>{
+        match self.source {
+            "order" => db.collection::<Document>("orders").update_one(doc! { "id": &self.id }, update).await?,
+            _ => db.collection::<Document>("reviews").update_one(id_filter(&self.id), update).await?,
+        };
+        Ok(())
+    }
+}
+
+pub async fn find_review(db: &Database, id: &str) -> Result<Option<FoundReview>, (StatusCode, Json<Document>)>{
+    let order = db.collection::<Document>("orders")
+        .find_one(doc! { "id": id, "rating.score": { "$exists": true } })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    if let Some(order) = order {
+        let rating = order.get_document("rating").cloned().unwrap_or_default();
+        return Ok(Some(FoundReview {
+            id: id.to_string(),
+            source: "order",
+            restaurant_id: get_string(&order, "restaurantId").unwrap_or_default(),
+            score: order_score(&order),
+            hidden: rating.get_bool("hidden").unwrap_or(false),
+        }));
+    }
+    let review = db.collection::<Document>("reviews")
+        .find_one(id_filter(id))
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    Ok(review.map(|review| FoundReview {
+        id: id.to_string(),
+        source: "review",
+        restaurant_id: get_string(&review, "restaurantId")
+            .or_else(|| get_string(&review, "shop_id"))
+            .or_else(|| get_string(&review, "restaurant_id"))
+            .unwrap_or_default(),
+        score: review_score(&review),
+        hidden: review.get_bool("hidden").unwrap_or(false),
+    }))
+}
+
+fn legacy_review_filter(restaurant_id: &str, review_id: &str) -> Document{
+    let mut filter = restaurant_match(restaurant_id);
+    filter.insert("$and", vec![Bson::Document(id_filter(review_id))]);
+    filter
+}
+
+fn parse_stars(raw: Option<&str>) -> Result<Vec<i64>, (StatusCode, Json<Document>)>{
+    let mut stars = Vec::new();
+    for part in raw.unwrap_or_default().split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        match part.parse::<i64>() {
+            Ok(star) if (1..=5).contains(&star) => stars.push(star),
+            _ => return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "stars must be values 1-5")),
+        }
+    }
+    Ok(stars)
+}
+
+fn reply_view(reply: Option<&Document>) -> Bson{
+    match reply {
+        Some(r) => Bson::Document(doc! {
+            "text": get_string(r, "text").unwrap_or_default(),
+            "createdAt": r.get("createdAt").and_then(iso_from_bson),
+            "updatedAt": r.get("updatedAt").and_then(iso_from_bson)
+        }),
+        None => Bson::Null,
+    }
+}
+
+// A rating as the customer sees it on their order, with the restaurant's reply underneath.
+pub fn order_rating_view(rating: &Document) -> Document{
+    let editable_until = rating.get_datetime("createdAt").ok()
+        .map(|t| Bson::DateTime(DateTime::from_millis(t.timestamp_millis() + rating_edit_window_millis())));
+    doc! {
+        "score": rating.get("score").cloned().unwrap_or(Bson::Null),
+        "riderScore": rating.get("riderScore").cloned().unwrap_or(Bson::Null),
+        "comment": get_string(rating, "comment"),
+        "tags": rating.get("tags").cloned().unwrap_or_else(|| Bson::Array(Vec::new())),
+        "createdAt": rating.get("createdAt").and_then(iso_from_bson),
+        "updatedAt": rating.get("updatedAt").and_then(iso_from_bson),
+        // legacy ratings without a timestamp are already locked
+        "editableUntil": editable_until.as_ref().and_then(iso_from_bson),
+        "reply": reply_view(rating.get_document("reply").ok())
+    }
+}
+
+// One page of a restaurant's reviews and the cursor for the next page, if there is one.
+pub async fn review_feed(db: &Database, restaurant_id: &str, query: &ReviewFeedQuery) -> Result<(Vec<Bson>, Option<String>), (StatusCode, Json<Document>)>{
+    let by_rating = match query.sort.as_deref().unwrap_or("newest") {
+        "newest" => false,
+        "rating" => true,
+        _ => return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "sort must be newest or rating")),
+    };
+    let stars = parse_stars(query.stars.as_deref())?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    let mut pipeline = feed_pipeline(restaurant_id);
+    if !stars.is_empty() {
+        pipeline.push(doc! { "$match": { "score": { "$in": stars } } });
+    }
+    if query.unreplied == Some(true) {
+        pipeline.push(doc! { "$match": { "reply": null } });
+    }
+    if let Some(raw) = query.cursor.as_deref() {
+        let cursor = FeedCursor::decode(raw, by_rating)
+            .ok_or_else(|| error_response(StatusCode::BAD_REQUEST, "validation.failed", "invalid cursor"))?;
+        pipeline.push(doc! { "$match": cursor.after() });
+    }
+    pipeline.push(if by_rating {
+        doc! { "$sort": { "score": -1, "createdAt": -1, "reviewId": -1 } }
+    } else {
+        doc! { "$sort": { "createdAt": -1, "reviewId": -1 } }
+    });
+    // one extra row tells us whether another page exists
+    pipeline.push(doc! { "$limit": limit + 1 });
+
+    let mut rows: Vec<Document> = db.collection::<Document>("reviews").aggregate(pipeline)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .try_collect()
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let has_more = rows.len() as i64 > limit;
+    rows.truncate(limit as usize);
+
+    let next_cursor = match rows.last() {
+        Some(last) if has_more => Some(FeedCursor {
+            score: by_rating.then(|| last.get("score").and_then(|s| s.as_i32().map(|v| v as i64).or_else(|| s.as_i64())).unwrap_or(0)),
+            created_at: last.get_datetime("createdAt").map(|d| d.timestamp_millis()).unwrap_or(0),
+            review_id: get_string(last, "reviewId").unwrap_or_default(),
+        }.encode()),
+        _ => None,
+    };
+    let items = rows.iter().map(|row| {
+        let created_at = row.get_datetime("createdAt").ok().filter(|d| d.timestamp_millis() > 0).map(|d| Bson::DateTime(*d));
+        Bson::Document(doc! {
+            "id": get_string(row, "reviewId").unwrap_or_default(),
+            "source": get_string(row, "source").unwrap_or_default(),
+            "userName": get_string(row, "userName"),
+            "rating": row.get("score").cloned().unwrap_or(Bson::Null),
+            "comment": get_string(row, "comment"),
+            "tags": row.get("tags").cloned().unwrap_or_else(|| Bson::Array(Vec::new())),
+            "createdAt": created_at.as_ref().and_then(iso_from_bson),
+            "reply": reply_view(row.get_document("reply").ok())
+        })
+    }).collect();
+    Ok((items, next_cursor))
+}
+
+pub fn feed_response(items: Vec<Bson>, next_cursor: Option<String>) -> axum::response::Response{
+    Json(doc! { "data": items, "meta": { "nextCursor": next_cursor } }).into_response()
+}
+
+// GET /restaurant/reviews?sort=&stars=&cursor=&limit=&unreplied=
+async fn list_own_reviews(State(db): State<Database>, headers: HeaderMap, Query(query): Query<ReviewFeedQuery>) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let (items, next_cursor) = review_feed(&db, &claims_restaurant_id(&claims), &query).await?;
+    Ok(feed_response(items, next_cursor))
+}
+
+// PUT /restaurant/reviews/{id}/reply
+// A review has at most one public reply; putting again edits it.
+async fn put_reply(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, Json(payload): Json<ReplyRequest>) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let restaurant_id = claims_restaurant_id(&claims);
+    let text = payload.text.trim();
+    if text.is_empty() || text.chars().count() > MAX_REPLY_CHARS {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("reply must be 1-{} characters", MAX_REPLY_CHARS)));
+    }
+    check_banned_words(&db, Some(text)).await?;
+    let now = now_datetime();
+
+    // ratings on orders first, then the legacy collection
+    let orders = db.collection::<Document>("orders");
+    let order_filter = doc! { "id": &id, "restaurantId": &restaurant_id, "rating.score": { "$exists": true } };
+    let update = doc! {
+        "$set": { "rating.reply.text": text, "rating.reply.updatedAt": now },
+        "$min": { "rating.reply.createdAt": now }
+    };
+    let result = orders.update_one(order_filter, update)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    if result.matched_count == 0 {
+        let review_filter = legacy_review_filter(&restaurant_id, &id);
+        let update = doc! {
+            "$set": { "reply.text": text, "reply.updatedAt": now },
+            "$min": { "reply.createdAt": now }
+        };
+        let result = db.collection::<Document>("reviews").update_one(review_filter, update)
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        if result.matched_count == 0 {
+            return Err(error_response(StatusCode::NOT_FOUND, "review.not_found", "Review not found"));
+        }
+    }
+    Ok(data_response(Bson::Document(doc! { "id": &id, "reply": { "text": text, "updatedAt": iso_from_bson(&Bson::DateTime(now)) } })))
+}
+
+// DELETE /restaurant/reviews/{id}/reply
+async fn delete_reply(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let restaurant_id = claims_restaurant_id(&claims);
+    let result = db.collection::<Document>("orders")
+        .update_one(doc! { "id": &id, "restaurantId": &restaurant_id, "rating.reply": { "$exists": true } }, doc! { "$unset": { "rating.reply": "" } })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    if result.matched_count == 0 {
+        let mut review_filter = legacy_review_filter(&restaurant_id, &id);
+        review_filter.insert("reply", doc! { "$exists": true });
+        let result = db.collection::<Document>("reviews").update_one(review_filter, doc! { "$unset": { "reply": "" } })
+            .await
+            .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+        if result.matched_count == 0 {
+            return Err(error_response(StatusCode::NOT_FOUND, "review.not_found", "Reply not found"));
+        }
+    }
+    Ok(data_response(Bson::Document(doc! { "id": &id, "reply": Bson::Null })))
+}
+
+pub fn reviews_router(db: Database) -> Router{
+    Router::new()
+        .route("/reviews", get(list_own_reviews))
+        .route("/reviews/{id}/reply", put(put_reply).delete(delete_reply))
+        .with_state(db)
+}
@@ -0,0 +1,215 @@
+use axum::Json;
+use axum::http::StatusCode;
+use chrono::{DateTime, Datelike, FixedOffset, Timelike};
+use futures::stream::TryStreamExt;
+use mongodb::{bson::{doc, Bson, Document}, Database};
+use serde::Deserialize;
+use std::collections::HashMap;
+use crate::routes::common::{error_response, get_string, taipei_now, menu_restaurant_id};
+
+// A weekly time window in Asia/Taipei. `days` are ISO weekdays (1 = Monday .. 7 = Sunday),
+// `start`/`end` are "HH:MM". An `end` at or before `start` runs past midnight into the next day.
+#[derive(Deserialize, Clone)]
+pub struct TimeWindow {
+    pub days: Vec<u32>,
+    pub start: String,
+    pub end: String,
+}
+
+pub fn parse_hhmm(value: &str) -> Option<u32>{
+    let (h, m) = value.split_once(':')?;
+    let h: u32 = h.parse().ok()?;
+    let m: u32 = m.parse().ok()?;
+    if h > 24 || m > 59 || (h == 24 && m != 0) {
+        return None;
+    }
+    Some(h * 60 + m)
+}
+
+pub fn validate_windows(windows: &[TimeWindow]) -> Result<(), (StatusCode, Json<Document>)>{
+    for window in windows {
+        if window.days.is_empty() || window.days.iter().any(|d| !(1..=7).contains(d)) {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "days must be 1 (Mon) - 7 (Sun)"));
+        }
+        if parse_hhmm(&window.start).is_none() || parse_hhmm(&window.end).is_none() {
+            return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "start/end must be HH:MM"));
+        }
+    }
+    Ok(())
+}
+
+pub fn windows_to_bson(windows: &[TimeWindow]) -> Bson{
+    Bson::Array(windows.iter().map(|w| {
+        Bson::Document(doc! {
+            "days": w.days.iter().map(|d| *d as i64).collect::<Vec<i64>>(),
+            "start": &w.start,
+            "end": &w.end
+        })
+    }).collect())
+}
+
+fn window_days(window: &Document) -> Vec<u32>{
+    window.get_array("days")
+        .map(|days| days.iter().filter_map(|d| match d {
+            Bson::Int32(v) => Some(*v as u32),
+            Bson::Int64(v) => Some(*v as u32),
+            Bson::Double(v) => Some(*v as u32),
+            _ => None,
+        }).collect())
+        .unwrap_or_default()
+}
+
+fn window_contains(window: &Document, at: &DateTime<FixedOffset>) -> bool{
+    let (Some(start), Some(end)) = (
+        get_string(window, "start").as_deref().and_then(parse_hhmm),
+        get_string(window, "end").as_deref().and_then(parse_hhmm),
+    ) else {
+        return false;
+    };
+    let days = window_days(window);
+    let today = at.weekday().number_from_monday();
+    let yesterday = if today == 1 { 7 } else { today - 1 };
+    let minute = at.hour() * 60 + at.minute();
+    if start < end {
+        days.contains(&today) && minute >= start && minute < end
+    } else {
+        (days.contains(&today) && minute >= start) || (days.contains(&yesterday) && minute < end)
+    }
+}
+
+// No windows (or an empty list) means "always"; otherwise any matching window is enough.
+pub fn windows_allow(windows: Option<&Vec<Bson>>, at: &DateTime<FixedOffset>) -> bool{
+    match windows {
+        Some(list) if !list.is_empty() => list.iter()
+            .filter_map(Bson::as_document)
+            .any(|w| window_contains(w, at)),
+        _ => true,
+    }
+}
+
+// An item's own windows win; items without any fall back to their category's windows.
+pub fn item_in_window(menu_doc: &Document, category_doc: Option<&Document>, at: &DateTime<FixedOffset>) -> bool{
+    match menu_doc.get_array("availability") {
+        Ok(own) if !own.is_empty() => windows_allow(Some(own), at),
+        _ => windows_allow(category_doc.and_then(|c| c.get_array("availability").ok()), at),
+    }
+}
+
+// how far in the past a requested time may be, to allow for clock skew and slow checkouts
+const REQUESTED_TIME_GRACE_MINUTES: i64 = 5;
+
+// The moment an order is for: its requested time, or now when none is given.
+// A time that doesn't parse, or that has already passed, is rejected rather than replaced.
+pub fn order_time(requested_time: Option<&str>) -> Result<DateTime<FixedOffset>, (StatusCode, Json<Document>)>{
+    let now = taipei_now();
+    let Some(requested) = requested_time.map(str::trim).filter(|t| !t.is_empty()) else {
+        return Ok(now);
+    };
+    let at = DateTime::parse_from_rfc3339(requested)
+        .map_err(|_| error_response(StatusCode::BAD_REQUEST, "validation.failed", "requestedTime must be an RFC 3339 timestamp"))?
+        .with_timezone(now.offset());
+    if at < now - chrono::Duration::minutes(REQUESTED_TIME_GRACE_MINUTES) {
+        return Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "requestedTime must not be in the past"));
+    }
+    Ok(at)
+}
+
+pub async fn load_category(db: &Database, menu_doc: &Document) -> Option<Document>{
+    let name = get_string(menu_doc, "category")?;
+    let restaurant_id = menu_restaurant_id(menu_doc)?;
+    db.collection::<Document>("menu_categories")
+        .find_one(doc! { "restaurantId": restaurant_id, "name": name })
+        .await
+        .ok()
+        .flatten()
+}
+
+pub async fn load_categories(db: &Database, restaurant_id: &str) -> HashMap<String, Document>{
+    let mut categories = HashMap::new();
+    if let Ok(mut cursor) = db.collection::<Document>("menu_categories").find(doc! { "restaurantId": restaurant_id }).await {
+        while let Ok(Some(category)) = cursor.try_next().await {
+            if let Some(name) = get_string(&category, "name") {
+                categories.insert(name, category);
+            }
+        }
+    }
+    categories
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // Monday 2026-10-19 in Taipei
+    fn monday(hour: u32, minute: u32) -> DateTime<FixedOffset>{
+        FixedOffset::east_opt(8 * 60 * 60).unwrap().with_ymd_and_hms(2026, 10, 19, hour, minute, 0).unwrap()
+    }
+
+    fn window(days: &[u32], start: &str, end: &str) -> TimeWindow{
+        TimeWindow { days: days.to_vec(), start: start.to_string(), end: end.to_string() }
+    }
+
+    fn windows(list: &[TimeWindow]) -> Vec<Bson>{
+        match windows_to_bson(list) {
+            Bson::Array(list) => list,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn parses_clock_times_up_to_midnight() {
+        assert_eq!(parse_hhmm("00:00"), Some(0));
+        assert_eq!(parse_hhmm("9:30"), Some(570));
+        assert_eq!(parse_hhmm("24:00"), Some(1440));
+        assert_eq!(parse_hhmm("24:01"), None);
+        assert_eq!(parse_hhmm("12:60"), None);
+        assert_eq!(parse_hhmm("noon"), None);
+    }
+
+    #[test]
+    fn validate_windows_checks_days_and_times() {
+        assert!(validate_windows(&[window(&[1, 7], "11:00", "14:00")]).is_ok());
+        assert!(validate_windows(&[window(&[], "11:00", "14:00")]).is_err());
+        assert!(validate_windows(&[window(&[0], "11:00", "14:00")]).is_err());
+        assert!(validate_windows(&[window(&[8], "11:00", "14:00")]).is_err());
+        assert!(validate_windows(&[window(&[1], "11", "14:00")]).is_err());
+    }
+
+    #[test]
+    fn no_windows_means_always() {
+        assert!(windows_allow(None, &monday(3, 0)));
+        assert!(windows_allow(Some(&Vec::new()), &monday(3, 0)));
+    }
+
+    #[test]
+    fn window_includes_its_start_but_not_its_end() {
+        let lunch = windows(&[window(&[1], "11:00", "14:00")]);
+        assert!(!windows_allow(Some(&lunch), &monday(10, 59)));
+        assert!(windows_allow(Some(&lunch), &monday(11, 0)));
+        assert!(!windows_allow(Some(&lunch), &monday(14, 0)));
+        // Tuesday
+        assert!(!windows_allow(Some(&lunch), &(monday(12, 0) + chrono::Duration::days(1))));
+    }
+
+    #[test]
+    fn overnight_windows_run_into_the_next_day() {
+        // Sunday night to Monday morning
+        let late = windows(&[window(&[7], "22:00", "02:00")]);
+        assert!(windows_allow(Some(&late), &monday(1, 30)));
+        assert!(!windows_allow(Some(&late), &monday(2, 0)));
+        assert!(!windows_allow(Some(&late), &monday(23, 0)));
+    }
+
+    #[test]
+    fn item_windows_override_the_category() {
+        let category = doc! { "availability": windows(&[window(&[1], "06:00", "10:00")]) };
+        let breakfast_item = doc! { "name": "toast" };
+        assert!(item_in_window(&breakfast_item, Some(&category), &monday(8, 0)));
+        assert!(!item_in_window(&breakfast_item, Some(&category), &monday(12, 0)));
+
+        let all_day = doc! { "name": "tea", "availability": windows(&[window(&[1], "06:00", "20:00")]) };
+        assert!(item_in_window(&all_day, Some(&category), &monday(12, 0)));
+        assert!(item_in_window(&breakfast_item, None, &monday(12, 0)));
+    }
+}
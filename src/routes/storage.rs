@@ -0,0 +1,98 @@
+use axum::{Router, routing::get, extract::Path, http::header, response::IntoResponse};
+use axum::http::StatusCode;
+use std::path::{Component, PathBuf};
+use crate::routes::common::{ApiResult, error_response};
+
+// Where uploaded images live. Backends are blocking; call them from `spawn_blocking`.
+pub trait ImageStorage: Send + Sync {
+    // Store `bytes` under `key` and return the public URL clients should use.
+    fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<String>;
+    fn delete(&self, key: &str) -> std::io::Result<()>;
+}
+
+// Default backend: files under UPLOAD_DIR, served by this app at /uploads.
+pub struct LocalStorage {
+    root: PathBuf,
+    base_url: String,
+}
+
+impl LocalStorage {
+    pub fn from_env() -> Self{
+        LocalStorage {
+            root: PathBuf::from(std::env::var("UPLOAD_DIR").unwrap_or_else(|_| "uploads".to_string())),
+            // e.g. https://api.example.com; empty keeps URLs relative to this server
+            base_url: std::env::var("PUBLIC_BASE_URL").unwrap_or_default().trim_end_matches('/').to_string(),
+        }
+    }
+
+    // Resolve a key inside the upload root, refusing anything that could escape it.
+    fn path_for(&self, key: &str) -> Option<PathBuf>{
+        let relative = PathBuf::from(key);
+        if relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            Some(self.root.join(relative))
+        } else {
+            None
+        }
+    }
+}
+
+impl ImageStorage for LocalStorage {
+    fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<String>{
+        let path = self.path_for(key).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid storage key"))?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, bytes)?;
+        Ok(format!("{}/uploads/{}", self.base_url, key))
+    }
+
+    fn delete(&self, key: &str) -> std::io::Result<()>{
+        match self.path_for(key) {
+            Some(path) => std::fs::remove_file(path),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn storage_backend() -> Box<dyn ImageStorage>{
+    match std::env::var("STORAGE_BACKEND").as_deref() {
+        Ok("local") | Err(_) => Box::new(LocalStorage::from_env()),
+        Ok(other) => {
+            eprintln!("storage: unknown STORAGE_BACKEND {}, using local", other);
+            Box::new(LocalStorage::from_env())
+        }
+    }
+}
+
+fn content_type_for(key: &str) -> &'static str{
+    match key.rsplit('.').next().map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+// GET /uploads/{*key}
+async fn serve_upload(Path(key): Path<String>) -> ApiResult{
+    let storage = LocalStorage::from_env();
+    let Some(path) = storage.path_for(&key) else {
+        return Err(error_response(StatusCode::NOT_FOUND, "file.not_found", "File not found"));
+    };
+    let bytes = tokio::fs::read(path)
+        .await
+        .map_err(|_| error_response(StatusCode::NOT_FOUND, "file.not_found", "File not found"))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type_for(&key)),
+            (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+        ],
+        bytes,
+    ).into_response())
+}
+
+pub fn files_router() -> Router{
+    Router::new()
+        .route("/{*key}", get(serve_upload))
+}
@@ -0,0 +1,266 @@
+use axum::{Router, routing::post, extract::{State, Path, Multipart, DefaultBodyLimit}, Json, http::HeaderMap};
+use mongodb::{bson::{doc, Bson, Document}, Database};
+use axum::http::StatusCode;
+use image::{DynamicImage, ImageFormat, ImageReader, imageops::FilterType};
+use std::io::Cursor;
+use crate::routes::common::{ApiResult, data_response, data_response_with_status, error_response, get_array, require_role, claims_restaurant_id, menu_item_filter, menu_restaurant_id};
+use crate::routes::onboarding::{MAX_DOCUMENTS, applicant_claims};
+use crate::routes::restaurant::map_menu_item;
+use crate::routes::storage::storage_backend;
+
+const ALLOWED_TYPES: [&str; 3] = ["image/jpeg", "image/png", "image/webp"];
+const MAX_DIMENSION: u32 = 1280;
+const THUMB_DIMENSION: u32 = 320;
+// anything larger is refused from its header, before a decode can allocate for it
+const MAX_SOURCE_DIMENSION: u32 = 8000;
+const MAX_SOURCE_PIXELS: u64 = 40_000_000;
+// application documents are kept as sent, so scans and PDFs are both fine
+const DOCUMENT_TYPES: [&str; 4] = ["image/jpeg", "image/png", "image/webp", "application/pdf"];
+const MAX_DOCUMENT_TYPE_CHARS: usize = 100;
+
+fn max_upload_bytes() -> usize{
+    std::env::var("MAX_UPLOAD_BYTES").ok().and_then(|v| v.parse().ok()).unwrap_or(5 * 1024 * 1024)
+}
+
+// Only the body limit means the file was too large; any other read failure is a bad request.
+fn read_error(e: axum::extract::multipart::MultipartError) -> (StatusCode, Json<Document>){
+    if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        error_response(StatusCode::PAYLOAD_TOO_LARGE, "upload.too_large", "file too large")
+    } else {
+        error_response(StatusCode::BAD_REQUEST, "validation.failed", &e.body_text())
+    }
+}
+
+fn check_dimensions(bytes: &[u8], format: ImageFormat) -> Result<(), (StatusCode, Json<Document>)>{
+    let (width, height) = ImageReader::with_format(Cursor::new(bytes), format)
+        .into_dimensions()
+        .map_err(|_| error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "upload.type", "image could not be decoded"))?;
+    if width > MAX_SOURCE_DIMENSION || height > MAX_SOURCE_DIMENSION || width as u64 * height as u64 > MAX_SOURCE_PIXELS {
+        return Err(error_response(StatusCode::PAYLOAD_TOO_LARGE, "upload.too_large", &format!("images may be at most {0}x{0} pixels", MAX_SOURCE_DIMENSION)));
+    }
+    Ok(())
+}
+
+// Pull the `file` part out of the form, checking declared type, size and the actual bytes.
+async fn read_image(mut multipart: Multipart) -> Result<(Vec<u8>, ImageFormat), (StatusCode, Json<Document>)>{
+    while let Some(field) = multipart.next_field()
+        .await
+        .map_err(read_error)? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let declared = field.content_type().unwrap_or_default().to_ascii_lowercase();
+        if !ALLOWED_TYPES.contains(&declared.as_str()) {
+            return Err(error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "upload.type", "only JPEG, PNG or WebP images are accepted"));
+        }
+        let bytes = field.bytes().await.map_err(read_error)?;
+        if bytes.len() > max_upload_bytes() {
+            return Err(error_response(StatusCode::PAYLOAD_TOO_LARGE, "upload.too_large", "image too large"));
+        }
+        // the declared type is only a hint; the bytes have to agree with it
+        let format = image::guess_format(&bytes)
+            .ok()
+            .filter(|f| ALLOWED_TYPES.contains(&f.to_mime_type()))
+            .filter(|f| f.to_mime_type() == declared)
+            .ok_or_else(|| error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "upload.type", "file content does not match its type"))?;
+        return Ok((bytes.to_vec(), format));
+    }
+    Err(error_response(StatusCode::BAD_REQUEST, "validation.failed", "file field required"))
+}
+
+// Pull the document `type` and `file` out of an application upload form.
+async fn read_document(mut multipart: Multipart) -> Result<(String, Vec<u8>, &'static str), (StatusCode, Json<Document>)>{
+    let mut kind: Option<String> = None;
+    let mut file: Option<(Vec<u8>, &'static str)> = None;
+    while let Some(field) = multipart.next_field()
+        .await
+        .map_err(read_error)? {
+        match field.name() {
+            Some("type") => {
+                let text = field.text().await.map_err(read_error)?;
+                kind = Some(text.trim().to_string());
+            }
+            Some("file") => {
+                let declared = field.content_type().unwrap_or_default().to_ascii_lowercase();
+                if !DOCUMENT_TYPES.contains(&declared.as_str()) {
+                    return Err(error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "upload.type", "only JPEG, PNG, WebP or PDF files are accepted"));
+                }
+                let bytes = field.bytes().await.map_err(read_error)?;
+                if bytes.len() > max_upload_bytes() {
+                    return Err(error_response(StatusCode::PAYLOAD_TOO_LARGE, "upload.too_large", "file too large"));
+                }
+                let actual = if bytes.starts_with(b"%PDF-") {
+                    Some("application/pdf")
+                } else {
+                    image::guess_format(&bytes).ok().map(|f| f.to_mime_type())
+                };
+                let ext = match actual.filter(|a| *a == declared) {
+                    Some("application/pdf") => "pdf",
+                    Some("image/jpeg") => "jpg",
+                    Some("image/png") => "png",
+                    Some("image/webp") => "webp",
+                    _ => return Err(error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "upload.type", "file content does not match its type")),
+                };
+                file = Some((bytes.to_vec(), ext));
+            }
+            _ => {}
+        }
+    }
+    let kind = kind.filter(|k| !k.is_empty() && k.chars().count() <= MAX_DOCUMENT_TYPE_CHARS)
+        .ok_or_else(|| error_response(StatusCode::BAD_REQUEST, "validation.failed", &format!("type must be 1-{} characters", MAX_DOCUMENT_TYPE_CHARS)))?;
+    let (bytes, ext) = file.ok_or_else(|| error_response(StatusCode::BAD_REQUEST, "validation.failed", "file field required"))?;
+    Ok((kind, bytes, ext))
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> image::ImageResult<Vec<u8>>{
+    let mut out = Cursor::new(Vec::new());
+    match format {
+        // JPEG has no alpha channel
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut out, format)?,
+        _ => image.write_to(&mut out, format)?,
+    }
+    Ok(out.into_inner())
+}
+
+// Resize, store both sizes and return (imageUrl, thumbnailUrl, storage keys).
+async fn store_image(prefix: String, bytes: Vec<u8>, format: ImageFormat) -> Result<(String, String, Vec<String>), (StatusCode, Json<Document>)>{
+    tokio::task::spawn_blocking(move || {
+        check_dimensions(&bytes, format)?;
+        let decoded = image::load_from_memory_with_format(&bytes, format)
+            .map_err(|_| error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, "upload.type", "image could not be decoded"))?;
+        let full = if decoded.width() > MAX_DIMENSION || decoded.height() > MAX_DIMENSION {
+            decoded.resize(MAX_DIMENSION, MAX_DIMENSION, FilterType::Lanczos3)
+        } else {
+            decoded.clone()
+        };
+        let thumb = decoded.thumbnail(THUMB_DIMENSION, THUMB_DIMENSION);
+        let encode_err = |e: image::ImageError| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string());
+        let full_bytes = encode(&full, format).map_err(encode_err)?;
+        let thumb_bytes = encode(&thumb, format).map_err(encode_err)?;
+
+        let ext = format.extensions_str().first().copied().unwrap_or("img");
+        let name = mongodb::bson::oid::ObjectId::new().to_hex();
+        let full_key = format!("{}/{}.{}", prefix, name, ext);
+        let thumb_key = format!("{}/{}_thumb.{}", prefix, name, ext);
+        let storage = storage_backend();
+        let store_err = |e: std::io::Error| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string());
+        let image_url = storage.put(&full_key, &full_bytes).map_err(store_err)?;
+        let thumb_url = storage.put(&thumb_key, &thumb_bytes).map_err(store_err)?;
+        Ok((image_url, thumb_url, vec![full_key, thumb_key]))
+    })
+    .await
+    .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+}
+
+// Drop the files an image replaced; failures only leave orphans behind.
+fn remove_old_images(doc: &Document){
+    let keys: Vec<String> = get_array(doc, "imageKeys").unwrap_or_default()
+        .iter()
+        .filter_map(|k| k.as_str().map(|s| s.to_string()))
+        .collect();
+    if keys.is_empty() {
+        return;
+    }
+    tokio::task::spawn_blocking(move || {
+        let storage = storage_backend();
+        for key in keys {
+            if let Err(e) = storage.delete(&key) {
+                eprintln!("uploads.remove_old_images {}: {}", key, e);
+            }
+        }
+    });
+}
+
+// POST /restaurant/menu/{id}/image
+async fn upload_menu_image(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, multipart: Multipart) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let collection = db.collection::<Document>("menu");
+    let existing = collection.find_one(menu_item_filter(&id))
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let Some(menu_doc) = existing else {
+        return Err(error_response(StatusCode::NOT_FOUND, "menu.unavailable", "Menu item not found"));
+    };
+    if let Some(rest_id) = menu_restaurant_id(&menu_doc)
+        && rest_id != claims_restaurant_id(&claims) {
+        return Err(error_response(StatusCode::FORBIDDEN, "auth.forbidden", "forbidden"));
+    }
+
+    let (bytes, format) = read_image(multipart).await?;
+    let (image_url, thumb_url, keys) = store_image(format!("menu/{}", id), bytes, format).await?;
+    let update = doc! { "$set": { "imageUrl": &image_url, "thumbnailUrl": &thumb_url, "imageKeys": keys } };
+    let updated = collection.find_one_and_update(menu_item_filter(&id), update)
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "menu.unavailable", "Menu item not found"))?;
+    remove_old_images(&menu_doc);
+    Ok(data_response(Bson::Document(map_menu_item(&updated))))
+}
+
+// POST /restaurant/profile/image
+async fn upload_shop_image(State(db): State<Database>, headers: HeaderMap, multipart: Multipart) -> ApiResult{
+    let claims = require_role(&headers, &["restaurant"])?;
+    let restaurant_id = claims_restaurant_id(&claims);
+    let shops = db.collection::<Document>("shops");
+    let Some(shop) = shops.find_one(doc! { "id": &restaurant_id })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))? else {
+        return Err(error_response(StatusCode::NOT_FOUND, "restaurant.not_found", "Restaurant not found"));
+    };
+
+    let (bytes, format) = read_image(multipart).await?;
+    let (image_url, thumb_url, keys) = store_image(format!("shops/{}", restaurant_id), bytes, format).await?;
+    shops.update_one(doc! { "id": &restaurant_id }, doc! { "$set": { "imageUrl": &image_url, "thumbnailUrl": &thumb_url, "imageKeys": keys } })
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    remove_old_images(&shop);
+    Ok(data_response(Bson::Document(doc! { "imageUrl": image_url, "thumbnailUrl": thumb_url })))
+}
+
+// POST /restaurant-applications/{id}/documents
+// Applicants have no restaurant login yet, so this takes the applicant token from submission.
+async fn upload_application_document(Path(id): Path<String>, State(db): State<Database>, headers: HeaderMap, multipart: Multipart) -> ApiResult{
+    applicant_claims(&headers, &id)?;
+    let (kind, bytes, ext) = read_document(multipart).await?;
+    let key = format!("applications/{}/{}.{}", id, mongodb::bson::oid::ObjectId::new().to_hex(), ext);
+    let stored_key = key.clone();
+    let url = tokio::task::spawn_blocking(move || storage_backend().put(&stored_key, &bytes))
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+
+    // only while the application is still pending and has room for another file
+    let filter = doc! { "id": &id, "status": "pending", format!("documents.{}", MAX_DOCUMENTS - 1): { "$exists": false } };
+    let document = doc! { "type": &kind, "url": &url, "key": &key };
+    let updated = db.collection::<Document>("restaurant_applications")
+        .find_one_and_update(filter, doc! { "$push": { "documents": &document } })
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await
+        .map_err(|e| error_response(StatusCode::INTERNAL_SERVER_ERROR, "server.error", &e.to_string()))?;
+    let Some(application) = updated else {
+        tokio::task::spawn_blocking(move || storage_backend().delete(&key));
+        return Err(error_response(StatusCode::CONFLICT, "application.conflict", &format!("application is not pending or already has {} documents", MAX_DOCUMENTS)));
+    };
+    Ok(data_response_with_status(StatusCode::CREATED, Bson::Document(doc! {
+        "type": kind,
+        "url": url,
+        "documents": application.get("documents").cloned().unwrap_or_else(|| Bson::Array(Vec::new()))
+    })))
+}
+
+pub fn uploads_router(db: Database) -> Router{
+    Router::new()
+        .route("/menu/{id}/image", post(upload_menu_image))
+        .route("/profile/image", post(upload_shop_image))
+        // leave room for multipart framing on top of the image itself
+        .layer(DefaultBodyLimit::max(max_upload_bytes() + 64 * 1024))
+        .with_state(db)
+}
+
+pub fn application_uploads_router(db: Database) -> Router{
+    Router::new()
+        .route("/{id}/documents", post(upload_application_document))
+        .layer(DefaultBodyLimit::max(max_upload_bytes() + 64 * 1024))
+        .with_state(db)
+}